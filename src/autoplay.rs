@@ -0,0 +1,118 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use serenity::model::id::GuildId;
+use smallvec::SmallVec;
+use songbird::{
+    Event, EventContext, EventHandler, Songbird,
+    input::Input,
+    tracks::{PlayMode, Track},
+};
+use tracing::info;
+#[cfg(feature = "spotify")]
+use tracing::warn;
+
+use crate::{Data, track_info};
+
+/// How many tracks are added at once when the queue runs dry
+const TRACKS_COUNT: usize = 3;
+
+/// Songbird event handler that enqueues tracks similar to the last one once the queue is over
+pub(crate) struct QueueEndHandler {
+    data: Arc<Data>,
+    songbird: Arc<Songbird>,
+    guild_id: GuildId,
+}
+
+impl QueueEndHandler {
+    pub(crate) fn new(data: Arc<Data>, songbird: Arc<Songbird>, guild_id: GuildId) -> Self {
+        Self {
+            data,
+            songbird,
+            guild_id,
+        }
+    }
+}
+
+#[async_trait]
+impl EventHandler for QueueEndHandler {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        let EventContext::Track([(state, handle)]) = ctx else {
+            return None;
+        };
+        // Tracks that were skipped or stopped via `/stop` should not trigger autoplay
+        let settings = self.data.settings.get(self.guild_id);
+        if !matches!(state.playing, PlayMode::End) || !settings.autoplay {
+            return None;
+        }
+
+        let vc = self.songbird.get(self.guild_id)?;
+        // Track end handler of the queue is invoked before this one, so the queue is already updated
+        if !vc.lock().await.queue().is_empty() {
+            return None;
+        }
+
+        // Resolving tracks might take a while, so don't block other songbird events
+        let seed = handle.data::<track_info::TrackInfo>();
+        let data = self.data.clone();
+        let guild_id = self.guild_id;
+        tokio::spawn(async move {
+            let mut tracks = recommend(&data, guild_id, seed.metadata()).await.into_vec();
+            info!(
+                "Autoplay found {} tracks similar to '{}' in {guild_id}",
+                tracks.len(),
+                seed.metadata().title,
+            );
+            // Nobody requested these tracks, but they still shouldn't bypass the blocklist
+            for blocked in data.blocklists.apply(guild_id, &mut tracks) {
+                info!("Dropped autoplay tracks in {guild_id}. {blocked}");
+            }
+
+            let mut vc = vc.lock().await;
+            for (metadata, input) in tracks {
+                let track = Track::new_with_data(
+                    input,
+                    Arc::new(track_info::TrackInfo::new(
+                        metadata,
+                        track_info::Requester::Autoplay,
+                    )),
+                )
+                .volume(settings.track_volume());
+                let _ = vc.enqueue(track).await;
+            }
+        });
+        None
+    }
+}
+
+/// Resolves tracks similar to the seed one, using the same source as the seed
+async fn recommend(
+    data: &Data,
+    _guild_id: GuildId,
+    seed: &track_info::Metadata,
+) -> SmallVec<[(track_info::Metadata, Input); 1]> {
+    #[cfg(feature = "spotify")]
+    if let Some(result) = data
+        .spotify_resolver
+        .recommend(_guild_id, &seed.source_url, TRACKS_COUNT)
+        .await
+    {
+        return match result {
+            Ok(tracks) => tracks
+                .into_iter()
+                .map(|track| (track.metadata().clone(), track.into()))
+                .collect(),
+            Err(err) => {
+                warn!("Failed to fetch Spotify radio for '{}': {err}", seed.title);
+                SmallVec::new()
+            }
+        };
+    }
+
+    data.yt_dlp_resolver
+        .related(&seed.source_url, TRACKS_COUNT)
+        .await
+        .into_iter()
+        .map(|yt_dlp| (yt_dlp.metadata().clone(), yt_dlp.into()))
+        .collect()
+}
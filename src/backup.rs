@@ -0,0 +1,495 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, bail, ensure};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serenity::model::id::{ChannelId, GuildId, UserId};
+use tracing::{info, warn};
+
+use crate::{blocklist, channels, guild_settings, playlist, yt_dlp};
+
+/// Version of the backup format. Should be bumped on incompatible changes of [`Backup`]
+pub(crate) const BACKUP_VERSION: u32 = 1;
+
+/// Usage of the bot data management subcommands
+pub(crate) const USAGE: &str = "Usage:
+    prospero export <file> [--with-credentials]   Export bot data to the JSON file
+    prospero import <file>                        Import bot data from the JSON file";
+
+/// Bot data that is worth moving between hosts. Play history and saved queues are not included
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Backup {
+    pub(crate) version: u32,
+    #[serde(default)]
+    pub(crate) guild_settings: Vec<GuildSettings>,
+    pub(crate) spotify_settings: Vec<SpotifySettings>,
+    pub(crate) playlists: Vec<Playlist>,
+    pub(crate) query_cache: Vec<CachedQuery>,
+    #[serde(default)]
+    pub(crate) blocklist: Vec<BlocklistRule>,
+    #[serde(default)]
+    pub(crate) allowed_channels: Vec<AllowedChannel>,
+    /// Linked Spotify accounts. `None` if the backup was made without credentials
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) credentials: Option<Vec<Credentials>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct GuildSettings {
+    pub(crate) guild_id: GuildId,
+    #[serde(flatten)]
+    pub(crate) settings: guild_settings::GuildSettings,
+}
+
+/// Spotify playback settings of the guild, as they are stored regardless of the `spotify` feature
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct SpotifySettings {
+    pub(crate) guild_id: GuildId,
+    pub(crate) bitrate_kbps: u32,
+    pub(crate) normalisation: bool,
+    pub(crate) normalisation_pregain_db: f64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Playlist {
+    pub(crate) guild_id: GuildId,
+    pub(crate) name: String,
+    pub(crate) tracks: Vec<playlist::PlaylistTrack>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct CachedQuery {
+    pub(crate) query: String,
+    pub(crate) webpage_url: String,
+    /// Unix timestamp in seconds when the query was cached
+    pub(crate) created_at: u64,
+    pub(crate) hits: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct BlocklistRule {
+    pub(crate) guild_id: GuildId,
+    #[serde(flatten)]
+    pub(crate) rule: blocklist::Rule,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct AllowedChannel {
+    pub(crate) guild_id: GuildId,
+    pub(crate) kind: channels::ChannelKind,
+    pub(crate) channel_id: ChannelId,
+}
+
+/// Owner of the linked Spotify account
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum CredentialsOwner {
+    Guild(GuildId),
+    User(UserId),
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Credentials {
+    pub(crate) owner: CredentialsOwner,
+    pub(crate) username: String,
+    pub(crate) password: String,
+}
+
+/// An interface for dumping and restoring the bot data at once
+#[async_trait]
+pub(crate) trait BackupStorage: Send + Sync {
+    /// Dumps the bot data, including Spotify credentials only if asked to
+    async fn export(&self, include_credentials: bool) -> Result<Backup, anyhow::Error>;
+    /// Loads the backup atomically, replacing existing entries with the same keys and keeping the rest
+    async fn import(&self, backup: Backup) -> Result<(), anyhow::Error>;
+}
+
+impl Backup {
+    /// Checks that the backup is of the supported version and can be imported as is
+    pub(crate) fn validate(&self) -> Result<(), anyhow::Error> {
+        ensure!(
+            self.version == BACKUP_VERSION,
+            "Unsupported backup version {}, expected {BACKUP_VERSION}",
+            self.version
+        );
+
+        let mut guilds = HashSet::new();
+        for settings in &self.guild_settings {
+            let guild_id = settings.guild_id;
+            ensure!(guilds.insert(guild_id), "Duplicate settings of {guild_id}");
+            ensure!(
+                settings.settings.is_valid(),
+                "Invalid settings of {guild_id}: {:?}",
+                settings.settings
+            );
+        }
+
+        let mut guilds = HashSet::new();
+        for settings in &self.spotify_settings {
+            let guild_id = settings.guild_id;
+            ensure!(
+                guilds.insert(guild_id),
+                "Duplicate Spotify settings of {guild_id}"
+            );
+            ensure!(
+                [96, 160, 320].contains(&settings.bitrate_kbps),
+                "Invalid Spotify bitrate {} in {guild_id}",
+                settings.bitrate_kbps
+            );
+            ensure!(
+                settings.normalisation_pregain_db.is_finite(),
+                "Invalid normalisation pregain in {guild_id}"
+            );
+        }
+
+        let mut playlists = HashSet::new();
+        for playlist in &self.playlists {
+            let (guild_id, name) = (playlist.guild_id, &playlist.name);
+            ensure!(
+                playlist::normalize_name(name) == Some(name.as_str()),
+                "Invalid playlist name '{name}' in {guild_id}"
+            );
+            ensure!(
+                playlists.insert((guild_id, name)),
+                "Duplicate playlist '{name}' in {guild_id}"
+            );
+            if let Some(track) = playlist.tracks.iter().find(|t| t.source_url.is_empty()) {
+                bail!(
+                    "Track '{}' of playlist '{name}' in {guild_id} has no source URL",
+                    track.title
+                );
+            }
+        }
+
+        let mut queries = HashSet::new();
+        for cached in &self.query_cache {
+            let query = &cached.query;
+            ensure!(
+                !yt_dlp::normalize_query(query).is_empty(),
+                "Empty cached query"
+            );
+            ensure!(queries.insert(query), "Duplicate cached query '{query}'");
+            ensure!(
+                !cached.webpage_url.is_empty(),
+                "Cached query '{query}' has no webpage URL"
+            );
+        }
+
+        let mut rules = HashSet::new();
+        for blocked in &self.blocklist {
+            let (guild_id, rule) = (blocked.guild_id, &blocked.rule);
+            ensure!(
+                blocklist::Rule::new(rule.kind, &rule.value).as_ref() == Ok(rule),
+                "Invalid blocklist rule {rule:?} in {guild_id}"
+            );
+            ensure!(
+                rules.insert((guild_id, rule)),
+                "Duplicate blocklist rule {rule:?} in {guild_id}"
+            );
+        }
+
+        let mut allowed_channels = HashSet::new();
+        for allowed in &self.allowed_channels {
+            let (guild_id, channel_id) = (allowed.guild_id, allowed.channel_id);
+            ensure!(
+                allowed_channels.insert((guild_id, channel_id)),
+                "Duplicate allowed channel {channel_id} in {guild_id}"
+            );
+        }
+
+        let mut owners = HashSet::new();
+        for credentials in self.credentials.iter().flatten() {
+            let owner = credentials.owner;
+            ensure!(owners.insert(owner), "Duplicate credentials of {owner:?}");
+            ensure!(
+                !credentials.username.is_empty(),
+                "Empty Spotify username of {owner:?}"
+            );
+        }
+        Ok(())
+    }
+}
+
+/// `export` or `import` subcommand, see [`USAGE`]
+#[derive(Debug, PartialEq)]
+pub(crate) enum Command {
+    Export {
+        path: PathBuf,
+        include_credentials: bool,
+    },
+    Import {
+        path: PathBuf,
+    },
+}
+
+impl Command {
+    /// Parses the command line arguments that follow the binary name
+    pub(crate) fn parse(args: &[String]) -> Result<Self, anyhow::Error> {
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        Ok(match args.as_slice() {
+            ["export", path] => Self::Export {
+                path: path.into(),
+                include_credentials: false,
+            },
+            ["export", path, "--with-credentials"] => Self::Export {
+                path: path.into(),
+                include_credentials: true,
+            },
+            ["import", path] => Self::Import { path: path.into() },
+            _ => bail!("{USAGE}"),
+        })
+    }
+}
+
+/// Runs `export` or `import` subcommand
+pub(crate) async fn run(
+    storage: &dyn BackupStorage,
+    command: Command,
+) -> Result<(), anyhow::Error> {
+    match command {
+        Command::Export {
+            path,
+            include_credentials,
+        } => export(storage, &path, include_credentials).await,
+        Command::Import { path } => import(storage, &path).await,
+    }
+}
+
+async fn export(
+    storage: &dyn BackupStorage,
+    path: &Path,
+    include_credentials: bool,
+) -> Result<(), anyhow::Error> {
+    let backup = storage.export(include_credentials).await?;
+    let json = serde_json::to_vec_pretty(&backup)?;
+    std::fs::write(path, json).with_context(|| format!("Failed to write {}", path.display()))?;
+
+    info!(
+        "Exported settings of {} guilds, {} playlists, {} cached queries, {} blocklist rules, \
+        {} allowed channels and Spotify settings of {} guilds to {}",
+        backup.guild_settings.len(),
+        backup.playlists.len(),
+        backup.query_cache.len(),
+        backup.blocklist.len(),
+        backup.allowed_channels.len(),
+        backup.spotify_settings.len(),
+        path.display()
+    );
+    if let Some(credentials) = &backup.credentials {
+        warn!(
+            "{} contains {} Spotify passwords in plain text, keep it safe",
+            path.display(),
+            credentials.len()
+        );
+    }
+    Ok(())
+}
+
+async fn import(storage: &dyn BackupStorage, path: &Path) -> Result<(), anyhow::Error> {
+    let json = std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let backup: Backup = serde_json::from_slice(&json)
+        .with_context(|| format!("{} is not a valid backup", path.display()))?;
+    backup.validate()?;
+
+    let summary = format!(
+        "settings of {} guilds, {} playlists, {} cached queries, {} blocklist rules, \
+        {} allowed channels, Spotify settings of {} guilds and {} Spotify accounts",
+        backup.guild_settings.len(),
+        backup.playlists.len(),
+        backup.query_cache.len(),
+        backup.blocklist.len(),
+        backup.allowed_channels.len(),
+        backup.spotify_settings.len(),
+        backup.credentials.as_ref().map_or(0, Vec::len)
+    );
+    storage.import(backup).await?;
+    info!("Imported {summary} from {}", path.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn backup() -> Backup {
+        Backup {
+            version: BACKUP_VERSION,
+            guild_settings: vec![GuildSettings {
+                guild_id: GuildId::new(101),
+                settings: guild_settings::GuildSettings {
+                    autoplay: true,
+                    ..Default::default()
+                },
+            }],
+            spotify_settings: vec![SpotifySettings {
+                guild_id: GuildId::new(101),
+                bitrate_kbps: 320,
+                normalisation: true,
+                normalisation_pregain_db: -2.0,
+            }],
+            playlists: vec![Playlist {
+                guild_id: GuildId::new(101),
+                name: "party".into(),
+                tracks: vec![playlist::PlaylistTrack {
+                    source_url: "https://example.com/1".into(),
+                    title: "Track 1".into(),
+                }],
+            }],
+            query_cache: vec![CachedQuery {
+                query: "rick astley".into(),
+                webpage_url: "https://www.youtube.com/watch?v=dQw4w9WgXcQ".into(),
+                created_at: 1_700_000_000,
+                hits: 3,
+            }],
+            blocklist: vec![BlocklistRule {
+                guild_id: GuildId::new(101),
+                rule: blocklist::Rule::new(blocklist::RuleKind::Title, "earrape").unwrap(),
+            }],
+            allowed_channels: vec![AllowedChannel {
+                guild_id: GuildId::new(101),
+                kind: channels::ChannelKind::Text,
+                channel_id: ChannelId::new(404),
+            }],
+            credentials: Some(vec![Credentials {
+                owner: CredentialsOwner::User(UserId::new(303)),
+                username: "my username".into(),
+                password: "my password".into(),
+            }]),
+        }
+    }
+
+    #[test]
+    fn parse_command() {
+        let parse = |args: &[&str]| {
+            let args: Vec<String> = args.iter().map(ToString::to_string).collect();
+            Command::parse(&args).ok()
+        };
+        assert_eq!(
+            parse(&["export", "backup.json"]),
+            Some(Command::Export {
+                path: "backup.json".into(),
+                include_credentials: false,
+            })
+        );
+        assert_eq!(
+            parse(&["export", "backup.json", "--with-credentials"]),
+            Some(Command::Export {
+                path: "backup.json".into(),
+                include_credentials: true,
+            })
+        );
+        assert_eq!(
+            parse(&["import", "backup.json"]),
+            Some(Command::Import {
+                path: "backup.json".into(),
+            })
+        );
+        assert_eq!(parse(&["exprot", "backup.json"]), None);
+        assert_eq!(parse(&["import"]), None);
+    }
+
+    #[test]
+    fn json_roundtrip() {
+        let backup = backup();
+        let json = serde_json::to_string(&backup).unwrap();
+        assert_eq!(serde_json::from_str::<Backup>(&json).unwrap(), backup);
+
+        // Credentials are omitted if not exported
+        let backup = Backup {
+            credentials: None,
+            ..backup
+        };
+        let json = serde_json::to_string(&backup).unwrap();
+        assert!(!json.contains("credentials"));
+        assert_eq!(serde_json::from_str::<Backup>(&json).unwrap(), backup);
+
+        // Settings added later default for older backups
+        let json = json.replace(",\"autoplay\":true", "");
+        assert_eq!(
+            serde_json::from_str::<Backup>(&json)
+                .unwrap()
+                .guild_settings[0]
+                .settings,
+            guild_settings::GuildSettings::default()
+        );
+
+        // Zero ids are invalid in Discord
+        let json = json.replace("\"101\"", "\"0\"");
+        assert!(serde_json::from_str::<Backup>(&json).is_err());
+    }
+
+    #[test]
+    fn validate_test() {
+        assert!(backup().validate().is_ok());
+        assert!(Backup::default().validate().is_err());
+
+        let mut newer = backup();
+        newer.version = BACKUP_VERSION + 1;
+        assert!(newer.validate().is_err());
+
+        let mut settings = backup();
+        settings.guild_settings[0].settings.volume = 101;
+        assert!(settings.validate().is_err());
+
+        let mut bitrate = backup();
+        bitrate.spotify_settings[0].bitrate_kbps = 128;
+        assert!(bitrate.validate().is_err());
+
+        let mut pregain = backup();
+        pregain.spotify_settings[0].normalisation_pregain_db = f64::NAN;
+        assert!(pregain.validate().is_err());
+
+        let mut name = backup();
+        name.playlists[0].name = " party ".into();
+        assert!(name.validate().is_err());
+
+        // The same name is fine in another guild, but not in the same one
+        let mut playlists = backup();
+        let mut another = playlists.playlists[0].clone();
+        another.guild_id = GuildId::new(202);
+        playlists.playlists.push(another.clone());
+        assert!(playlists.validate().is_ok());
+        another.guild_id = GuildId::new(101);
+        playlists.playlists.push(another);
+        assert!(playlists.validate().is_err());
+
+        let mut track = backup();
+        track.playlists[0].tracks[0].source_url.clear();
+        assert!(track.validate().is_err());
+
+        let mut queries = backup();
+        queries.query_cache.push(queries.query_cache[0].clone());
+        assert!(queries.validate().is_err());
+
+        let mut rule = backup();
+        rule.blocklist[0].rule.value = "(unclosed".into();
+        assert!(rule.validate().is_err());
+
+        // Rules are normalized on creation, so others could never be matched
+        let mut domain = backup();
+        domain.blocklist[0].rule = blocklist::Rule {
+            kind: blocklist::RuleKind::Domain,
+            value: "WWW.example.com".into(),
+        };
+        assert!(domain.validate().is_err());
+
+        let mut rules = backup();
+        rules.blocklist.push(rules.blocklist[0].clone());
+        assert!(rules.validate().is_err());
+
+        // A channel is either a text or a voice one
+        let mut allowed = backup();
+        let mut another = allowed.allowed_channels[0].clone();
+        another.kind = channels::ChannelKind::Voice;
+        allowed.allowed_channels.push(another);
+        assert!(allowed.validate().is_err());
+
+        let mut credentials = backup();
+        if let Some(credentials) = &mut credentials.credentials {
+            credentials.push(credentials[0].clone());
+        }
+        assert!(credentials.validate().is_err());
+    }
+}
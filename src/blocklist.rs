@@ -0,0 +1,476 @@
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+use std::sync::{Arc, RwLock};
+
+use async_trait::async_trait;
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use serenity::model::id::{GuildId, UserId};
+
+use crate::track_info;
+
+/// Longest rule value, so the blocklist fits into a message
+const MAX_VALUE_CHARS: usize = 200;
+/// Largest compiled title regex in bytes, so a rule can't make matching slow
+const TITLE_REGEX_SIZE_LIMIT: usize = 1 << 16;
+
+/// What a blocklist rule matches
+#[derive(
+    Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize, poise::ChoiceParameter,
+)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum RuleKind {
+    /// Tracks hosted on the domain or its subdomains
+    #[name = "Domain"]
+    Domain,
+    /// The track with exactly this URL
+    #[name = "URL"]
+    Url,
+    /// Tracks with titles matching the case-insensitive regex
+    #[name = "Title regex"]
+    Title,
+    /// Tracks requested by the user
+    #[name = "User"]
+    User,
+}
+
+impl RuleKind {
+    /// Returns the name the kind is stored under
+    pub(crate) const fn key(self) -> &'static str {
+        match self {
+            Self::Domain => "domain",
+            Self::Url => "url",
+            Self::Title => "title",
+            Self::User => "user",
+        }
+    }
+
+    pub(crate) fn from_key(key: &str) -> Option<Self> {
+        [Self::Domain, Self::Url, Self::Title, Self::User]
+            .into_iter()
+            .find(|kind| kind.key() == key)
+    }
+}
+
+/// A blocklist rule of the guild
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub(crate) struct Rule {
+    pub(crate) kind: RuleKind,
+    /// Normalized value, see [`Rule::new`]
+    pub(crate) value: String,
+}
+
+impl Rule {
+    /// Creates the rule, normalizing the value so the same rule is stored once.
+    /// Returns the reason if the value is invalid for the kind
+    pub(crate) fn new(kind: RuleKind, value: &str) -> Result<Self, String> {
+        let value = value.trim();
+        if value.chars().count() > MAX_VALUE_CHARS {
+            return Err(format!(
+                "Blocklist rules are limited to {MAX_VALUE_CHARS} characters"
+            ));
+        }
+        let value = match kind {
+            RuleKind::Domain => {
+                let domain = value.to_lowercase();
+                let domain = domain
+                    .trim_start_matches("https://")
+                    .trim_start_matches("http://")
+                    .trim_start_matches("www.")
+                    .trim_end_matches('/');
+                if !domain.contains('.') || domain.contains(|c: char| c == '/' || c.is_whitespace())
+                {
+                    return Err(format!("'{value}' is not a domain like example.com"));
+                }
+                domain.to_owned()
+            }
+            RuleKind::Url => {
+                if !value.starts_with("https://") && !value.starts_with("http://") {
+                    return Err(format!("'{value}' is not a URL"));
+                }
+                value.to_owned()
+            }
+            RuleKind::Title => {
+                if value.is_empty() {
+                    return Err("Title regex is empty".into());
+                }
+                title_regex(value).map_err(|err| format!("Invalid regex '{value}': {err}"))?;
+                value.to_owned()
+            }
+            RuleKind::User => {
+                // Accept mentions as well, as they are easier to type
+                let id = value
+                    .trim_start_matches("<@")
+                    .trim_start_matches('!')
+                    .trim_end_matches('>');
+                match id.parse::<u64>() {
+                    Ok(id) if id != 0 => id.to_string(),
+                    _ => return Err(format!("'{value}' is not a user mention or id")),
+                }
+            }
+        };
+        Ok(Self { kind, value })
+    }
+}
+
+impl Display for Rule {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let value = &self.value;
+        match self.kind {
+            RuleKind::Domain => write!(f, "domain {value}"),
+            RuleKind::Url => write!(f, "URL <{value}>"),
+            RuleKind::Title => write!(f, "title matching `{value}`"),
+            RuleKind::User => write!(f, "user <@{value}>"),
+        }
+    }
+}
+
+fn title_regex(pattern: &str) -> Result<Regex, regex::Error> {
+    RegexBuilder::new(pattern)
+        .case_insensitive(true)
+        .size_limit(TITLE_REGEX_SIZE_LIMIT)
+        .build()
+}
+
+/// Requested tracks dropped by a rule of the guild's blocklist
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Blocked {
+    pub(crate) rule: Rule,
+    pub(crate) count: usize,
+}
+
+impl Display for Blocked {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.count {
+            1 => write!(f, "The track is blocked by {}", self.rule),
+            count => write!(f, "{count} tracks are blocked by {}", self.rule),
+        }
+    }
+}
+
+/// An interface for storing and retrieving blocklists of guilds
+#[async_trait]
+pub(crate) trait BlocklistStorage: Send + Sync {
+    /// Adds the rule to the guild's blocklist. Returns `false` if it is already there
+    async fn add(&self, guild_id: GuildId, rule: &Rule) -> Result<bool, anyhow::Error>;
+    /// Removes the rule from the guild's blocklist. Returns `false` if there was no such rule
+    async fn remove(&self, guild_id: GuildId, rule: &Rule) -> Result<bool, anyhow::Error>;
+    /// Loads blocklist rules of all guilds
+    async fn load_all(&self) -> Vec<(GuildId, Rule)>;
+}
+
+/// A rule with its title regex compiled once
+struct Entry {
+    rule: Rule,
+    title: Option<Regex>,
+}
+
+impl Entry {
+    fn new(rule: Rule) -> Self {
+        let title = match rule.kind {
+            RuleKind::Title => title_regex(&rule.value).ok(),
+            _ => None,
+        };
+        Self { rule, title }
+    }
+
+    fn blocks(&self, metadata: &track_info::Metadata) -> bool {
+        let value = self.rule.value.as_str();
+        match self.rule.kind {
+            RuleKind::Domain => reqwest::Url::parse(&metadata.source_url)
+                .ok()
+                .and_then(|url| url.host_str().map(str::to_lowercase))
+                .is_some_and(|host| {
+                    host == value
+                        || host
+                            .strip_suffix(value)
+                            .is_some_and(|subdomain| subdomain.ends_with('.'))
+                }),
+            RuleKind::Url => *metadata.source_url == *value,
+            RuleKind::Title => self
+                .title
+                .as_ref()
+                .is_some_and(|title| title.is_match(&metadata.title)),
+            RuleKind::User => false,
+        }
+    }
+}
+
+/// Blocklists of all guilds, kept in memory as they are checked on every request
+pub(crate) struct Blocklists {
+    storage: Arc<dyn BlocklistStorage>,
+    guilds: RwLock<HashMap<GuildId, Vec<Entry>>>,
+}
+
+impl Blocklists {
+    /// Loads blocklists of all guilds from the storage
+    pub(crate) async fn load(storage: Arc<dyn BlocklistStorage>) -> Self {
+        let mut guilds: HashMap<GuildId, Vec<Entry>> = HashMap::new();
+        for (guild_id, rule) in storage.load_all().await {
+            guilds.entry(guild_id).or_default().push(Entry::new(rule));
+        }
+        Self {
+            storage,
+            guilds: RwLock::new(guilds),
+        }
+    }
+
+    /// Returns rules of the guild's blocklist
+    pub(crate) fn list(&self, guild_id: GuildId) -> Vec<Rule> {
+        self.guilds
+            .read()
+            .unwrap()
+            .get(&guild_id)
+            .map(|entries| entries.iter().map(|entry| entry.rule.clone()).collect())
+            .unwrap_or_default()
+    }
+
+    /// Returns whether the user is banned from requesting tracks in the guild
+    pub(crate) fn is_user_blocked(&self, guild_id: GuildId, user_id: UserId) -> bool {
+        let user_id = user_id.to_string();
+        self.guilds
+            .read()
+            .unwrap()
+            .get(&guild_id)
+            .is_some_and(|entries| {
+                entries
+                    .iter()
+                    .any(|entry| entry.rule.kind == RuleKind::User && entry.rule.value == user_id)
+            })
+    }
+
+    /// Returns the rule that blocks the track in the guild if any
+    pub(crate) fn blocking_rule(
+        &self,
+        guild_id: GuildId,
+        metadata: &track_info::Metadata,
+    ) -> Option<Rule> {
+        self.guilds
+            .read()
+            .unwrap()
+            .get(&guild_id)?
+            .iter()
+            .find(|entry| entry.blocks(metadata))
+            .map(|entry| entry.rule.clone())
+    }
+
+    /// Drops requested tracks blocked in the guild, keeping the order of the rest.
+    /// Returns the rules that blocked them
+    pub(crate) fn apply<T>(
+        &self,
+        guild_id: GuildId,
+        items: &mut Vec<(track_info::Metadata, T)>,
+    ) -> Vec<Blocked> {
+        let mut blocked: Vec<Blocked> = vec![];
+        items.retain(|(metadata, _)| {
+            let Some(rule) = self.blocking_rule(guild_id, metadata) else {
+                return true;
+            };
+            match blocked.iter_mut().find(|blocked| blocked.rule == rule) {
+                Some(blocked) => blocked.count += 1,
+                None => blocked.push(Blocked { rule, count: 1 }),
+            }
+            false
+        });
+        blocked
+    }
+
+    /// Adds the rule to the guild's blocklist. Returns `false` if it is already there
+    pub(crate) async fn add(&self, guild_id: GuildId, rule: Rule) -> Result<bool, anyhow::Error> {
+        if !self.storage.add(guild_id, &rule).await? {
+            return Ok(false);
+        }
+        let mut guilds = self.guilds.write().unwrap();
+        guilds.entry(guild_id).or_default().push(Entry::new(rule));
+        Ok(true)
+    }
+
+    /// Removes the rule from the guild's blocklist. Returns `false` if there was no such rule
+    pub(crate) async fn remove(
+        &self,
+        guild_id: GuildId,
+        rule: &Rule,
+    ) -> Result<bool, anyhow::Error> {
+        if !self.storage.remove(guild_id, rule).await? {
+            return Ok(false);
+        }
+        if let Some(entries) = self.guilds.write().unwrap().get_mut(&guild_id) {
+            entries.retain(|entry| entry.rule != *rule);
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct MemoryStorage {
+        rules: Mutex<Vec<(GuildId, Rule)>>,
+    }
+
+    #[async_trait]
+    impl BlocklistStorage for MemoryStorage {
+        async fn add(&self, guild_id: GuildId, rule: &Rule) -> Result<bool, anyhow::Error> {
+            let mut rules = self.rules.lock().unwrap();
+            let entry = (guild_id, rule.clone());
+            if rules.contains(&entry) {
+                return Ok(false);
+            }
+            rules.push(entry);
+            Ok(true)
+        }
+
+        async fn remove(&self, guild_id: GuildId, rule: &Rule) -> Result<bool, anyhow::Error> {
+            let mut rules = self.rules.lock().unwrap();
+            let count = rules.len();
+            rules.retain(|entry| *entry != (guild_id, rule.clone()));
+            Ok(rules.len() < count)
+        }
+
+        async fn load_all(&self) -> Vec<(GuildId, Rule)> {
+            self.rules.lock().unwrap().clone()
+        }
+    }
+
+    fn metadata(source_url: &str, title: &str) -> track_info::Metadata {
+        track_info::Metadata {
+            title: title.into(),
+            source_url: source_url.into(),
+            thumbnail_url: None,
+            duration_sec: None,
+        }
+    }
+
+    #[test]
+    fn rule_new() {
+        let value = |kind, value| Rule::new(kind, value).map(|rule| rule.value);
+
+        assert_eq!(
+            value(RuleKind::Domain, " https://WWW.Example.com/"),
+            Ok("example.com".into())
+        );
+        assert!(value(RuleKind::Domain, "localhost").is_err());
+        assert!(value(RuleKind::Domain, "example.com/video").is_err());
+
+        assert_eq!(
+            value(RuleKind::Url, "https://example.com/1 "),
+            Ok("https://example.com/1".into())
+        );
+        assert!(value(RuleKind::Url, "example.com/1").is_err());
+
+        assert_eq!(value(RuleKind::Title, "earrape"), Ok("earrape".into()));
+        assert!(value(RuleKind::Title, "(unclosed").is_err());
+        assert!(value(RuleKind::Title, " ").is_err());
+
+        assert_eq!(value(RuleKind::User, "<@!101>"), Ok("101".into()));
+        assert_eq!(value(RuleKind::User, "101"), Ok("101".into()));
+        assert!(value(RuleKind::User, "0").is_err());
+        assert!(value(RuleKind::User, "alice").is_err());
+
+        assert!(value(RuleKind::Title, &"a".repeat(MAX_VALUE_CHARS + 1)).is_err());
+    }
+
+    #[test]
+    fn rule_kind_keys() {
+        for kind in [
+            RuleKind::Domain,
+            RuleKind::Url,
+            RuleKind::Title,
+            RuleKind::User,
+        ] {
+            assert_eq!(RuleKind::from_key(kind.key()), Some(kind));
+        }
+        assert_eq!(RuleKind::from_key("unknown"), None);
+    }
+
+    #[tokio::test]
+    async fn blocklists() {
+        let storage = Arc::new(MemoryStorage::default());
+        let blocklists = Blocklists::load(storage.clone()).await;
+        let guild_id = GuildId::new(101);
+        let rule = |kind, value| Rule::new(kind, value).unwrap();
+
+        let domain = rule(RuleKind::Domain, "example.com");
+        assert!(blocklists.add(guild_id, domain.clone()).await.unwrap());
+        assert!(!blocklists.add(guild_id, domain.clone()).await.unwrap());
+        assert_eq!(
+            blocklists.blocking_rule(guild_id, &metadata("https://music.example.com/1", "Song")),
+            Some(domain.clone())
+        );
+        assert_eq!(
+            blocklists.blocking_rule(guild_id, &metadata("https://notexample.com/1", "Song")),
+            None
+        );
+        // Blocklists are per guild
+        assert_eq!(
+            blocklists.blocking_rule(
+                GuildId::new(202),
+                &metadata("https://example.com/1", "Song")
+            ),
+            None
+        );
+
+        let url = rule(RuleKind::Url, "https://youtube.com/watch?v=1");
+        let title = rule(RuleKind::Title, r"ear\s*rape");
+        let user = rule(RuleKind::User, "303");
+        for rule in [&url, &title, &user] {
+            assert!(blocklists.add(guild_id, rule.clone()).await.unwrap());
+        }
+        assert_eq!(
+            blocklists.blocking_rule(guild_id, &metadata("https://youtube.com/watch?v=1", "Song")),
+            Some(url)
+        );
+        assert_eq!(
+            blocklists.blocking_rule(guild_id, &metadata("https://youtube.com/watch?v=2", "Song")),
+            None
+        );
+        assert_eq!(
+            blocklists.blocking_rule(
+                guild_id,
+                &metadata("https://youtube.com/watch?v=3", "Song (EAR RAPE)")
+            ),
+            Some(title.clone())
+        );
+        assert!(blocklists.is_user_blocked(guild_id, UserId::new(303)));
+        assert!(!blocklists.is_user_blocked(guild_id, UserId::new(404)));
+
+        let mut items = vec![
+            (metadata("https://example.com/1", "Song"), 0),
+            (metadata("https://youtube.com/watch?v=2", "Song"), 1),
+            (metadata("https://example.com/3", "Song"), 2),
+            (metadata("https://youtube.com/watch?v=4", "Earrape"), 3),
+        ];
+        assert_eq!(
+            blocklists.apply(guild_id, &mut items),
+            vec![
+                Blocked {
+                    rule: domain.clone(),
+                    count: 2
+                },
+                Blocked {
+                    rule: title.clone(),
+                    count: 1
+                }
+            ]
+        );
+        assert_eq!(items.iter().map(|(_, i)| *i).collect::<Vec<_>>(), vec![1]);
+
+        assert!(blocklists.remove(guild_id, &title).await.unwrap());
+        assert!(!blocklists.remove(guild_id, &title).await.unwrap());
+        assert_eq!(
+            blocklists.blocking_rule(
+                guild_id,
+                &metadata("https://youtube.com/watch?v=3", "Song (EAR RAPE)")
+            ),
+            None
+        );
+
+        // Saved rules are loaded on start
+        let blocklists = Blocklists::load(storage).await;
+        assert_eq!(blocklists.list(guild_id).len(), 3);
+        assert!(blocklists.is_user_blocked(guild_id, UserId::new(303)));
+    }
+}
@@ -0,0 +1,280 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serenity::model::{
+    channel::ChannelType,
+    id::{ChannelId, GuildId},
+};
+
+/// What the allowed channel is used for
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum ChannelKind {
+    /// Music commands may be used in the text channel
+    Text,
+    /// The bot may join the voice channel
+    Voice,
+}
+
+impl ChannelKind {
+    /// Returns how the channel of the type can be allowed, if it can be at all
+    pub(crate) fn of(channel_type: ChannelType) -> Option<Self> {
+        match channel_type {
+            ChannelType::Text | ChannelType::News => Some(Self::Text),
+            ChannelType::Voice | ChannelType::Stage => Some(Self::Voice),
+            _ => None,
+        }
+    }
+
+    /// Returns the name the kind is stored under
+    pub(crate) const fn key(self) -> &'static str {
+        match self {
+            Self::Text => "text",
+            Self::Voice => "voice",
+        }
+    }
+
+    pub(crate) fn from_key(key: &str) -> Option<Self> {
+        [Self::Text, Self::Voice]
+            .into_iter()
+            .find(|kind| kind.key() == key)
+    }
+}
+
+/// Formats the channels as a list of mentions
+pub(crate) fn mentions(channels: &[ChannelId]) -> String {
+    channels
+        .iter()
+        .map(|channel_id| format!("<#{channel_id}>"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// An interface for storing and retrieving channels the bot is restricted to
+#[async_trait]
+pub(crate) trait AllowedChannelsStorage: Send + Sync {
+    /// Allows the channel in the guild. Returns `false` if it is already allowed
+    async fn allow(
+        &self,
+        guild_id: GuildId,
+        kind: ChannelKind,
+        channel_id: ChannelId,
+    ) -> Result<bool, anyhow::Error>;
+    /// Removes the channel from the allowed ones. Returns `false` if it wasn't allowed
+    async fn disallow(
+        &self,
+        guild_id: GuildId,
+        channel_id: ChannelId,
+    ) -> Result<bool, anyhow::Error>;
+    /// Loads allowed channels of all guilds
+    async fn load_all(&self) -> Vec<(GuildId, ChannelKind, ChannelId)>;
+}
+
+/// Channels the bot is restricted to in all guilds, kept in memory as they are checked on every
+/// command. A guild without allowed channels of a kind doesn't restrict channels of that kind
+pub(crate) struct AllowedChannels {
+    storage: Arc<dyn AllowedChannelsStorage>,
+    guilds: RwLock<HashMap<(GuildId, ChannelKind), HashSet<ChannelId>>>,
+}
+
+impl AllowedChannels {
+    /// Loads allowed channels of all guilds from the storage
+    pub(crate) async fn load(storage: Arc<dyn AllowedChannelsStorage>) -> Self {
+        let mut guilds: HashMap<_, HashSet<_>> = HashMap::new();
+        for (guild_id, kind, channel_id) in storage.load_all().await {
+            guilds
+                .entry((guild_id, kind))
+                .or_default()
+                .insert(channel_id);
+        }
+        Self {
+            storage,
+            guilds: RwLock::new(guilds),
+        }
+    }
+
+    /// Returns the guild's allowed channels of the kind, sorted by id.
+    /// Empty if channels of the kind are not restricted
+    pub(crate) fn list(&self, guild_id: GuildId, kind: ChannelKind) -> Vec<ChannelId> {
+        let mut channels: Vec<_> = self
+            .guilds
+            .read()
+            .unwrap()
+            .get(&(guild_id, kind))
+            .map(|channels| channels.iter().copied().collect())
+            .unwrap_or_default();
+        channels.sort_unstable();
+        channels
+    }
+
+    /// Returns whether the channel may be used in the guild
+    pub(crate) fn is_allowed(
+        &self,
+        guild_id: GuildId,
+        kind: ChannelKind,
+        channel_id: ChannelId,
+    ) -> bool {
+        self.guilds
+            .read()
+            .unwrap()
+            .get(&(guild_id, kind))
+            .is_none_or(|channels| channels.is_empty() || channels.contains(&channel_id))
+    }
+
+    /// Allows the channel in the guild. Returns `false` if it is already allowed
+    pub(crate) async fn allow(
+        &self,
+        guild_id: GuildId,
+        kind: ChannelKind,
+        channel_id: ChannelId,
+    ) -> Result<bool, anyhow::Error> {
+        if !self.storage.allow(guild_id, kind, channel_id).await? {
+            return Ok(false);
+        }
+        let mut guilds = self.guilds.write().unwrap();
+        guilds
+            .entry((guild_id, kind))
+            .or_default()
+            .insert(channel_id);
+        Ok(true)
+    }
+
+    /// Removes the channel from the allowed ones. Returns `false` if it wasn't allowed
+    pub(crate) async fn disallow(
+        &self,
+        guild_id: GuildId,
+        channel_id: ChannelId,
+    ) -> Result<bool, anyhow::Error> {
+        if !self.storage.disallow(guild_id, channel_id).await? {
+            return Ok(false);
+        }
+        let mut guilds = self.guilds.write().unwrap();
+        for kind in [ChannelKind::Text, ChannelKind::Voice] {
+            if let Some(channels) = guilds.get_mut(&(guild_id, kind)) {
+                channels.remove(&channel_id);
+            }
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct MemoryStorage {
+        channels: Mutex<Vec<(GuildId, ChannelKind, ChannelId)>>,
+    }
+
+    #[async_trait]
+    impl AllowedChannelsStorage for MemoryStorage {
+        async fn allow(
+            &self,
+            guild_id: GuildId,
+            kind: ChannelKind,
+            channel_id: ChannelId,
+        ) -> Result<bool, anyhow::Error> {
+            let mut channels = self.channels.lock().unwrap();
+            if channels
+                .iter()
+                .any(|&(guild, _, channel)| (guild, channel) == (guild_id, channel_id))
+            {
+                return Ok(false);
+            }
+            channels.push((guild_id, kind, channel_id));
+            Ok(true)
+        }
+
+        async fn disallow(
+            &self,
+            guild_id: GuildId,
+            channel_id: ChannelId,
+        ) -> Result<bool, anyhow::Error> {
+            let mut channels = self.channels.lock().unwrap();
+            let count = channels.len();
+            channels.retain(|&(guild, _, channel)| (guild, channel) != (guild_id, channel_id));
+            Ok(channels.len() < count)
+        }
+
+        async fn load_all(&self) -> Vec<(GuildId, ChannelKind, ChannelId)> {
+            self.channels.lock().unwrap().clone()
+        }
+    }
+
+    #[test]
+    fn channel_kind_test() {
+        assert_eq!(ChannelKind::of(ChannelType::Text), Some(ChannelKind::Text));
+        assert_eq!(
+            ChannelKind::of(ChannelType::Stage),
+            Some(ChannelKind::Voice)
+        );
+        assert_eq!(ChannelKind::of(ChannelType::Category), None);
+
+        for kind in [ChannelKind::Text, ChannelKind::Voice] {
+            assert_eq!(ChannelKind::from_key(kind.key()), Some(kind));
+        }
+        assert_eq!(ChannelKind::from_key("unknown"), None);
+    }
+
+    #[test]
+    fn mentions_test() {
+        assert_eq!(mentions(&[]), "");
+        assert_eq!(
+            mentions(&[ChannelId::new(1), ChannelId::new(2)]),
+            "<#1>, <#2>"
+        );
+    }
+
+    #[tokio::test]
+    async fn allowed_channels() {
+        let storage = Arc::new(MemoryStorage::default());
+        let channels = AllowedChannels::load(storage.clone()).await;
+        let guild_id = GuildId::new(101);
+        let [music, general, lounge] = [1, 2, 3].map(ChannelId::new);
+
+        // Nothing is restricted by default
+        assert!(channels.is_allowed(guild_id, ChannelKind::Text, general));
+        assert!(channels.is_allowed(guild_id, ChannelKind::Voice, lounge));
+
+        assert!(
+            channels
+                .allow(guild_id, ChannelKind::Text, music)
+                .await
+                .unwrap()
+        );
+        assert!(
+            !channels
+                .allow(guild_id, ChannelKind::Text, music)
+                .await
+                .unwrap()
+        );
+        assert!(channels.is_allowed(guild_id, ChannelKind::Text, music));
+        assert!(!channels.is_allowed(guild_id, ChannelKind::Text, general));
+        // Kinds and guilds are restricted separately
+        assert!(channels.is_allowed(guild_id, ChannelKind::Voice, lounge));
+        assert!(channels.is_allowed(GuildId::new(202), ChannelKind::Text, general));
+
+        assert!(
+            channels
+                .allow(guild_id, ChannelKind::Voice, lounge)
+                .await
+                .unwrap()
+        );
+        assert_eq!(channels.list(guild_id, ChannelKind::Voice), vec![lounge]);
+
+        // Saved channels are loaded on start
+        let channels = AllowedChannels::load(storage).await;
+        assert_eq!(channels.list(guild_id, ChannelKind::Text), vec![music]);
+        assert!(!channels.is_allowed(guild_id, ChannelKind::Voice, music));
+
+        // Removing the last allowed channel lifts the restriction
+        assert!(channels.disallow(guild_id, music).await.unwrap());
+        assert!(!channels.disallow(guild_id, music).await.unwrap());
+        assert!(channels.is_allowed(guild_id, ChannelKind::Text, general));
+        assert!(!channels.is_allowed(guild_id, ChannelKind::Voice, music));
+    }
+}
@@ -1,23 +1,513 @@
 use std::fmt::Write;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use poise::CreateReply;
-use serenity::builder::{CreateEmbed, CreateMessage};
+use futures::stream::{self, StreamExt};
+use poise::{ChoiceParameter, CreateReply};
+use serenity::builder::{
+    CreateActionRow, CreateButton, CreateEmbed, CreateInteractionResponse,
+    CreateInteractionResponseMessage, CreateMessage, CreateSelectMenu, CreateSelectMenuKind,
+    CreateSelectMenuOption,
+};
+use serenity::collector::ComponentInteractionCollector;
+use serenity::model::{
+    application::ComponentInteractionDataKind,
+    channel::GuildChannel,
+    guild::Role,
+    id::{ChannelId, GuildId, UserId},
+    user::User,
+};
 use smallvec::{SmallVec, smallvec};
-use songbird::{input::Input, tracks::Track};
+use songbird::{
+    Call,
+    error::JoinError,
+    input::Input,
+    tracks::{Track, TrackHandle},
+};
+use tokio::{sync::Mutex, task::JoinHandle};
 use tracing::info;
 
-use crate::{Context, track_info};
+use crate::{
+    Context, Data, blocklist, channels, events, history, limits, permissions, playlist,
+    saved_queue, track_info, vote_skip,
+};
 
-fn get_author_vc(ctx: &Context<'_>) -> Option<serenity::model::id::ChannelId> {
+/// How many playlist tracks are resolved at once
+const PLAYLIST_LOAD_CONCURRENCY: usize = 4;
+/// How many recently played tracks `/history` shows
+const HISTORY_LENGTH: usize = 15;
+/// How many entries each `/stats` leaderboard shows
+const STATS_LEADERBOARD_LENGTH: usize = 5;
+/// How long `/history` waits for a track to replay
+const HISTORY_REPLAY_TIMEOUT: Duration = Duration::from_secs(120);
+/// How long `/skip` accepts votes to skip the track
+const VOTE_SKIP_TIMEOUT: Duration = Duration::from_secs(120);
+
+fn get_author_vc(ctx: &Context<'_>) -> Option<ChannelId> {
     ctx.guild()?
         .voice_states
         .get(&ctx.author().id)
         .and_then(|voice_state| voice_state.channel_id)
 }
 
+/// Continue playing similar tracks once the queue is over
+#[poise::command(
+    guild_only,
+    slash_command,
+    default_member_permissions = "MANAGE_GUILD",
+    required_permissions = "MANAGE_GUILD",
+    check = "permissions::in_music_channel"
+)]
+pub(crate) async fn autoplay(
+    ctx: Context<'_>,
+    #[description = "Enable or disable autoplay"] enabled: bool,
+) -> Result<(), anyhow::Error> {
+    let guild_id = ctx.guild().unwrap().id;
+    ctx.data()
+        .settings
+        .update(guild_id, |settings| settings.autoplay = enabled)
+        .await?;
+
+    ctx.reply(if enabled {
+        "Autoplay enabled. I'll add similar tracks once the queue is over"
+    } else {
+        "Autoplay disabled"
+    })
+    .await?;
+    Ok(())
+}
+
+/// Manage tracks and users that are not allowed to be played
+#[poise::command(
+    guild_only,
+    slash_command,
+    default_member_permissions = "MANAGE_GUILD",
+    subcommands("blocklist_add", "blocklist_remove", "blocklist_list")
+)]
+pub(crate) async fn blocklist(_ctx: Context<'_>) -> Result<(), anyhow::Error> {
+    // Discord doesn't allow to invoke the parent of slash subcommands, so this is never called
+    Ok(())
+}
+
+/// Block a domain, a URL, titles matching a regex or a user from requesting tracks
+#[poise::command(
+    guild_only,
+    slash_command,
+    rename = "add",
+    required_permissions = "MANAGE_GUILD"
+)]
+pub(crate) async fn blocklist_add(
+    ctx: Context<'_>,
+    #[description = "What to block"] kind: blocklist::RuleKind,
+    #[description = "Domain, URL, case-insensitive title regex or user mention"] value: String,
+) -> Result<(), anyhow::Error> {
+    let guild_id = ctx.guild_id().unwrap();
+    let reply = match blocklist::Rule::new(kind, &value) {
+        Ok(rule) => {
+            let reply = format!("Blocked {rule}");
+            if ctx.data().blocklists.add(guild_id, rule.clone()).await? {
+                info!("{} blocked {rule} in {guild_id}", ctx.author().name);
+                reply
+            } else {
+                format!("{rule} is already blocked")
+            }
+        }
+        Err(reason) => reason,
+    };
+    ctx.send(CreateReply::default().content(reply).ephemeral(true))
+        .await?;
+    Ok(())
+}
+
+/// Remove a rule from the blocklist
+#[poise::command(
+    guild_only,
+    slash_command,
+    rename = "remove",
+    required_permissions = "MANAGE_GUILD"
+)]
+pub(crate) async fn blocklist_remove(
+    ctx: Context<'_>,
+    #[description = "What was blocked"] kind: blocklist::RuleKind,
+    #[description = "Domain, URL, title regex or user mention as it was blocked"] value: String,
+) -> Result<(), anyhow::Error> {
+    let guild_id = ctx.guild_id().unwrap();
+    let reply = match blocklist::Rule::new(kind, &value) {
+        Ok(rule) if ctx.data().blocklists.remove(guild_id, &rule).await? => {
+            info!("{} unblocked {rule} in {guild_id}", ctx.author().name);
+            format!("Unblocked {rule}")
+        }
+        Ok(rule) => format!("{rule} is not blocked"),
+        Err(reason) => reason,
+    };
+    ctx.send(CreateReply::default().content(reply).ephemeral(true))
+        .await?;
+    Ok(())
+}
+
+/// Show the blocklist of this server
+#[poise::command(
+    guild_only,
+    slash_command,
+    rename = "list",
+    required_permissions = "MANAGE_GUILD"
+)]
+pub(crate) async fn blocklist_list(ctx: Context<'_>) -> Result<(), anyhow::Error> {
+    let rules = ctx.data().blocklists.list(ctx.guild_id().unwrap());
+    let reply = if rules.is_empty() {
+        CreateReply::default().content("Nothing is blocked")
+    } else {
+        let rules = rules
+            .iter()
+            .map(|rule| format!("- {rule}"))
+            .collect::<Vec<_>>()
+            .join("\n");
+        CreateReply::default().embed(CreateEmbed::default().title("Blocklist").description(rules))
+    };
+    ctx.send(reply.ephemeral(true)).await?;
+    Ok(())
+}
+
+/// Manage the cache of search queries
+// The cache is shared by all servers, so only the bot owner may see and change it
+#[poise::command(slash_command, owners_only, subcommands("cache_forget", "cache_stats"))]
+pub(crate) async fn cache(_ctx: Context<'_>) -> Result<(), anyhow::Error> {
+    // Discord doesn't allow to invoke the parent of slash subcommands, so this is never called
+    Ok(())
+}
+
+/// Forget the cached result of a search query, so it will be searched again
+#[poise::command(slash_command, rename = "forget", owners_only)]
+pub(crate) async fn cache_forget(
+    ctx: Context<'_>,
+    #[description = "Search query as it was used with /play"] query: String,
+) -> Result<(), anyhow::Error> {
+    let reply = if ctx.data().yt_dlp_resolver.forget(&query).await? {
+        info!(
+            "{} removed '{query}' from the query cache",
+            ctx.author().name
+        );
+        format!("Forgot '{query}'. It will be searched again next time")
+    } else {
+        format!("'{query}' is not cached")
+    };
+    ctx.send(CreateReply::default().content(reply).ephemeral(true))
+        .await?;
+    Ok(())
+}
+
+/// Show usage statistics of the search query cache
+#[poise::command(slash_command, rename = "stats", owners_only)]
+pub(crate) async fn cache_stats(ctx: Context<'_>) -> Result<(), anyhow::Error> {
+    let stats = ctx
+        .data()
+        .yt_dlp_resolver
+        .cache_stats(STATS_LEADERBOARD_LENGTH)
+        .await;
+
+    let top_queries = stats
+        .top_queries
+        .iter()
+        .map(|(query, hits)| format!("{query} - {hits} hits"));
+    let embed = CreateEmbed::default()
+        .title("Query cache")
+        .field("Queries", stats.entries.to_string(), true)
+        .field("Expired", stats.expired.to_string(), true)
+        .field("Hits", stats.hits.to_string(), true)
+        .field("Most hit", leaderboard(top_queries), false);
+    ctx.send(CreateReply::default().embed(embed).ephemeral(true))
+        .await?;
+    Ok(())
+}
+
+/// Restrict music commands and the bot to some channels of this server
+#[poise::command(
+    guild_only,
+    slash_command,
+    default_member_permissions = "MANAGE_GUILD",
+    subcommands("channels_allow", "channels_disallow", "channels_list")
+)]
+pub(crate) async fn channels(_ctx: Context<'_>) -> Result<(), anyhow::Error> {
+    // Discord doesn't allow to invoke the parent of slash subcommands, so this is never called
+    Ok(())
+}
+
+/// Allow music commands in a text channel or the bot in a voice channel, restricting the rest
+#[poise::command(
+    guild_only,
+    slash_command,
+    rename = "allow",
+    required_permissions = "MANAGE_GUILD"
+)]
+pub(crate) async fn channels_allow(
+    ctx: Context<'_>,
+    #[description = "Text or voice channel"]
+    #[channel_types("Text", "News", "Voice", "Stage")]
+    channel: GuildChannel,
+) -> Result<(), anyhow::Error> {
+    let guild_id = ctx.guild_id().unwrap();
+    let reply = match channels::ChannelKind::of(channel.kind) {
+        Some(kind) => {
+            if ctx
+                .data()
+                .allowed_channels
+                .allow(guild_id, kind, channel.id)
+                .await?
+            {
+                info!(
+                    "{} allowed {kind:?} channel {} in {guild_id}",
+                    ctx.author().name,
+                    channel.id
+                );
+                let allowed = ctx.data().allowed_channels.list(guild_id, kind);
+                match kind {
+                    channels::ChannelKind::Text => format!(
+                        "Music commands can be used in {}",
+                        channels::mentions(&allowed)
+                    ),
+                    channels::ChannelKind::Voice => {
+                        format!("I can play in {}", channels::mentions(&allowed))
+                    }
+                }
+            } else {
+                format!("<#{}> is already allowed", channel.id)
+            }
+        }
+        None => format!("<#{}> is neither a text nor a voice channel", channel.id),
+    };
+    ctx.send(CreateReply::default().content(reply).ephemeral(true))
+        .await?;
+    Ok(())
+}
+
+/// Remove a channel from the allowed ones. Once none are left, all channels are allowed
+#[poise::command(
+    guild_only,
+    slash_command,
+    rename = "disallow",
+    required_permissions = "MANAGE_GUILD"
+)]
+pub(crate) async fn channels_disallow(
+    ctx: Context<'_>,
+    #[description = "Allowed text or voice channel"]
+    #[channel_types("Text", "News", "Voice", "Stage")]
+    channel: GuildChannel,
+) -> Result<(), anyhow::Error> {
+    let guild_id = ctx.guild_id().unwrap();
+    let reply = if ctx
+        .data()
+        .allowed_channels
+        .disallow(guild_id, channel.id)
+        .await?
+    {
+        info!(
+            "{} disallowed channel {} in {guild_id}",
+            ctx.author().name,
+            channel.id
+        );
+        format!("<#{}> is not allowed anymore", channel.id)
+    } else {
+        format!("<#{}> is not among allowed channels", channel.id)
+    };
+    ctx.send(CreateReply::default().content(reply).ephemeral(true))
+        .await?;
+    Ok(())
+}
+
+/// Show channels music commands and the bot are restricted to
+#[poise::command(
+    guild_only,
+    slash_command,
+    rename = "list",
+    required_permissions = "MANAGE_GUILD"
+)]
+pub(crate) async fn channels_list(ctx: Context<'_>) -> Result<(), anyhow::Error> {
+    let guild_id = ctx.guild_id().unwrap();
+    let allowed = |kind| {
+        let list = ctx.data().allowed_channels.list(guild_id, kind);
+        if list.is_empty() {
+            "Any".to_owned()
+        } else {
+            channels::mentions(&list)
+        }
+    };
+    let embed = CreateEmbed::default()
+        .title("Allowed channels")
+        .field(
+            "Music commands",
+            allowed(channels::ChannelKind::Text),
+            false,
+        )
+        .field("Voice", allowed(channels::ChannelKind::Voice), false);
+    ctx.send(CreateReply::default().embed(embed).ephemeral(true))
+        .await?;
+    Ok(())
+}
+
+/// Show recently played tracks and replay one of them
+#[poise::command(guild_only, slash_command, check = "permissions::in_music_channel")]
+pub(crate) async fn history(
+    ctx: Context<'_>,
+    #[description = "Show only tracks requested by this user"] user: Option<User>,
+) -> Result<(), anyhow::Error> {
+    let guild_id = ctx.guild().unwrap().id;
+    let entries = ctx
+        .data()
+        .history
+        .recent(guild_id, user.as_ref().map(|user| user.id), HISTORY_LENGTH)
+        .await;
+    if entries.is_empty() {
+        ctx.reply("Nothing was played yet").await?;
+        return Ok(());
+    }
+
+    let mut description = String::new();
+    for (i, entry) in entries.iter().enumerate() {
+        let _ = write!(
+            description,
+            "{}. [{}]({}) <t:{}:R> by {}",
+            i + 1,
+            entry.title,
+            entry.source_url,
+            entry.started_at,
+            entry.requested_by
+        );
+        if let Some(played) = entry.played {
+            let secs = played.as_secs();
+            let _ = write!(description, ", played {}:{:02}", secs / 60, secs % 60);
+        }
+        description.push('\n');
+    }
+    let embed = CreateEmbed::default()
+        .title(match &user {
+            Some(user) => format!("Recently requested by {}", user.name),
+            None => "Recently played".into(),
+        })
+        .description(description);
+
+    let menu_id = format!("{}-replay", ctx.id());
+    let options = entries
+        .iter()
+        .enumerate()
+        .map(|(i, entry)| {
+            // Discord limits labels to 100 characters
+            let label: String = format!("{}. {}", i + 1, entry.title)
+                .chars()
+                .take(100)
+                .collect();
+            CreateSelectMenuOption::new(label, i.to_string())
+        })
+        .collect();
+    let menu = CreateSelectMenu::new(&menu_id, CreateSelectMenuKind::String { options })
+        .placeholder("Replay a track");
+    let reply = ctx
+        .send(
+            CreateReply::default()
+                .embed(embed.clone())
+                .components(vec![CreateActionRow::SelectMenu(menu)]),
+        )
+        .await?;
+
+    // Only the author of the command can replay tracks from the menu
+    while let Some(interaction) = ComponentInteractionCollector::new(ctx.serenity_context())
+        .author_id(ctx.author().id)
+        .custom_ids(vec![menu_id.clone()])
+        .timeout(HISTORY_REPLAY_TIMEOUT)
+        .await
+    {
+        // Resolving the track might take longer than Discord waits for the response
+        interaction
+            .create_response(ctx, CreateInteractionResponse::Acknowledge)
+            .await?;
+        let ComponentInteractionDataKind::StringSelect { values } = &interaction.data.kind else {
+            continue;
+        };
+        let Some(entry) = values
+            .first()
+            .and_then(|value| value.parse::<usize>().ok())
+            .and_then(|i| entries.get(i))
+        else {
+            continue;
+        };
+        replay(ctx, guild_id, entry).await?;
+    }
+
+    // Disable the menu once it's expired
+    reply
+        .edit(ctx, CreateReply::default().embed(embed).components(vec![]))
+        .await?;
+    Ok(())
+}
+
+/// Adds the track from the play history to the queue
+async fn replay(
+    ctx: Context<'_>,
+    guild_id: GuildId,
+    entry: &history::HistoryEntry,
+) -> Result<(), anyhow::Error> {
+    info!(
+        "{} requested to replay '{}'",
+        ctx.author().name,
+        entry.title
+    );
+
+    let Some(channel_id) = get_author_vc(&ctx) else {
+        ctx.reply("You should be in a voice channel if you want me to play for you")
+            .await?;
+        return Ok(());
+    };
+    // Menu interactions bypass command checks, so the same check is done here
+    if !permissions::in_bot_vc(ctx).await? || !may_request(ctx).await? {
+        return Ok(());
+    }
+    let vc = join_vc(&ctx, guild_id, channel_id);
+
+    let resolved_items = match resolve_query(
+        ctx.data(),
+        guild_id,
+        Some(ctx.author().id),
+        &entry.source_url,
+    )
+    .await
+    {
+        Ok(resolved_items) => resolved_items,
+        Err(reply) => {
+            ctx.reply(reply).await?;
+            return Ok(());
+        }
+    };
+
+    let vc = vc.await??;
+    let mut vc = vc.lock().await;
+    let (added, not_added) = enqueue(&ctx, &mut vc, resolved_items.into_vec()).await;
+    if added == 0 {
+        drop(vc);
+        ctx.reply(list_reasons(
+            &format!("'{}' is not added:", entry.title),
+            &not_added,
+        ))
+        .await?;
+        return Ok(());
+    }
+    let queue_info = form_currently_played(&vc.queue().current_queue()).await;
+    drop(vc);
+
+    ctx.channel_id()
+        .send_message(
+            ctx.serenity_context(),
+            CreateMessage::default().embed(queue_info),
+        )
+        .await?;
+    Ok(())
+}
+
 /// Join my current voice channel
-#[poise::command(guild_only, slash_command)]
+#[poise::command(
+    guild_only,
+    slash_command,
+    check = "permissions::in_music_channel",
+    check = "permissions::in_bot_vc"
+)]
 pub(crate) async fn join(ctx: Context<'_>) -> Result<(), anyhow::Error> {
     let guild_id = ctx.guild().unwrap().id;
     let Some(channel_id) = get_author_vc(&ctx) else {
@@ -25,6 +515,15 @@ pub(crate) async fn join(ctx: Context<'_>) -> Result<(), anyhow::Error> {
             .await?;
         return Ok(());
     };
+    // DJs pass `permissions::in_bot_vc` from anywhere, but can't take the bot to restricted channels
+    if !ctx
+        .data()
+        .allowed_channels
+        .is_allowed(guild_id, channels::ChannelKind::Voice, channel_id)
+    {
+        permissions::refuse_to_join(ctx, channel_id).await?;
+        return Ok(());
+    }
 
     let _vc_handler = songbird::get(ctx.serenity_context())
         .await
@@ -37,7 +536,13 @@ pub(crate) async fn join(ctx: Context<'_>) -> Result<(), anyhow::Error> {
 }
 
 /// Leave voice channel
-#[poise::command(guild_only, slash_command)]
+#[poise::command(
+    guild_only,
+    slash_command,
+    check = "permissions::in_music_channel",
+    check = "permissions::dj_only",
+    check = "permissions::in_bot_vc"
+)]
 pub(crate) async fn leave(ctx: Context<'_>) -> Result<(), anyhow::Error> {
     let guild_id = ctx.guild().unwrap().id;
 
@@ -61,7 +566,12 @@ pub(crate) async fn ping(ctx: Context<'_>) -> Result<(), anyhow::Error> {
 }
 
 /// Play a song from a URL or search query
-#[poise::command(guild_only, slash_command)]
+#[poise::command(
+    guild_only,
+    slash_command,
+    check = "permissions::in_music_channel",
+    check = "permissions::in_bot_vc"
+)]
 pub(crate) async fn play(ctx: Context<'_>, query: String) -> Result<(), anyhow::Error> {
     info!("{} requested to play '{query}'", ctx.author().name);
 
@@ -71,92 +581,462 @@ pub(crate) async fn play(ctx: Context<'_>, query: String) -> Result<(), anyhow::
         return Ok(());
     };
 
-    let songbird = songbird::get(ctx.serenity_context())
-        .await
-        .expect("Songbird Voice client placed in at initialisation.");
-
     let guild_id = ctx.guild().unwrap().id;
+    if !may_request(ctx).await? {
+        return Ok(());
+    }
+    let vc = join_vc(&ctx, guild_id, channel_id);
+
+    let _ = ctx.reply(format!("Processing {query}...")).await;
+
+    let resolved_items =
+        match resolve_query(ctx.data(), guild_id, Some(ctx.author().id), &query).await {
+            Ok(resolved_items) => resolved_items.into_vec(),
+            Err(reply) => {
+                ctx.reply(reply).await?;
+                return Ok(());
+            }
+        };
+
+    let vc = vc.await??;
+    let mut vc = vc.lock().await;
+    let (added, not_added) = enqueue(&ctx, &mut vc, resolved_items).await;
+    if added == 0 {
+        drop(vc);
+        ctx.reply(list_reasons(
+            &format!("Nothing is added from {query}:"),
+            &not_added,
+        ))
+        .await?;
+        return Ok(());
+    }
+    let queue_info = form_currently_played(&vc.queue().current_queue()).await;
+    drop(vc);
+
+    // fetching track info from yt-dlp may take some time (youtube seems to slow down such requests),
+    // so instead of replying we send a message.
+    let mut message = CreateMessage::default().embed(queue_info);
+    if !not_added.is_empty() {
+        message = message.content(list_reasons("Some tracks are not added:", &not_added));
+    }
+    ctx.channel_id()
+        .send_message(ctx.serenity_context(), message)
+        .await?;
+
+    Ok(())
+}
 
-    // Do it in a separate task as joining a voice channel can take some time
-    let vc = tokio::spawn(async move {
+/// Joins the voice channel unless the bot is already in one in this guild.
+/// It's done in a separate task as joining a voice channel can take some time
+fn join_vc(
+    ctx: &Context<'_>,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+) -> JoinHandle<Result<Arc<Mutex<Call>>, JoinError>> {
+    let serenity_ctx = ctx.serenity_context().clone();
+    tokio::spawn(async move {
+        let songbird = songbird::get(&serenity_ctx)
+            .await
+            .expect("Songbird Voice client placed in at initialisation.");
         match songbird.get(guild_id) {
-            // todo: check if bot is in the same channel as the user
+            // Only DJs can get here from another channel, see `permissions::in_bot_vc`
             Some(vc) => Ok(vc),
             None => songbird.join(guild_id, channel_id).await,
         }
-    });
-
-    let _ = ctx.reply(format!("Processing {query}...")).await;
+    })
+}
 
+/// Resolves the query to tracks, trying Spotify, Radio-T (unless disabled in the guild settings)
+/// and yt-dlp in this order.
+/// Returns a user-friendly explanation if nothing can be played.
+pub(crate) async fn resolve_query(
+    data: &Data,
+    guild_id: GuildId,
+    _requester: Option<UserId>,
+    query: &str,
+) -> Result<SmallVec<[(track_info::Metadata, Input); 1]>, String> {
     #[cfg(feature = "spotify")]
-    let spotify_tracks: Option<SmallVec<[(track_info::Metadata, Input); 1]>> = ctx
-        .data()
+    if let Some(result) = data
         .spotify_resolver
-        .resolve(guild_id, &query)
+        .resolve(guild_id, _requester, query)
         .await
-        .map(|tracks| {
-            tracks
+    {
+        return match result {
+            Ok(tracks) => Ok(tracks
                 .into_iter()
                 .map(|track| (track.metadata().clone(), track.into()))
-                .collect()
-        });
-    #[cfg(not(feature = "spotify"))]
-    let spotify_tracks: Option<SmallVec<[(track_info::Metadata, Input); 1]>> = None;
-
-    let resolved_items: SmallVec<[(track_info::Metadata, Input); 1]> =
-        if let Some(tracks) = spotify_tracks {
-            if tracks.is_empty() {
-                ctx.reply(format!(
-                    "Invalid Spotify query '{query}'. Please try something else"
-                ))
-                .await?;
-                return Ok(());
-            }
-            tracks
-        } else if let Some(podcast) = ctx.data().radio_t_resolver.resolve(&query).await {
-            smallvec![(podcast.metadata().clone(), podcast.into())]
-        } else if let Some(yt_dlp) = ctx.data().yt_dlp_resolver.resolve(&query).await {
-            smallvec![(yt_dlp.metadata().clone(), yt_dlp.into())]
-        } else {
-            ctx.reply(format!(
-                "Found nothing for '{query}'. Please try something else"
-            ))
-            .await?;
-            return Ok(());
+                .collect()),
+            Err(err) => Err(spotify_error_reply(query, &err)),
         };
+    }
 
-    let vc = vc.await??;
-    let mut vc = vc.lock().await;
-    for (metadata, input) in resolved_items {
+    let podcast = if data.settings.get(guild_id).radio_t {
+        data.radio_t_resolver.resolve(query).await
+    } else {
+        None
+    };
+    if let Some(podcast) = podcast {
+        Ok(smallvec![(podcast.metadata().clone(), podcast.into())])
+    } else if let Some(yt_dlp) = data.yt_dlp_resolver.resolve(query).await {
+        Ok(smallvec![(yt_dlp.metadata().clone(), yt_dlp.into())])
+    } else {
+        Err(format!(
+            "Found nothing for '{query}'. Please try something else"
+        ))
+    }
+}
+
+/// Checks that the invoker may request tracks right now, telling them why not otherwise
+async fn may_request(ctx: Context<'_>) -> Result<bool, anyhow::Error> {
+    let guild_id = ctx.guild_id().unwrap();
+    // Blocklists apply to everyone, as they are the guild's rules rather than flood protection
+    if ctx
+        .data()
+        .blocklists
+        .is_user_blocked(guild_id, ctx.author().id)
+    {
+        ctx.send(
+            CreateReply::default()
+                .content("You are not allowed to request tracks here")
+                .ephemeral(true),
+        )
+        .await?;
+        return Ok(false);
+    }
+    // DJs are trusted not to flood the queue, so the cooldown doesn't apply to them
+    if permissions::is_dj(ctx).await {
+        return Ok(true);
+    }
+    let Some(remaining) = ctx
+        .data()
+        .cooldowns
+        .remaining(guild_id, ctx.author().id, Instant::now())
+    else {
+        return Ok(true);
+    };
+    ctx.send(
+        CreateReply::default()
+            .content(format!(
+                "You can request tracks once in {} s, try again in {} s",
+                ctx.data().settings.get(guild_id).play_cooldown_secs,
+                remaining.as_secs() + 1
+            ))
+            .ephemeral(true),
+    )
+    .await?;
+    Ok(false)
+}
+
+/// Adds the requested tracks to the queue, dropping blocked ones and those over the guild's
+/// limits unless the invoker is a DJ. Returns how many tracks are added and why the rest are not
+async fn enqueue(
+    ctx: &Context<'_>,
+    vc: &mut Call,
+    mut items: Vec<(track_info::Metadata, Input)>,
+) -> (usize, Vec<String>) {
+    let guild_id = ctx.guild_id().unwrap();
+    let settings = ctx.data().settings.get(guild_id);
+    // Blocked tracks are dropped first, so they don't take up the user's quota
+    let mut not_added: Vec<String> = ctx
+        .data()
+        .blocklists
+        .apply(guild_id, &mut items)
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+
+    // DJs are trusted not to flood the queue, so limits don't apply to them
+    if !permissions::is_dj(*ctx).await {
+        let requester = track_info::Requester::User(ctx.author().id);
+        let user_tracks = vc
+            .queue()
+            .current_queue()
+            .iter()
+            .filter(|track| track.data::<track_info::TrackInfo>().added_by() == requester)
+            .count();
+        let hit_limits = limits::apply(&settings, &mut items, user_tracks);
+        not_added.extend(hit_limits.iter().map(ToString::to_string));
+        // Requests that added nothing don't count
+        if !items.is_empty() {
+            let cooldown = Duration::from_secs(settings.play_cooldown_secs.into());
+            ctx.data()
+                .cooldowns
+                .start(guild_id, ctx.author().id, cooldown, Instant::now());
+        }
+    }
+
+    let added = items.len();
+    let volume = settings.track_volume();
+    for (metadata, input) in items {
         // Attach description to the track handle so we can display each entry in the queue
         let track = Track::new_with_data(
             input,
             Arc::new(track_info::TrackInfo::new(
                 metadata,
-                ctx.author().name.clone(),
+                track_info::Requester::User(ctx.author().id),
             )),
         )
-        // Reduce volume to 50% to avoid ear damage for new users
-        .volume(0.5);
+        .volume(volume);
         let _ = vc.enqueue(track).await;
     }
-    let queue_info = form_currently_played(&vc.queue().current_queue()).await;
-    drop(vc);
+    if added > 0 {
+        saved_queue::save(ctx.data(), guild_id, vc).await;
+    }
+    (added, not_added)
+}
 
-    // fetching track info from yt-dlp may take some time (youtube seems to slow down such requests),
-    // so instead of replying we send a message.
-    ctx.channel_id()
-        .send_message(
-            ctx.serenity_context(),
-            CreateMessage::default().embed(queue_info),
-        )
+/// Lists why some of the requested tracks are not added under the heading
+fn list_reasons(heading: &str, reasons: &[String]) -> String {
+    let mut list = heading.to_owned();
+    for reason in reasons {
+        let _ = write!(list, "\n- {reason}");
+    }
+    list
+}
+
+/// Remove a track from the queue
+#[poise::command(
+    guild_only,
+    slash_command,
+    check = "permissions::in_music_channel",
+    check = "permissions::in_bot_vc"
+)]
+pub(crate) async fn remove(
+    ctx: Context<'_>,
+    #[description = "Position of the track in the list of next tracks"]
+    #[min = 1]
+    position: usize,
+) -> Result<(), anyhow::Error> {
+    let guild_id = ctx.guild().unwrap().id;
+    let songbird = songbird::get(ctx.serenity_context())
+        .await
+        .expect("Songbird Voice client placed in at initialisation.");
+    let Some(vc) = songbird.get(guild_id) else {
+        ctx.reply("I'm not in a voice channel").await?;
+        return Ok(());
+    };
+
+    // The current track is at 0, so positions of the next ones match indices in the queue
+    let Some(track) = vc
+        .lock()
+        .await
+        .queue()
+        .current_queue()
+        .get(position)
+        .cloned()
+    else {
+        ctx.reply(format!("There is no track at position {position}"))
+            .await?;
+        return Ok(());
+    };
+    let track_info = track.data::<track_info::TrackInfo>();
+    // Everyone may remove their own tracks, but only DJs may remove others' ones
+    if track_info.added_by() != track_info::Requester::User(ctx.author().id)
+        && !permissions::dj_only(ctx).await?
+    {
+        return Ok(());
+    }
+
+    // The queue might have changed in the meantime, so look the track up again
+    let removed = vc.lock().await.queue().modify_queue(|queue| {
+        let index = queue
+            .iter()
+            .position(|queued| queued.uuid() == track.uuid())?;
+        queue.remove(index)
+    });
+    let Some(removed) = removed else {
+        ctx.reply("The track has already left the queue").await?;
+        return Ok(());
+    };
+    // Tracks in the queue are already added to the driver, so it should be stopped as well
+    let _ = removed.stop();
+
+    info!(
+        "{} removed '{}' from the queue",
+        ctx.author().name,
+        track_info.metadata().title
+    );
+    ctx.reply(format!("Removed {}", track_info.metadata()))
         .await?;
+    Ok(())
+}
+
+/// Show or change bot settings for this server
+#[poise::command(
+    guild_only,
+    slash_command,
+    default_member_permissions = "MANAGE_GUILD",
+    required_permissions = "MANAGE_GUILD"
+)]
+#[allow(clippy::too_many_arguments)] // each setting is a separate slash command option
+pub(crate) async fn settings(
+    ctx: Context<'_>,
+    #[description = "Volume of newly added tracks in percent"]
+    #[min = 1]
+    #[max = 100]
+    volume: Option<u8>,
+    #[description = "Voice bitrate in kbps. Discord allows up to 96 kbps without server boosts"]
+    #[min = 8]
+    #[max = 384]
+    bitrate: Option<u32>,
+    #[description = "Play similar tracks once the queue is over"] autoplay: Option<bool>,
+    #[description = "Pause and leave the voice channel once everyone else has left it"]
+    leave_when_alone: Option<bool>,
+    #[description = "Minutes to wait with an empty queue or alone before leaving the voice channel"]
+    #[min = 1]
+    #[max = 1440]
+    idle_timeout: Option<u32>,
+    #[description = "Play Radio-T for shortcuts like 'rt' instead of searching them"]
+    radio_t: Option<bool>,
+    #[description = "Role allowed to stop and skip others' tracks, @everyone to allow all"]
+    dj_role: Option<Role>,
+    #[description = "Percent of listeners needed to skip others' tracks, 0 to disable voting"]
+    #[min = 0]
+    #[max = 100]
+    vote_skip: Option<u8>,
+    #[description = "How many tracks a user may have in the queue, 0 for no limit"]
+    #[min = 0]
+    #[max = 1000]
+    user_tracks_limit: Option<u32>,
+    #[description = "How long a requested track may be in minutes, 0 for no limit"]
+    #[min = 0]
+    #[max = 1440]
+    track_minutes_limit: Option<u32>,
+    #[description = "How many tracks of a requested playlist are added, 0 for no limit"]
+    #[min = 0]
+    #[max = 1000]
+    playlist_tracks_limit: Option<u32>,
+    #[description = "Seconds a user waits between /play commands, 0 for no cooldown"]
+    #[min = 0]
+    #[max = 3600]
+    play_cooldown: Option<u32>,
+    #[description = "Follow the latest requester to another voice channel once I'm left alone"]
+    follow_requester: Option<bool>,
+) -> Result<(), anyhow::Error> {
+    let guild_id = ctx.guild().unwrap().id;
+    let changed = volume.is_some()
+        || bitrate.is_some()
+        || autoplay.is_some()
+        || leave_when_alone.is_some()
+        || idle_timeout.is_some()
+        || radio_t.is_some()
+        || dj_role.is_some()
+        || vote_skip.is_some()
+        || user_tracks_limit.is_some()
+        || track_minutes_limit.is_some()
+        || playlist_tracks_limit.is_some()
+        || play_cooldown.is_some()
+        || follow_requester.is_some();
+    let settings = if changed {
+        let settings = ctx
+            .data()
+            .settings
+            .update(guild_id, |settings| {
+                settings.volume = volume.unwrap_or(settings.volume);
+                settings.bitrate_kbps = bitrate.unwrap_or(settings.bitrate_kbps);
+                settings.autoplay = autoplay.unwrap_or(settings.autoplay);
+                settings.leave_when_alone = leave_when_alone.unwrap_or(settings.leave_when_alone);
+                settings.idle_timeout_minutes =
+                    idle_timeout.unwrap_or(settings.idle_timeout_minutes);
+                settings.radio_t = radio_t.unwrap_or(settings.radio_t);
+                if let Some(role) = &dj_role {
+                    // @everyone role has the same id as the guild
+                    settings.dj_role = (role.id.get() != guild_id.get()).then_some(role.id);
+                }
+                settings.vote_skip_percent = vote_skip.unwrap_or(settings.vote_skip_percent);
+                settings.user_tracks_limit =
+                    user_tracks_limit.unwrap_or(settings.user_tracks_limit);
+                settings.track_minutes_limit =
+                    track_minutes_limit.unwrap_or(settings.track_minutes_limit);
+                settings.playlist_tracks_limit =
+                    playlist_tracks_limit.unwrap_or(settings.playlist_tracks_limit);
+                settings.play_cooldown_secs = play_cooldown.unwrap_or(settings.play_cooldown_secs);
+                settings.follow_requester = follow_requester.unwrap_or(settings.follow_requester);
+            })
+            .await?;
+        info!("{} changed settings in {guild_id}", ctx.author().name);
+
+        // Apply the bitrate right away if the bot is in a voice channel
+        let songbird = songbird::get(ctx.serenity_context())
+            .await
+            .expect("Songbird Voice client placed in at initialisation.");
+        if bitrate.is_some()
+            && let Some(vc) = songbird.get(guild_id)
+        {
+            vc.lock().await.set_bitrate(settings.voice_bitrate());
+        }
+        settings
+    } else {
+        ctx.data().settings.get(guild_id)
+    };
 
+    let on_off = |enabled| if enabled { "on" } else { "off" };
+    let limit = |value: u32, unit: &str| match value {
+        0 => "no limit".to_owned(),
+        value => format!("{value}{unit}"),
+    };
+    let mut embed = CreateEmbed::default()
+        .title("Settings")
+        .field("Volume", format!("{}%", settings.volume), true)
+        .field("Bitrate", format!("{} kbps", settings.bitrate_kbps), true)
+        .field("Autoplay", on_off(settings.autoplay), true)
+        .field("Leave when alone", on_off(settings.leave_when_alone), true)
+        .field(
+            "Idle timeout",
+            format!("{} min", settings.idle_timeout_minutes),
+            true,
+        )
+        .field("Radio-T", on_off(settings.radio_t), true)
+        .field(
+            "DJ role",
+            settings
+                .dj_role
+                .map_or_else(|| "everyone".into(), |id| format!("<@&{id}>")),
+            true,
+        )
+        .field(
+            "Vote skip",
+            match settings.vote_skip_percent {
+                0 => "off".into(),
+                percent => format!("{percent}% of listeners"),
+            },
+            true,
+        )
+        .field(
+            "Tracks per user",
+            limit(settings.user_tracks_limit, ""),
+            true,
+        )
+        .field(
+            "Track duration",
+            limit(settings.track_minutes_limit, " min"),
+            true,
+        )
+        .field(
+            "Playlist tracks",
+            limit(settings.playlist_tracks_limit, ""),
+            true,
+        )
+        .field(
+            "Play cooldown",
+            limit(settings.play_cooldown_secs, " s"),
+            true,
+        )
+        .field("Follow requester", on_off(settings.follow_requester), true);
+    if volume.is_some() {
+        embed = embed.description("Volume applies to tracks added from now on");
+    }
+    ctx.send(CreateReply::default().embed(embed)).await?;
     Ok(())
 }
 
-/// Skip the current song
-#[poise::command(guild_only, slash_command)]
+/// Skip the current song or vote to skip it
+#[poise::command(
+    guild_only,
+    slash_command,
+    check = "permissions::in_music_channel",
+    check = "permissions::in_bot_vc"
+)]
 pub(crate) async fn skip(ctx: Context<'_>) -> Result<(), anyhow::Error> {
     let guild_id = ctx.guild().unwrap().id;
     let songbird = songbird::get(ctx.serenity_context())
@@ -168,12 +1048,30 @@ pub(crate) async fn skip(ctx: Context<'_>) -> Result<(), anyhow::Error> {
         return Ok(());
     };
 
+    // Everyone may skip their own tracks, while others' ones are skipped by DJs or by vote
+    let current = vc.lock().await.queue().current();
+    if let Some(current) = current
+        && current.data::<track_info::TrackInfo>().added_by()
+            != track_info::Requester::User(ctx.author().id)
+    {
+        let percent = ctx.data().settings.get(guild_id).vote_skip_percent;
+        if percent > 0 && !permissions::is_dj(ctx).await {
+            return vote_skip(ctx, guild_id, current, percent).await;
+        }
+        if !permissions::dj_only(ctx).await? {
+            return Ok(());
+        }
+    }
+
     let vc = vc.lock().await;
 
     // Unfortunately, `queue().skip()` doesn't update queue immidiately, so we take the queue *before*
     // and form the embed with tracks after the current one via `get(1..)`
     let queue_info =
         form_currently_played(vc.queue().current_queue().get(1..).unwrap_or_default()).await;
+    if let Some(current) = vc.queue().current() {
+        current.data::<track_info::TrackInfo>().mark_skipped();
+    }
     let _ = vc.queue().skip();
     drop(vc);
 
@@ -181,8 +1079,213 @@ pub(crate) async fn skip(ctx: Context<'_>) -> Result<(), anyhow::Error> {
     Ok(())
 }
 
+/// Adds the invoker's vote to skip the track and keeps counting votes via the button
+/// under the reply until enough listeners have voted
+async fn vote_skip(
+    ctx: Context<'_>,
+    guild_id: GuildId,
+    track: TrackHandle,
+    percent: u8,
+) -> Result<(), anyhow::Error> {
+    let metadata = track.data::<track_info::TrackInfo>().metadata().clone();
+    let title = metadata.to_string();
+    let vote = |user_id| {
+        let listeners = events::listeners(ctx.serenity_context(), guild_id);
+        ctx.data().vote_skips.vote(
+            guild_id,
+            track.uuid().as_u128(),
+            user_id,
+            &listeners,
+            percent,
+        )
+    };
+    let embed = |description: String| {
+        CreateEmbed::default()
+            .title("Vote skip")
+            .description(description)
+    };
+    let voting = |tally: vote_skip::Tally| {
+        embed(format!(
+            "Skip {title}?\n{} of {} votes",
+            tally.votes, tally.required
+        ))
+    };
+    let skipped = || {
+        info!("'{}' is skipped by vote in {guild_id}", metadata.title);
+        track.data::<track_info::TrackInfo>().mark_skipped();
+        let _ = track.stop();
+        embed(format!("Skipped {title} by vote"))
+    };
+
+    let mut tally = vote(ctx.author().id);
+    if tally.passed() {
+        ctx.send(CreateReply::default().embed(skipped())).await?;
+        return Ok(());
+    }
+
+    let button_id = format!("{}-vote-skip", ctx.id());
+    let buttons = vec![CreateActionRow::Buttons(vec![
+        CreateButton::new(&button_id).label("Skip"),
+    ])];
+    let reply = ctx
+        .send(
+            CreateReply::default()
+                .embed(voting(tally))
+                .components(buttons.clone()),
+        )
+        .await?;
+
+    while let Some(interaction) = ComponentInteractionCollector::new(ctx.serenity_context())
+        .custom_ids(vec![button_id.clone()])
+        .timeout(VOTE_SKIP_TIMEOUT)
+        .await
+    {
+        let listeners = events::listeners(ctx.serenity_context(), guild_id);
+        if !listeners.contains(&interaction.user.id) {
+            interaction
+                .create_response(
+                    ctx,
+                    CreateInteractionResponse::Message(
+                        CreateInteractionResponseMessage::new()
+                            .content("Only those listening with me can vote")
+                            .ephemeral(true),
+                    ),
+                )
+                .await?;
+            continue;
+        }
+
+        // The track might have ended or been skipped in the meantime
+        let current = match songbird::get(ctx.serenity_context())
+            .await
+            .expect("Songbird Voice client placed in at initialisation.")
+            .get(guild_id)
+        {
+            Some(vc) => vc.lock().await.queue().current(),
+            None => None,
+        };
+        let (embed, finished) = if current.is_none_or(|current| current.uuid() != track.uuid()) {
+            (embed(format!("{title} is already over")), true)
+        } else {
+            tally = vote(interaction.user.id);
+            if tally.passed() {
+                (skipped(), true)
+            } else {
+                (voting(tally), false)
+            }
+        };
+        let mut update = CreateInteractionResponseMessage::new().embed(embed);
+        if finished {
+            update = update.components(vec![]);
+        }
+        interaction
+            .create_response(ctx, CreateInteractionResponse::UpdateMessage(update))
+            .await?;
+        if finished {
+            return Ok(());
+        }
+    }
+
+    // Disable the button once the vote has expired
+    reply
+        .edit(
+            ctx,
+            CreateReply::default()
+                .embed(embed(format!(
+                    "Vote to skip {title} has expired with {} of {} votes",
+                    tally.votes, tally.required
+                )))
+                .components(vec![]),
+        )
+        .await?;
+    Ok(())
+}
+
+/// Show listening statistics of this server
+#[poise::command(guild_only, slash_command, check = "permissions::in_music_channel")]
+pub(crate) async fn stats(
+    ctx: Context<'_>,
+    #[description = "This week by default"] period: Option<history::Period>,
+) -> Result<(), anyhow::Error> {
+    let guild_id = ctx.guild().unwrap().id;
+    let period = period.unwrap_or_default();
+    let stats = ctx
+        .data()
+        .history
+        .stats(guild_id, period, STATS_LEADERBOARD_LENGTH)
+        .await;
+
+    let embed = if stats.top_tracks.is_empty() {
+        CreateEmbed::default()
+            .title(format!("Stats: {}", period.name()))
+            .description("Nothing was played in this period")
+    } else {
+        let requesters = stats
+            .top_requesters
+            .iter()
+            .map(|(name, count)| format!("{name} - {count} tracks"));
+        CreateEmbed::default()
+            .title(format!("Stats: {}", period.name()))
+            .description(format!(
+                "Listened for {:.1} hours",
+                stats.listened.as_secs_f64() / 3600.0
+            ))
+            .field(
+                "Top tracks",
+                leaderboard(track_counts(&stats.top_tracks, "plays")),
+                false,
+            )
+            .field("Top requesters", leaderboard(requesters), false)
+            .field(
+                "Most skipped",
+                leaderboard(track_counts(&stats.most_skipped, "skips")),
+                false,
+            )
+    };
+    ctx.send(CreateReply::default().embed(embed)).await?;
+    Ok(())
+}
+
+/// Formats tracks as "[title](source_url) - N plays" lines
+fn track_counts<'a>(
+    tracks: &'a [history::TrackCount],
+    unit: &'a str,
+) -> impl Iterator<Item = String> + 'a {
+    tracks.iter().map(move |track| {
+        format!(
+            "[{}]({}) - {} {unit}",
+            track.title, track.source_url, track.count
+        )
+    })
+}
+
+/// Forms a numbered list that fits into the embed field
+fn leaderboard(lines: impl Iterator<Item = String>) -> String {
+    // Discord limits embed field values to 1024 characters
+    const MAX_LEN: usize = 1024;
+
+    let mut list = String::new();
+    for (i, line) in lines.enumerate() {
+        let line = format!("{}. {line}\n", i + 1);
+        if list.chars().count() + line.chars().count() > MAX_LEN {
+            break;
+        }
+        list.push_str(&line);
+    }
+    if list.is_empty() {
+        list.push('-');
+    }
+    list
+}
+
 /// Stop playing and clear the queue
-#[poise::command(guild_only, slash_command)]
+#[poise::command(
+    guild_only,
+    slash_command,
+    check = "permissions::in_music_channel",
+    check = "permissions::dj_only",
+    check = "permissions::in_bot_vc"
+)]
 pub(crate) async fn stop(ctx: Context<'_>) -> Result<(), anyhow::Error> {
     let guild_id = ctx.guild().unwrap().id;
     let songbird = songbird::get(ctx.serenity_context())
@@ -198,9 +1301,245 @@ pub(crate) async fn stop(ctx: Context<'_>) -> Result<(), anyhow::Error> {
     Ok(())
 }
 
-/// Connect Spotify account to be used by bot.
+/// Manage playlists saved in this server
+#[poise::command(
+    guild_only,
+    slash_command,
+    subcommands("playlist_save", "playlist_load", "playlist_list", "playlist_delete"),
+    // Checks of the parent command run for its subcommands as well
+    check = "permissions::in_music_channel"
+)]
+pub(crate) async fn playlist(_ctx: Context<'_>) -> Result<(), anyhow::Error> {
+    // Discord doesn't allow to invoke the parent of slash subcommands, so this is never called
+    Ok(())
+}
+
+/// Save the current queue as a playlist
+#[poise::command(guild_only, slash_command, rename = "save")]
+pub(crate) async fn playlist_save(
+    ctx: Context<'_>,
+    #[description = "Playlist name. Existing playlist with the same name is replaced, only by DJs"]
+    name: String,
+) -> Result<(), anyhow::Error> {
+    let guild_id = ctx.guild().unwrap().id;
+    let Some(name) = playlist::normalize_name(&name) else {
+        ctx.reply("Playlist name should be 1 to 50 characters long")
+            .await?;
+        return Ok(());
+    };
+    // Replacing a playlist destroys it just like `/playlist delete` does
+    let exists = ctx
+        .data()
+        .playlist_storage
+        .load(guild_id, name)
+        .await
+        .is_some();
+    if exists && !permissions::dj_only(ctx).await? {
+        return Ok(());
+    }
+
+    let songbird = songbird::get(ctx.serenity_context())
+        .await
+        .expect("Songbird Voice client placed in at initialisation.");
+    let queue = match songbird.get(guild_id) {
+        Some(vc) => vc.lock().await.queue().current_queue(),
+        None => vec![],
+    };
+    if queue.is_empty() {
+        ctx.reply("Nothing to save, the queue is empty").await?;
+        return Ok(());
+    }
+
+    let tracks: Vec<_> = queue
+        .iter()
+        .map(|track| {
+            let metadata = track.data::<track_info::TrackInfo>().metadata().clone();
+            playlist::PlaylistTrack {
+                source_url: metadata.source_url.into(),
+                title: metadata.title.into(),
+            }
+        })
+        .collect();
+    ctx.data()
+        .playlist_storage
+        .save(guild_id, name, &tracks)
+        .await?;
+
+    ctx.reply(format!("Saved {} tracks to '{name}'", tracks.len()))
+        .await?;
+    Ok(())
+}
+
+/// Add tracks from a saved playlist to the queue
+#[poise::command(
+    guild_only,
+    slash_command,
+    rename = "load",
+    check = "permissions::in_bot_vc"
+)]
+pub(crate) async fn playlist_load(
+    ctx: Context<'_>,
+    #[description = "Playlist name"]
+    #[autocomplete = "autocomplete_playlist"]
+    name: String,
+) -> Result<(), anyhow::Error> {
+    let Some(channel_id) = get_author_vc(&ctx) else {
+        ctx.reply("You should be in a voice channel if you want me to play for you")
+            .await?;
+        return Ok(());
+    };
+
+    if !may_request(ctx).await? {
+        return Ok(());
+    }
+
+    let guild_id = ctx.guild().unwrap().id;
+    let name = name.trim();
+    let Some(tracks) = ctx.data().playlist_storage.load(guild_id, name).await else {
+        ctx.reply(format!(
+            "There is no playlist '{name}'. Use `/playlist list` to see saved ones"
+        ))
+        .await?;
+        return Ok(());
+    };
+    info!(
+        "{} requested to load playlist '{name}' with {} tracks",
+        ctx.author().name,
+        tracks.len()
+    );
+
+    let vc = join_vc(&ctx, guild_id, channel_id);
+    let _ = ctx
+        .reply(format!("Loading {} tracks from '{name}'...", tracks.len()))
+        .await;
+
+    // Resolve a few tracks at once to speed up loading, keeping the playlist order
+    let source_urls: Vec<String> = tracks
+        .iter()
+        .map(|track| track.source_url.clone())
+        .collect();
+    let resolved: Vec<_> = stream::iter(source_urls)
+        .map(|source_url| async move {
+            resolve_query(ctx.data(), guild_id, Some(ctx.author().id), &source_url).await
+        })
+        .buffered(PLAYLIST_LOAD_CONCURRENCY)
+        .collect()
+        .await;
+    let mut resolved_items = vec![];
+    let mut failed = vec![];
+    for (track, result) in tracks.iter().zip(resolved) {
+        match result {
+            Ok(items) => resolved_items.extend(items),
+            Err(_) => failed.push(track.title.as_str()),
+        }
+    }
+
+    let vc = vc.await??;
+    let mut vc = vc.lock().await;
+    let (_, not_added) = enqueue(&ctx, &mut vc, resolved_items).await;
+    let queue_info = form_currently_played(&vc.queue().current_queue()).await;
+    drop(vc);
+
+    let mut content = String::new();
+    if !failed.is_empty() {
+        let _ = write!(
+            content,
+            "Failed to load {} tracks from '{name}':",
+            failed.len()
+        );
+        for title in failed.iter().take(10) {
+            let _ = write!(content, "\n- {title}");
+        }
+        if failed.len() > 10 {
+            let _ = write!(content, "\n- and {} more", failed.len() - 10);
+        }
+    }
+    if !not_added.is_empty() {
+        if !content.is_empty() {
+            content.push('\n');
+        }
+        content.push_str(&list_reasons("Some tracks are not added:", &not_added));
+    }
+    let mut message = CreateMessage::default().embed(queue_info);
+    if !content.is_empty() {
+        message = message.content(content);
+    }
+    ctx.channel_id()
+        .send_message(ctx.serenity_context(), message)
+        .await?;
+    Ok(())
+}
+
+/// Show playlists saved in this server
+#[poise::command(guild_only, slash_command, rename = "list")]
+pub(crate) async fn playlist_list(ctx: Context<'_>) -> Result<(), anyhow::Error> {
+    let guild_id = ctx.guild().unwrap().id;
+    let playlists = ctx.data().playlist_storage.list(guild_id).await;
+
+    let embed = if playlists.is_empty() {
+        CreateEmbed::default()
+            .title("No saved playlists")
+            .description("Save the current queue with `/playlist save`")
+    } else {
+        let mut description = String::new();
+        for (name, tracks_count) in &playlists {
+            let _ = writeln!(description, "- {name} ({tracks_count} tracks)");
+        }
+        CreateEmbed::default()
+            .title("Saved playlists")
+            .description(description)
+    };
+    ctx.send(CreateReply::default().embed(embed)).await?;
+    Ok(())
+}
+
+/// Delete a saved playlist
+#[poise::command(
+    guild_only,
+    slash_command,
+    rename = "delete",
+    check = "permissions::dj_only"
+)]
+pub(crate) async fn playlist_delete(
+    ctx: Context<'_>,
+    #[description = "Playlist name"]
+    #[autocomplete = "autocomplete_playlist"]
+    name: String,
+) -> Result<(), anyhow::Error> {
+    let guild_id = ctx.guild().unwrap().id;
+    let name = name.trim();
+
+    let reply = if ctx.data().playlist_storage.delete(guild_id, name).await? {
+        format!("Deleted playlist '{name}'")
+    } else {
+        format!("There is no playlist '{name}'")
+    };
+    ctx.reply(reply).await?;
+    Ok(())
+}
+
+/// Suggests names of the guild's playlists that contain the typed text
+async fn autocomplete_playlist(ctx: Context<'_>, partial: &str) -> Vec<String> {
+    let Some(guild_id) = ctx.guild_id() else {
+        return vec![];
+    };
+    let partial = partial.to_lowercase();
+    ctx.data()
+        .playlist_storage
+        .list(guild_id)
+        .await
+        .into_iter()
+        .map(|(name, _)| name)
+        .filter(|name| name.to_lowercase().contains(&partial))
+        // Discord allows at most 25 choices
+        .take(25)
+        .collect()
+}
+
+/// Connect Spotify account to be used by bot in this server
+///
 /// https://www.spotify.com/us/account/set-device-password/
-#[poise::command(guild_only, slash_command)]
+#[poise::command(guild_only, slash_command, check = "permissions::dj_only")]
 #[cfg(feature = "spotify")]
 pub(crate) async fn connect_spotify(
     ctx: Context<'_>,
@@ -212,7 +1551,12 @@ pub(crate) async fn connect_spotify(
     let result = ctx
         .data()
         .spotify_resolver
-        .connect(guild_id, username, password)
+        .connect(
+            crate::spotify::CredentialsOwner::Guild(guild_id),
+            guild_id,
+            username,
+            password,
+        )
         .await;
     let reply = if let Err(err) = result {
         format!("Failed to connect Spotify account: {err:#}.")
@@ -226,6 +1570,144 @@ pub(crate) async fn connect_spotify(
     Ok(())
 }
 
+/// Spotify account and playback management
+#[poise::command(
+    guild_only,
+    slash_command,
+    subcommands("spotify_link", "spotify_unlink", "spotify_settings")
+)]
+#[cfg(feature = "spotify")]
+pub(crate) async fn spotify(_ctx: Context<'_>) -> Result<(), anyhow::Error> {
+    // Discord doesn't allow to invoke the parent of slash subcommands, so this is never called
+    Ok(())
+}
+
+/// Link your own Spotify account, used for tracks you request
+///
+/// https://www.spotify.com/us/account/set-device-password/
+#[poise::command(guild_only, slash_command, rename = "link")]
+#[cfg(feature = "spotify")]
+pub(crate) async fn spotify_link(
+    ctx: Context<'_>,
+    username: String,
+    password: String,
+) -> Result<(), anyhow::Error> {
+    let guild_id = ctx.guild().unwrap().id;
+
+    let result = ctx
+        .data()
+        .spotify_resolver
+        .connect(
+            crate::spotify::CredentialsOwner::User(ctx.author().id),
+            guild_id,
+            username,
+            password,
+        )
+        .await;
+    let reply = if let Err(err) = result {
+        format!("Failed to link Spotify account: {err:#}.")
+    } else {
+        "Spotify account linked successfully. It will be used for tracks you request.".into()
+    };
+
+    // Show reply only to user who invoked the command to avoid credentials leakage
+    ctx.send(CreateReply::default().content(reply).ephemeral(true))
+        .await?;
+    Ok(())
+}
+
+/// Unlink your Spotify account
+#[poise::command(guild_only, slash_command, rename = "unlink")]
+#[cfg(feature = "spotify")]
+pub(crate) async fn spotify_unlink(ctx: Context<'_>) -> Result<(), anyhow::Error> {
+    ctx.data().spotify_resolver.unlink(ctx.author().id).await?;
+
+    ctx.send(
+        CreateReply::default()
+            .content("Spotify account unlinked. Server's account will be used for your requests")
+            .ephemeral(true),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Show or change Spotify playback settings for this server
+#[poise::command(
+    guild_only,
+    slash_command,
+    rename = "settings",
+    required_permissions = "MANAGE_GUILD"
+)]
+#[cfg(feature = "spotify")]
+pub(crate) async fn spotify_settings(
+    ctx: Context<'_>,
+    #[description = "Audio quality of the Spotify stream"] bitrate: Option<crate::spotify::Bitrate>,
+    #[description = "Normalise volume across tracks"] normalisation: Option<bool>,
+    #[description = "Normalisation pregain in dB"]
+    #[min = -10.0]
+    #[max = 10.0]
+    pregain: Option<f64>,
+) -> Result<(), anyhow::Error> {
+    let guild_id = ctx.guild().unwrap().id;
+    let resolver = &ctx.data().spotify_resolver;
+
+    let mut settings = resolver.settings(guild_id).await;
+    let changed = bitrate.is_some() || normalisation.is_some() || pregain.is_some();
+    if changed {
+        settings.bitrate = bitrate.unwrap_or(settings.bitrate);
+        settings.normalisation = normalisation.unwrap_or(settings.normalisation);
+        settings.normalisation_pregain_db = pregain.unwrap_or(settings.normalisation_pregain_db);
+        resolver.update_settings(guild_id, settings).await?;
+    }
+
+    let mut embed = CreateEmbed::default()
+        .title("Spotify settings")
+        .field("Bitrate", format!("{} kbps", settings.bitrate.kbps()), true)
+        .field(
+            "Normalisation",
+            if settings.normalisation { "on" } else { "off" },
+            true,
+        )
+        .field(
+            "Pregain",
+            format!("{:+.1} dB", settings.normalisation_pregain_db),
+            true,
+        );
+    if changed {
+        embed = embed.description("Changes take effect after I rejoin the voice channel");
+    }
+    ctx.send(CreateReply::default().embed(embed)).await?;
+    Ok(())
+}
+
+/// Forms a user-friendly explanation why the Spotify query failed
+#[cfg(feature = "spotify")]
+fn spotify_error_reply(query: &str, err: &crate::spotify::Error) -> String {
+    use crate::spotify::Error;
+
+    match err {
+        Error::NotConnected => "No Spotify account is connected. \
+            Use `/spotify link` to link yours or `/connect_spotify` to connect one for the server"
+            .into(),
+        Error::AccountBusy => "Spotify account is already streaming in another server. \
+            Spotify allows only one stream per account, so link your own with `/spotify link`"
+            .into(),
+        Error::SessionExpired => "Spotify session has expired and couldn't be restored. \
+            Please reconnect the account with `/connect_spotify`"
+            .into(),
+        Error::LoginFailed => "Spotify rejected the account credentials, e.g. the password \
+            was changed. Please reconnect the account with `/spotify link` or `/connect_spotify`"
+            .into(),
+        Error::Private => format!("'{query}' is private and can't be played"),
+        Error::RegionLocked => format!("'{query}' is not available in the account's region"),
+        Error::NotFound => format!("'{query}' is not found on Spotify"),
+        Error::Unsupported => format!(
+            "'{query}' is not supported. Only Spotify tracks, albums and playlists can be played"
+        ),
+        Error::Other(_) => format!("Failed to fetch '{query}' from Spotify: {err}"),
+    }
+}
+
 async fn form_currently_played(tracks: &[songbird::tracks::TrackHandle]) -> CreateEmbed {
     let mut tracks = tracks.iter();
 
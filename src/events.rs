@@ -1,17 +1,26 @@
+use std::sync::Arc;
+
 use serenity::{
     client::Context,
     model::{
-        id::{ChannelId, GuildId},
+        id::{ChannelId, GuildId, UserId},
         voice::VoiceState,
     },
 };
-use tracing::info;
+#[cfg(feature = "spotify")]
+use songbird::tracks::Track;
+use songbird::tracks::TrackHandle;
+use songbird::{Event, TrackEvent};
+use tracing::{info, warn};
 
-use crate::Data;
+use crate::{
+    Data, autoplay, channels, follow, follow::VoiceStates, history, idle, now_playing, saved_queue,
+    track_info,
+};
 
 /// Invoked once, quickly after bot started, when the cache has received and inserted all data
 /// from guilds. Can be considered as an entry point for all preparations.
-pub(crate) async fn cache_ready(ctx: &Context, _data: &Data, guilds: &[GuildId]) {
+pub(crate) async fn cache_ready(ctx: &Context, data: &Arc<Data>, guilds: &[GuildId]) {
     let self_user_id = {
         let self_user = ctx.cache.current_user();
         info!("{} is connected!", self_user.name);
@@ -42,12 +51,136 @@ pub(crate) async fn cache_ready(ctx: &Context, _data: &Data, guilds: &[GuildId])
                 .await;
         }
     }
+
+    // Resume queues that were playing before the restart
+    for (guild_id, saved) in data.queue_storage.load_all().await {
+        if guilds.contains(&guild_id) {
+            tokio::spawn(saved_queue::restore(
+                ctx.clone(),
+                data.clone(),
+                guild_id,
+                saved,
+            ));
+        } else if let Err(err) = data.queue_storage.remove(guild_id).await {
+            warn!("Failed to remove the queue of {guild_id}: {err}");
+        }
+    }
+
+    #[cfg(feature = "spotify")]
+    tokio::spawn(spotify_watchdog(ctx.clone(), data.clone()));
+}
+
+/// Periodically checks Spotify sessions and restores ones dropped by Spotify, so the queue keeps playing
+#[cfg(feature = "spotify")]
+async fn spotify_watchdog(ctx: Context, data: Arc<Data>) {
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(30));
+    loop {
+        interval.tick().await;
+
+        for (guild_id, username) in data.spotify_resolver.invalidated_sessions().await {
+            warn!("Spotify session of '{username}' in {guild_id} was invalidated, reconnecting");
+
+            // Remember what was playing before the new session replaces the old one, interrupting the track
+            let interrupted = current_track(&ctx, guild_id).await;
+
+            match data.spotify_resolver.restore_session(&username).await {
+                Ok(()) => {
+                    if let Some(interrupted) = interrupted {
+                        reload_spotify_track(&ctx, &data, guild_id, &username, interrupted).await;
+                    }
+                }
+                Err(err) => {
+                    warn!("Failed to restore Spotify session of '{username}' in {guild_id}: {err}");
+                    notify_vc(
+                        &ctx,
+                        guild_id,
+                        format!(
+                            "Spotify session of '{username}' was lost and couldn't be restored: {err}. \
+                            Please reconnect the account with `/spotify link` or `/connect_spotify`"
+                        ),
+                    )
+                    .await;
+                }
+            }
+        }
+    }
+}
+
+/// Returns the track that is currently playing in the guild if any
+#[cfg(feature = "spotify")]
+async fn current_track(ctx: &Context, guild_id: GuildId) -> Option<TrackHandle> {
+    let vc = songbird::get(ctx)
+        .await
+        .expect("Songbird Voice client placed in at initialisation.")
+        .get(guild_id)?;
+    vc.lock().await.queue().current()
+}
+
+/// Restarts the interrupted Spotify track from the same position using the restored session
+#[cfg(feature = "spotify")]
+async fn reload_spotify_track(
+    ctx: &Context,
+    data: &Data,
+    guild_id: GuildId,
+    username: &str,
+    interrupted: TrackHandle,
+) {
+    let Ok(state) = interrupted.get_info().await else {
+        return; // The track has already finished
+    };
+    let track_info = interrupted.data::<track_info::TrackInfo>();
+    // Only the track played by this account is affected, the others resolve to `None` here
+    let Some(track) = data
+        .spotify_resolver
+        .reload(username, &track_info.metadata().source_url)
+        .await
+    else {
+        return;
+    };
+    let track = Track::new_with_data(track.starting_at(state.position).into(), track_info)
+        .volume(state.volume);
+
+    let Some(vc) = songbird::get(ctx)
+        .await
+        .expect("Songbird Voice client placed in at initialisation.")
+        .get(guild_id)
+    else {
+        return;
+    };
+    let mut vc = vc.lock().await;
+    // The track might have been skipped or removed while reloading, it shouldn't come back then
+    if vc
+        .queue()
+        .current()
+        .is_none_or(|current| current.uuid() != interrupted.uuid())
+    {
+        return;
+    }
+    vc.enqueue(track).await;
+    // Play the reloaded track right after the interrupted one
+    vc.queue().modify_queue(|queue| {
+        if queue.len() > 2 {
+            let reloaded = queue.pop_back().unwrap();
+            queue.insert(1, reloaded);
+        }
+    });
+    let _ = vc.queue().skip();
+    info!("Reloaded interrupted Spotify track in {guild_id}");
+}
+
+/// Posts a message to the text chat of the bot's voice channel
+pub(crate) async fn notify_vc(ctx: &Context, guild_id: GuildId, message: String) {
+    if let Some(channel_id) = bot_channel(ctx, guild_id)
+        && let Err(err) = channel_id.say(&ctx.http, message).await
+    {
+        warn!("Failed to send a message to {channel_id}: {err}");
+    }
 }
 
 /// Invoked when a user joins, leaves or moves to a voice channel.
 pub(crate) async fn voice_state_update(
     ctx: &Context,
-    data: &Data,
+    data: &Arc<Data>,
     old: &Option<VoiceState>,
     new: &VoiceState,
 ) {
@@ -72,7 +205,15 @@ pub(crate) async fn voice_state_update(
             if is_bot {
                 bot_changed_vc(ctx, data, new_guild_id, old_channel_id, new_channel_id).await;
             } else {
-                user_changed_vc(ctx, data, new_guild_id, old_channel_id, new_channel_id).await;
+                user_changed_vc(
+                    ctx,
+                    data,
+                    new_guild_id,
+                    new.user_id,
+                    old_channel_id,
+                    new_channel_id,
+                )
+                .await;
             }
         }
         // Left voice channel
@@ -89,116 +230,244 @@ pub(crate) async fn voice_state_update(
 }
 
 /// Invoked when bot joined a new voice channel
-async fn bot_joined_vc(ctx: &Context, _data: &Data, guild_id: GuildId, channel_id: ChannelId) {
-    info!(
-        "Joined '{}' vc in '{}' guild",
-        &ctx.cache.channel(channel_id).unwrap().name,
-        &ctx.cache.guild(guild_id).unwrap().name,
-    );
+async fn bot_joined_vc(ctx: &Context, data: &Arc<Data>, guild_id: GuildId, channel_id: ChannelId) {
+    setup_vc(ctx, data, guild_id).await;
 
-    setup_vc(ctx, guild_id).await;
+    let Some(guild) = ctx.cache.guild(guild_id) else {
+        return;
+    };
+    let Some(channel) = guild.channels.get(&channel_id) else {
+        return;
+    };
+    info!("Joined '{}' vc in '{}' guild", channel.name, guild.name);
 }
 
 /// Invoked when bot changed voice channel either because someone moved it or it moved itself
 async fn bot_changed_vc(
     ctx: &Context,
-    _data: &Data,
+    data: &Arc<Data>,
     guild_id: GuildId,
     from: ChannelId,
     to: ChannelId,
 ) {
+    setup_vc(ctx, data, guild_id).await;
+
+    let Some(guild) = ctx.cache.guild(guild_id) else {
+        return;
+    };
+    let (Some(from), Some(to)) = (guild.channels.get(&from), guild.channels.get(&to)) else {
+        return;
+    };
     info!(
         "Moved from '{}' vc to '{}' vc in '{}' guild",
-        &ctx.cache.channel(from).unwrap().name,
-        &ctx.cache.channel(to).unwrap().name,
-        &ctx.cache.guild(guild_id).unwrap().name,
+        from.name, to.name, guild.name,
     );
-
-    setup_vc(ctx, guild_id).await;
 }
 
 /// Invoked when bot left voice channel
-async fn bot_left_vc(ctx: &Context, _data: &Data, guild_id: GuildId) {
+async fn bot_left_vc(ctx: &Context, data: &Data, guild_id: GuildId) {
     info!(
         "Left voice chat in '{}' guild",
         &ctx.cache.guild(guild_id).unwrap().name
     );
 
+    data.idle.cancel_all(guild_id);
+    data.now_playing.clear(ctx, guild_id).await;
+
+    // The queue is gone together with the call, so there is nothing to restore anymore
+    if let Err(err) = data.queue_storage.remove(guild_id).await {
+        warn!("Failed to remove the queue of {guild_id}: {err}");
+    }
+
     #[cfg(feature = "spotify")]
-    _data.spotify_resolver.disconnect(guild_id).await;
+    data.spotify_resolver.disconnect(guild_id).await;
 }
 
 /// Invoked when user joined a voice channel
-async fn user_joined_vc(_ctx: &Context, _data: &Data, _guild_id: GuildId, _channel_id: ChannelId) {
-    // let users_in_vc = {
-    //     let guild = ctx.cache.guild(guild_id).unwrap();
-    //     guild.voice_states.len()
-    // };
+async fn user_joined_vc(ctx: &Context, data: &Data, guild_id: GuildId, channel_id: ChannelId) {
+    if bot_channel(ctx, guild_id) == Some(channel_id) {
+        data.idle.listener_returned(ctx, guild_id).await;
+    }
 }
 
 /// Invoked when user changed voice channel
 async fn user_changed_vc(
     ctx: &Context,
-    _data: &Data,
+    data: &Arc<Data>,
     guild_id: GuildId,
-    _from: ChannelId,
+    user_id: UserId,
+    from: ChannelId,
     to: ChannelId,
 ) {
-    // If bot left alone in the voice channel, then we should follow the user to the new channel
-    if bot_left_alone(ctx, guild_id) {
-        info!("Bot left alone, following the user to the new vc");
-        let _ = songbird::get(ctx)
-            .await
-            .expect("Songbird Voice client placed in at initialisation.")
-            .join(guild_id, to)
-            .await;
+    if bot_channel(ctx, guild_id) == Some(to) {
+        data.idle.listener_returned(ctx, guild_id).await;
     }
+
+    let settings = data.settings.get(guild_id);
+    let songbird = songbird::get(ctx)
+        .await
+        .expect("Songbird Voice client placed in at initialisation.");
+    let Some(vc) = songbird.get(guild_id) else {
+        return;
+    };
+    let latest_requester = if settings.follow_requester {
+        latest_requester(&vc.lock().await.queue().current_queue())
+    } else {
+        None
+    };
+    let (target, left_alone) = {
+        let Some(guild) = ctx.cache.guild(guild_id) else {
+            return;
+        };
+        let bot_id = ctx.cache.current_user().id;
+        let target = follow::follow_target(
+            &*guild,
+            bot_id,
+            user_id,
+            (from, to),
+            latest_requester,
+            |channel_id| {
+                data.allowed_channels
+                    .is_allowed(guild_id, channels::ChannelKind::Voice, channel_id)
+            },
+        );
+        (target, follow::left_alone(&*guild, bot_id, from))
+    };
+    if let Some(channel_id) = target {
+        info!("Bot left alone, following the latest requester to {channel_id}");
+        if let Err(err) = songbird.join(guild_id, channel_id).await {
+            warn!("Failed to follow the latest requester to {channel_id}: {err}");
+        }
+    } else if settings.leave_when_alone && left_alone {
+        // Moving to another channel leaves the bot alone just like disconnecting does
+        info!("Bot left alone, leaving the vc once idle timeout is over");
+        data.idle.everyone_left(ctx, data, guild_id).await;
+    }
+}
+
+/// Returns the user who requested the last track of the queue, skipping autoplayed ones
+fn latest_requester(queue: &[TrackHandle]) -> Option<UserId> {
+    queue.iter().rev().find_map(
+        |track| match track.data::<track_info::TrackInfo>().added_by() {
+            track_info::Requester::User(user_id) => Some(user_id),
+            track_info::Requester::Autoplay => None,
+        },
+    )
 }
 
 /// Invoked when user left a voice channel
-async fn user_left_vc(ctx: &Context, _data: &Data, guild_id: GuildId) {
+async fn user_left_vc(ctx: &Context, data: &Arc<Data>, guild_id: GuildId) {
     // Check if bot should leave voice channel when everyone left
-    if bot_left_alone(ctx, guild_id) {
-        info!("Bot left alone, leaving the vc");
-        let _ = songbird::get(ctx)
-            .await
-            .expect("Songbird Voice client placed in at initialisation.")
-            .remove(guild_id)
-            .await;
+    if data.settings.get(guild_id).leave_when_alone && bot_left_alone(ctx, guild_id) {
+        info!("Bot left alone, leaving the vc once idle timeout is over");
+        data.idle.everyone_left(ctx, data, guild_id).await;
     }
 }
 
-async fn setup_vc(ctx: &Context, guild_id: GuildId) {
-    if let Some(vc) = songbird::get(ctx)
+async fn setup_vc(ctx: &Context, data: &Arc<Data>, guild_id: GuildId) {
+    let songbird = songbird::get(ctx)
         .await
-        .expect("Songbird Voice client placed in at initialisation.")
-        .get(guild_id)
-    {
+        .expect("Songbird Voice client placed in at initialisation.");
+    let Some(vc) = songbird.get(guild_id) else {
+        return;
+    };
+    let (queue_is_empty, current) = {
         let mut vc = vc.lock().await;
-        // 96k is a default Discord bitrate in guilds without nitro so no need to send more data
-        vc.set_bitrate(songbird::driver::Bitrate::BitsPerSecond(96_000));
+        vc.set_bitrate(data.settings.get(guild_id).voice_bitrate());
+
+        // The same call is reused when bot moves between channels, so avoid registering handlers twice
+        vc.remove_all_global_events();
+        vc.add_global_event(
+            Event::Track(TrackEvent::End),
+            autoplay::QueueEndHandler::new(data.clone(), songbird.clone(), guild_id),
+        );
+        vc.add_global_event(
+            Event::Track(TrackEvent::Play),
+            history::TrackHandler::new(data.clone(), guild_id),
+        );
+        vc.add_global_event(
+            Event::Track(TrackEvent::End),
+            history::TrackHandler::new(data.clone(), guild_id),
+        );
+        for event in [
+            Event::Track(TrackEvent::Play),
+            Event::Track(TrackEvent::End),
+            Event::Periodic(saved_queue::SNAPSHOT_INTERVAL, None),
+        ] {
+            vc.add_global_event(
+                event,
+                saved_queue::SnapshotHandler::new(data.clone(), songbird.clone(), guild_id),
+            );
+        }
+        for event in [
+            Event::Track(TrackEvent::Play),
+            Event::Track(TrackEvent::End),
+        ] {
+            vc.add_global_event(
+                event,
+                idle::QueueHandler::new(ctx.clone(), data.clone(), guild_id),
+            );
+            vc.add_global_event(
+                event,
+                now_playing::TrackHandler::new(
+                    ctx.clone(),
+                    data.clone(),
+                    songbird.clone(),
+                    guild_id,
+                ),
+            );
+        }
+
+        // Moving to another channel keeps the track playing, so it is shown in the new channel
+        let current = vc
+            .current_channel()
+            .zip(vc.queue().current())
+            .map(|(channel_id, track)| {
+                let title = track
+                    .data::<track_info::TrackInfo>()
+                    .metadata()
+                    .title
+                    .clone();
+                (ChannelId::new(channel_id.0.get()), title)
+            });
+        (vc.queue().is_empty(), current)
+    };
+
+    if let Some((channel_id, title)) = current {
+        data.now_playing
+            .show(ctx, guild_id, channel_id, &title)
+            .await;
     }
-}
 
-fn bot_left_alone(ctx: &Context, guild_id: GuildId) -> bool {
-    let guild = ctx.cache.guild(guild_id).unwrap();
+    // The bot might have joined or been moved to a channel where it has nothing to do
+    if queue_is_empty {
+        data.idle
+            .start(ctx, data, guild_id, idle::Reason::QueueEmpty);
+    }
+    if data.settings.get(guild_id).leave_when_alone && bot_left_alone(ctx, guild_id) {
+        data.idle.everyone_left(ctx, data, guild_id).await;
+    } else {
+        data.idle.listener_returned(ctx, guild_id).await;
+    }
+}
 
-    let bot_channel = guild
+/// Returns the voice channel the bot is in
+pub(crate) fn bot_channel(ctx: &Context, guild_id: GuildId) -> Option<ChannelId> {
+    ctx.cache
+        .guild(guild_id)?
         .voice_states
         .get(&ctx.cache.current_user().id)
-        .and_then(|voice_state| voice_state.channel_id);
+        .and_then(|voice_state| voice_state.channel_id)
+}
 
-    guild
-        .voice_states
-        .values()
-        .filter(|voice_state| voice_state.channel_id == bot_channel)
-        // Ignore non-members and bots
-        .filter(|voice_state| {
-            voice_state
-                .member
-                .as_ref()
-                .is_some_and(|member| !member.user.bot)
-        })
-        .count()
-        == 0
+pub(crate) fn bot_left_alone(ctx: &Context, guild_id: GuildId) -> bool {
+    listeners(ctx, guild_id).is_empty()
+}
+
+/// Returns members who are in the bot's voice channel, except bots
+pub(crate) fn listeners(ctx: &Context, guild_id: GuildId) -> Vec<UserId> {
+    let Some(bot_channel) = bot_channel(ctx, guild_id) else {
+        return vec![];
+    };
+    ctx.cache.guild(guild_id).unwrap().listeners_in(bot_channel)
 }
@@ -0,0 +1,207 @@
+use serenity::model::{
+    guild::Guild,
+    id::{ChannelId, UserId},
+};
+
+/// Who is in which voice channel of a guild
+pub(crate) trait VoiceStates {
+    /// Returns the voice channel the user is in
+    fn channel_of(&self, user_id: UserId) -> Option<ChannelId>;
+    /// Returns members in the voice channel, except bots
+    fn listeners_in(&self, channel_id: ChannelId) -> Vec<UserId>;
+}
+
+impl VoiceStates for Guild {
+    fn channel_of(&self, user_id: UserId) -> Option<ChannelId> {
+        self.voice_states.get(&user_id)?.channel_id
+    }
+
+    fn listeners_in(&self, channel_id: ChannelId) -> Vec<UserId> {
+        self.voice_states
+            .values()
+            .filter(|voice_state| voice_state.channel_id == Some(channel_id))
+            // Ignore non-members and bots
+            .filter(|voice_state| {
+                voice_state
+                    .member
+                    .as_ref()
+                    .is_some_and(|member| !member.user.bot)
+            })
+            .map(|voice_state| voice_state.user_id)
+            .collect()
+    }
+}
+
+/// Decides whether the bot should follow the user who moved from one voice channel to another.
+/// It follows only the latest requester who left it alone, and only to an allowed channel.
+/// `voice_states` are expected to be updated with the move already
+pub(crate) fn follow_target(
+    voice_states: &impl VoiceStates,
+    bot_id: UserId,
+    moved: UserId,
+    (from, to): (ChannelId, ChannelId),
+    latest_requester: Option<UserId>,
+    allowed: impl Fn(ChannelId) -> bool,
+) -> Option<ChannelId> {
+    if latest_requester != Some(moved) || from == to {
+        return None;
+    }
+    (left_alone(voice_states, bot_id, from) && allowed(to)).then_some(to)
+}
+
+/// Returns whether the user who moved away from the channel left the bot without listeners.
+/// Moves between other channels don't concern the bot.
+/// `voice_states` are expected to be updated with the move already
+pub(crate) fn left_alone(voice_states: &impl VoiceStates, bot_id: UserId, from: ChannelId) -> bool {
+    voice_states.channel_of(bot_id) == Some(from) && voice_states.listeners_in(from).is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    const BOT: UserId = UserId::new(1);
+    const ANOTHER_BOT: UserId = UserId::new(2);
+    const ALICE: UserId = UserId::new(101);
+    const BOB: UserId = UserId::new(102);
+    const MUSIC: ChannelId = ChannelId::new(11);
+    const LOUNGE: ChannelId = ChannelId::new(12);
+    const AFK: ChannelId = ChannelId::new(13);
+
+    /// Voice channels of users without the Discord cache behind them
+    #[derive(Default)]
+    struct FakeVoiceStates {
+        channels: HashMap<UserId, ChannelId>,
+    }
+
+    impl FakeVoiceStates {
+        fn new(channels: &[(UserId, ChannelId)]) -> Self {
+            Self {
+                channels: channels.iter().copied().collect(),
+            }
+        }
+
+        /// Moves the user to another channel, returning where they moved from and to
+        fn move_user(&mut self, user_id: UserId, to: ChannelId) -> (ChannelId, ChannelId) {
+            let from = self.channels.insert(user_id, to).unwrap();
+            (from, to)
+        }
+    }
+
+    impl VoiceStates for FakeVoiceStates {
+        fn channel_of(&self, user_id: UserId) -> Option<ChannelId> {
+            self.channels.get(&user_id).copied()
+        }
+
+        fn listeners_in(&self, channel_id: ChannelId) -> Vec<UserId> {
+            self.channels
+                .iter()
+                .filter(|&(&user_id, &channel)| {
+                    channel == channel_id && ![BOT, ANOTHER_BOT].contains(&user_id)
+                })
+                .map(|(&user_id, _)| user_id)
+                .collect()
+        }
+    }
+
+    fn any_channel(_: ChannelId) -> bool {
+        true
+    }
+
+    #[test]
+    fn follows_latest_requester() {
+        let mut voice_states = FakeVoiceStates::new(&[(BOT, MUSIC), (ALICE, MUSIC)]);
+        let moved = voice_states.move_user(ALICE, LOUNGE);
+        assert_eq!(
+            follow_target(&voice_states, BOT, ALICE, moved, Some(ALICE), any_channel),
+            Some(LOUNGE)
+        );
+        // Other bots don't count as listeners
+        let mut voice_states =
+            FakeVoiceStates::new(&[(BOT, MUSIC), (ANOTHER_BOT, MUSIC), (ALICE, MUSIC)]);
+        let moved = voice_states.move_user(ALICE, LOUNGE);
+        assert_eq!(
+            follow_target(&voice_states, BOT, ALICE, moved, Some(ALICE), any_channel),
+            Some(LOUNGE)
+        );
+    }
+
+    #[test]
+    fn follows_only_latest_requester() {
+        let mut voice_states = FakeVoiceStates::new(&[(BOT, MUSIC), (BOB, MUSIC)]);
+        let moved = voice_states.move_user(BOB, LOUNGE);
+        assert_eq!(
+            follow_target(&voice_states, BOT, BOB, moved, Some(ALICE), any_channel),
+            None
+        );
+        // Nobody is followed if nothing was requested
+        assert_eq!(
+            follow_target(&voice_states, BOT, BOB, moved, None, any_channel),
+            None
+        );
+    }
+
+    #[test]
+    fn stays_with_listeners() {
+        let mut voice_states = FakeVoiceStates::new(&[(BOT, MUSIC), (ALICE, MUSIC), (BOB, MUSIC)]);
+        let moved = voice_states.move_user(ALICE, LOUNGE);
+        assert_eq!(
+            follow_target(&voice_states, BOT, ALICE, moved, Some(ALICE), any_channel),
+            None
+        );
+    }
+
+    #[test]
+    fn ignores_moves_elsewhere() {
+        // The requester wasn't listening to the bot
+        let mut voice_states = FakeVoiceStates::new(&[(BOT, MUSIC), (ALICE, LOUNGE)]);
+        let moved = voice_states.move_user(ALICE, AFK);
+        assert_eq!(
+            follow_target(&voice_states, BOT, ALICE, moved, Some(ALICE), any_channel),
+            None
+        );
+        // The requester joined the bot
+        let mut voice_states = FakeVoiceStates::new(&[(BOT, MUSIC), (ALICE, LOUNGE)]);
+        let moved = voice_states.move_user(ALICE, MUSIC);
+        assert_eq!(
+            follow_target(&voice_states, BOT, ALICE, moved, Some(ALICE), any_channel),
+            None
+        );
+        // The bot isn't in a voice channel
+        let mut voice_states = FakeVoiceStates::new(&[(ALICE, MUSIC)]);
+        let moved = voice_states.move_user(ALICE, LOUNGE);
+        assert_eq!(
+            follow_target(&voice_states, BOT, ALICE, moved, Some(ALICE), any_channel),
+            None
+        );
+    }
+
+    #[test]
+    fn left_alone_test() {
+        // The last listener moved away
+        let mut voice_states = FakeVoiceStates::new(&[(BOT, MUSIC), (BOB, MUSIC)]);
+        let (from, _) = voice_states.move_user(BOB, LOUNGE);
+        assert!(left_alone(&voice_states, BOT, from));
+        // Someone is still listening
+        let mut voice_states = FakeVoiceStates::new(&[(BOT, MUSIC), (ALICE, MUSIC), (BOB, MUSIC)]);
+        let (from, _) = voice_states.move_user(BOB, LOUNGE);
+        assert!(!left_alone(&voice_states, BOT, from));
+        // The move was between other channels
+        let mut voice_states = FakeVoiceStates::new(&[(BOT, MUSIC), (BOB, LOUNGE)]);
+        let (from, _) = voice_states.move_user(BOB, AFK);
+        assert!(!left_alone(&voice_states, BOT, from));
+    }
+
+    #[test]
+    fn respects_channel_restrictions() {
+        let mut voice_states = FakeVoiceStates::new(&[(BOT, MUSIC), (ALICE, MUSIC)]);
+        let moved = voice_states.move_user(ALICE, AFK);
+        assert_eq!(
+            follow_target(&voice_states, BOT, ALICE, moved, Some(ALICE), |channel| {
+                channel != AFK
+            }),
+            None
+        );
+    }
+}
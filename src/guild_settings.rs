@@ -0,0 +1,210 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serenity::model::id::{GuildId, RoleId};
+
+/// Lowest and highest voice bitrates in kbps. Discord allows up to 96 kbps in guilds without boosts
+pub(crate) const BITRATE_RANGE_KBPS: (u32, u32) = (8, 384);
+/// Longest idle timeout in minutes, so the bot doesn't occupy the voice channel for days
+pub(crate) const MAX_IDLE_TIMEOUT_MINUTES: u32 = 24 * 60;
+/// Highest limits of tracks per user and per playlist
+pub(crate) const MAX_TRACKS_LIMIT: u32 = 1000;
+/// Highest limit of track duration in minutes
+pub(crate) const MAX_TRACK_MINUTES_LIMIT: u32 = 24 * 60;
+/// Longest cooldown between `/play` commands of the same user in seconds
+pub(crate) const MAX_PLAY_COOLDOWN_SECS: u32 = 60 * 60;
+
+/// Per-guild bot settings, changeable via `/settings`
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct GuildSettings {
+    /// Volume of newly added tracks in percent
+    pub(crate) volume: u8,
+    /// Bitrate of the voice stream in kbps
+    pub(crate) bitrate_kbps: u32,
+    /// Whether the queue should be continued with similar tracks once it runs dry
+    pub(crate) autoplay: bool,
+    /// Whether the bot should pause and leave the voice channel once everyone else has left it.
+    /// It leaves only after the idle timeout and resumes the track if someone returns before that
+    pub(crate) leave_when_alone: bool,
+    /// How long the bot stays with an empty queue or alone before leaving the voice channel
+    pub(crate) idle_timeout_minutes: u32,
+    /// Whether shortcuts like `rt` should play Radio-T instead of being searched on YouTube
+    pub(crate) radio_t: bool,
+    /// Role allowed to stop the bot and skip tracks requested by others.
+    /// Everyone is allowed to if there is no such role
+    pub(crate) dj_role: Option<RoleId>,
+    /// Percent of listeners who should vote to skip a track requested by someone else.
+    /// 0 disables voting, so such tracks are skipped right away by those allowed to
+    pub(crate) vote_skip_percent: u8,
+    /// How many tracks a user may have in the queue at once. 0 means no limit
+    pub(crate) user_tracks_limit: u32,
+    /// How long a requested track may be in minutes. 0 means no limit
+    pub(crate) track_minutes_limit: u32,
+    /// How many tracks of a requested playlist are added at most. 0 means no limit
+    pub(crate) playlist_tracks_limit: u32,
+    /// How long a user should wait between `/play` commands in seconds. 0 means no cooldown
+    pub(crate) play_cooldown_secs: u32,
+    /// Whether the bot left alone should follow the latest requester to their new voice channel
+    pub(crate) follow_requester: bool,
+}
+
+impl Default for GuildSettings {
+    fn default() -> Self {
+        Self {
+            // Reduce volume to 50% to avoid ear damage for new users
+            volume: 50,
+            // 96k is a default Discord bitrate in guilds without nitro so no need to send more data
+            bitrate_kbps: 96,
+            autoplay: false,
+            leave_when_alone: true,
+            idle_timeout_minutes: 5,
+            radio_t: true,
+            dj_role: None,
+            vote_skip_percent: 0,
+            user_tracks_limit: 0,
+            track_minutes_limit: 0,
+            playlist_tracks_limit: 0,
+            play_cooldown_secs: 0,
+            follow_requester: false,
+        }
+    }
+}
+
+impl GuildSettings {
+    /// Returns volume of newly added tracks as songbird expects it, i.e. from 0 to 1
+    pub(crate) fn track_volume(&self) -> f32 {
+        f32::from(self.volume) / 100.0
+    }
+
+    /// Returns bitrate of the voice stream as songbird expects it
+    pub(crate) fn voice_bitrate(&self) -> songbird::driver::Bitrate {
+        songbird::driver::Bitrate::BitsPerSecond(self.bitrate_kbps as i32 * 1000)
+    }
+
+    pub(crate) fn idle_timeout(&self) -> Duration {
+        Duration::from_secs(u64::from(self.idle_timeout_minutes) * 60)
+    }
+
+    /// Checks that all values are within their allowed ranges
+    pub(crate) fn is_valid(&self) -> bool {
+        (1..=100).contains(&self.volume)
+            && (BITRATE_RANGE_KBPS.0..=BITRATE_RANGE_KBPS.1).contains(&self.bitrate_kbps)
+            && (1..=MAX_IDLE_TIMEOUT_MINUTES).contains(&self.idle_timeout_minutes)
+            && self.vote_skip_percent <= 100
+            && self.user_tracks_limit <= MAX_TRACKS_LIMIT
+            && self.track_minutes_limit <= MAX_TRACK_MINUTES_LIMIT
+            && self.playlist_tracks_limit <= MAX_TRACKS_LIMIT
+            && self.play_cooldown_secs <= MAX_PLAY_COOLDOWN_SECS
+    }
+}
+
+/// An interface for storing and retrieving settings of guilds
+#[async_trait]
+pub(crate) trait GuildSettingsStorage: Send + Sync {
+    /// Saves the guild's settings, replacing the previous ones
+    async fn save(&self, guild_id: GuildId, settings: &GuildSettings) -> Result<(), anyhow::Error>;
+    /// Loads settings of all guilds that have changed them
+    async fn load_all(&self) -> Vec<(GuildId, GuildSettings)>;
+}
+
+/// Settings of all guilds, kept in memory to be available in event handlers without hitting the storage
+pub(crate) struct Settings {
+    storage: Arc<dyn GuildSettingsStorage>,
+    guilds: RwLock<HashMap<GuildId, GuildSettings>>,
+}
+
+impl Settings {
+    /// Loads settings of all guilds from the storage
+    pub(crate) async fn load(storage: Arc<dyn GuildSettingsStorage>) -> Self {
+        let guilds = storage.load_all().await.into_iter().collect();
+        Self {
+            storage,
+            guilds: RwLock::new(guilds),
+        }
+    }
+
+    /// Returns the guild's settings, falling back to the default ones
+    pub(crate) fn get(&self, guild_id: GuildId) -> GuildSettings {
+        self.guilds
+            .read()
+            .unwrap()
+            .get(&guild_id)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Applies the change to the guild's settings and saves them. Returns the updated settings
+    pub(crate) async fn update(
+        &self,
+        guild_id: GuildId,
+        change: impl FnOnce(&mut GuildSettings),
+    ) -> Result<GuildSettings, anyhow::Error> {
+        let mut settings = self.get(guild_id);
+        change(&mut settings);
+        anyhow::ensure!(settings.is_valid(), "Invalid settings {settings:?}");
+
+        self.storage.save(guild_id, &settings).await?;
+        self.guilds.write().unwrap().insert(guild_id, settings);
+        Ok(settings)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct MemoryStorage {
+        guilds: Mutex<HashMap<GuildId, GuildSettings>>,
+    }
+
+    #[async_trait]
+    impl GuildSettingsStorage for MemoryStorage {
+        async fn save(
+            &self,
+            guild_id: GuildId,
+            settings: &GuildSettings,
+        ) -> Result<(), anyhow::Error> {
+            self.guilds.lock().unwrap().insert(guild_id, *settings);
+            Ok(())
+        }
+
+        async fn load_all(&self) -> Vec<(GuildId, GuildSettings)> {
+            self.guilds.lock().unwrap().clone().into_iter().collect()
+        }
+    }
+
+    #[tokio::test]
+    async fn settings_update() {
+        let storage = Arc::new(MemoryStorage::default());
+        let settings = Settings::load(storage.clone()).await;
+        let guild_id = GuildId::new(101);
+        assert_eq!(settings.get(guild_id), GuildSettings::default());
+
+        let updated = settings
+            .update(guild_id, |settings| settings.autoplay = true)
+            .await
+            .unwrap();
+        assert!(updated.autoplay);
+        assert_eq!(settings.get(guild_id), updated);
+        assert_eq!(settings.get(GuildId::new(202)), GuildSettings::default());
+
+        // Invalid values are neither applied nor saved
+        assert!(
+            settings
+                .update(guild_id, |settings| settings.volume = 0)
+                .await
+                .is_err()
+        );
+        assert_eq!(settings.get(guild_id), updated);
+
+        // Saved settings are loaded on start
+        let settings = Settings::load(storage).await;
+        assert_eq!(settings.get(guild_id), updated);
+    }
+}
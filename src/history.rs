@@ -0,0 +1,223 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use serenity::model::id::{GuildId, UserId};
+use songbird::{Event, EventContext, EventHandler, tracks::PlayMode};
+use tracing::warn;
+
+use crate::{Data, track_info};
+
+/// A track played in the guild
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct HistoryEntry {
+    pub(crate) source_url: String,
+    pub(crate) title: String,
+    /// Mention of the user who requested the track or `autoplay`.
+    /// Tracks played before requesters were stored by id have user names instead
+    pub(crate) requested_by: String,
+    /// Unix timestamp in seconds when the track started playing
+    pub(crate) started_at: u64,
+    /// How long the track was actually played. `None` if it is still playing or the bot was stopped
+    pub(crate) played: Option<Duration>,
+}
+
+/// An interface for storing and retrieving play history of guilds
+#[async_trait]
+pub(crate) trait HistoryStorage: Send + Sync {
+    /// Records the started track, returning the id of the record
+    async fn record_start(
+        &self,
+        guild_id: GuildId,
+        metadata: &track_info::Metadata,
+        requested_by: track_info::Requester,
+        started_at: u64,
+    ) -> Result<i64, anyhow::Error>;
+    /// Updates the record with how long the track was actually played and whether it was skipped
+    async fn record_end(
+        &self,
+        id: i64,
+        played: Duration,
+        skipped: bool,
+    ) -> Result<(), anyhow::Error>;
+    /// Returns up to `limit` recently played tracks in the guild, the most recent first,
+    /// optionally only ones requested by the provided user
+    async fn recent(
+        &self,
+        guild_id: GuildId,
+        requested_by: Option<UserId>,
+        limit: usize,
+    ) -> Vec<HistoryEntry>;
+    /// Aggregates listening statistics of the guild for tracks started since the provided unix timestamp.
+    /// Leaderboards contain up to `limit` entries
+    async fn stats(&self, guild_id: GuildId, since: u64, limit: usize) -> Stats;
+}
+
+/// How many times the track was played or skipped
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct TrackCount {
+    pub(crate) source_url: String,
+    pub(crate) title: String,
+    pub(crate) count: usize,
+}
+
+/// Listening statistics of the guild over some period
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct Stats {
+    /// The most played tracks
+    pub(crate) top_tracks: Vec<TrackCount>,
+    /// Users who requested the most tracks, formatted as [`HistoryEntry::requested_by`],
+    /// with the number of requested tracks
+    pub(crate) top_requesters: Vec<(String, usize)>,
+    /// The most skipped tracks
+    pub(crate) most_skipped: Vec<TrackCount>,
+    /// Total time the tracks were played
+    pub(crate) listened: Duration,
+}
+
+/// Period to aggregate statistics over
+#[derive(Clone, Copy, Debug, Default, PartialEq, poise::ChoiceParameter)]
+pub(crate) enum Period {
+    #[name = "Today"]
+    Day,
+    #[default]
+    #[name = "This week"]
+    Week,
+    #[name = "This month"]
+    Month,
+    #[name = "All time"]
+    AllTime,
+}
+
+impl Period {
+    /// Returns unix timestamp in seconds of the period start, relative to the provided timestamp
+    pub(crate) fn since(self, now: u64) -> u64 {
+        const DAY: u64 = 24 * 60 * 60;
+        match self {
+            Self::Day => now.saturating_sub(DAY),
+            Self::Week => now.saturating_sub(7 * DAY),
+            Self::Month => now.saturating_sub(30 * DAY),
+            Self::AllTime => 0,
+        }
+    }
+}
+
+/// Returns current unix timestamp in seconds
+pub(crate) fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Play history of all guilds
+pub(crate) struct History {
+    storage: Arc<dyn HistoryStorage>,
+    /// Records of tracks that are playing now, by track handle UUID
+    playing: Mutex<HashMap<u128, i64>>,
+}
+
+impl History {
+    pub(crate) fn new(storage: Arc<dyn HistoryStorage>) -> Self {
+        Self {
+            storage,
+            playing: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns up to `limit` recently played tracks in the guild, the most recent first
+    pub(crate) async fn recent(
+        &self,
+        guild_id: GuildId,
+        requested_by: Option<UserId>,
+        limit: usize,
+    ) -> Vec<HistoryEntry> {
+        self.storage.recent(guild_id, requested_by, limit).await
+    }
+
+    /// Aggregates listening statistics of the guild over the period
+    pub(crate) async fn stats(&self, guild_id: GuildId, period: Period, limit: usize) -> Stats {
+        let since = period.since(unix_now());
+        self.storage.stats(guild_id, since, limit).await
+    }
+}
+
+/// Songbird event handler that records track starts and ends to the play history
+pub(crate) struct TrackHandler {
+    data: Arc<Data>,
+    guild_id: GuildId,
+}
+
+impl TrackHandler {
+    pub(crate) fn new(data: Arc<Data>, guild_id: GuildId) -> Self {
+        Self { data, guild_id }
+    }
+}
+
+#[async_trait]
+impl EventHandler for TrackHandler {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        let EventContext::Track(tracks) = ctx else {
+            return None;
+        };
+        let history = &self.data.history;
+
+        for (state, handle) in tracks.iter() {
+            let uuid = handle.uuid().as_u128();
+            if let PlayMode::Play = state.playing {
+                // Resuming after pause emits the same event, but it's still the same play
+                if history.playing.lock().unwrap().contains_key(&uuid) {
+                    continue;
+                }
+
+                let track_info = handle.data::<track_info::TrackInfo>();
+                let started_at = unix_now().saturating_sub(state.play_time.as_secs());
+                match history
+                    .storage
+                    .record_start(
+                        self.guild_id,
+                        track_info.metadata(),
+                        track_info.added_by(),
+                        started_at,
+                    )
+                    .await
+                {
+                    Ok(id) => {
+                        history.playing.lock().unwrap().insert(uuid, id);
+                    }
+                    Err(err) => warn!("Failed to record track start in {}: {err}", self.guild_id),
+                }
+            } else if state.playing.is_done() {
+                let id = history.playing.lock().unwrap().remove(&uuid);
+                let skipped = handle.data::<track_info::TrackInfo>().skipped();
+                if let Some(id) = id
+                    && let Err(err) = history
+                        .storage
+                        .record_end(id, state.play_time, skipped)
+                        .await
+                {
+                    warn!("Failed to record track end in {}: {err}", self.guild_id);
+                }
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn period_since() {
+        let now = 100 * 24 * 60 * 60;
+        assert_eq!(Period::Day.since(now), 99 * 24 * 60 * 60);
+        assert_eq!(Period::Week.since(now), 93 * 24 * 60 * 60);
+        assert_eq!(Period::Month.since(now), 70 * 24 * 60 * 60);
+        assert_eq!(Period::AllTime.since(now), 0);
+
+        // Doesn't underflow
+        assert_eq!(Period::Month.since(60), 0);
+    }
+}
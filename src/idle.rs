@@ -0,0 +1,283 @@
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use serenity::{client::Context, model::id::GuildId};
+use songbird::{
+    Event, EventContext, EventHandler,
+    tracks::{PlayMode, TrackHandle},
+};
+use tokio::task::AbortHandle;
+use tracing::info;
+
+use crate::{Data, events};
+
+/// Why the bot is considered idle in the guild. Each reason has its own timer, as they end independently
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum Reason {
+    /// Nothing is playing and the queue is empty
+    QueueEmpty,
+    /// Nobody but bots is in the voice channel
+    Alone,
+}
+
+/// Pending timers that disconnect the bot once it has been idle for too long
+#[derive(Default)]
+pub(crate) struct IdleTimers {
+    timers: Arc<Mutex<HashMap<(GuildId, Reason), AbortHandle>>>,
+    /// Guilds where the current track was paused because everyone left the voice channel
+    paused: Mutex<HashSet<GuildId>>,
+}
+
+impl IdleTimers {
+    /// Starts the timer that disconnects the bot after the guild's idle timeout,
+    /// unless the bot is not idle anymore by then. Keeps the timer running if it is already started.
+    pub(crate) fn start(&self, ctx: &Context, data: &Arc<Data>, guild_id: GuildId, reason: Reason) {
+        let timeout = data.settings.get(guild_id).idle_timeout();
+        let (ctx, data) = (ctx.clone(), data.clone());
+        self.schedule(guild_id, reason, timeout, async move {
+            leave_if_idle(&ctx, &data, guild_id, reason).await;
+        });
+    }
+
+    /// Cancels the timer as the bot is not idle for this reason anymore
+    pub(crate) fn cancel(&self, guild_id: GuildId, reason: Reason) {
+        if let Some(timer) = self.timers.lock().unwrap().remove(&(guild_id, reason)) {
+            timer.abort();
+        }
+    }
+
+    /// Pauses the current track as nobody listens to it anymore and starts the timer to leave.
+    /// The queue is kept, so the track resumes if someone returns before the timer is over
+    pub(crate) async fn everyone_left(&self, ctx: &Context, data: &Arc<Data>, guild_id: GuildId) {
+        self.start(ctx, data, guild_id, Reason::Alone);
+
+        let Some(current) = current_track(ctx, guild_id).await else {
+            return;
+        };
+        // Tracks that are already paused shouldn't be resumed later
+        let playing = current
+            .get_info()
+            .await
+            .is_ok_and(|state| state.playing == PlayMode::Play);
+        if playing && current.pause().is_ok() {
+            info!("Everyone left the vc in {guild_id}, pausing");
+            self.paused.lock().unwrap().insert(guild_id);
+        }
+    }
+
+    /// Cancels the timer to leave and resumes the track paused by [`Self::everyone_left`] if any
+    pub(crate) async fn listener_returned(&self, ctx: &Context, guild_id: GuildId) {
+        self.cancel(guild_id, Reason::Alone);
+
+        if !self.paused.lock().unwrap().remove(&guild_id) {
+            return;
+        }
+        if let Some(current) = current_track(ctx, guild_id).await
+            && current.play().is_ok()
+        {
+            info!("Someone returned to the vc in {guild_id}, resuming");
+        }
+    }
+
+    /// Cancels all the guild's timers, e.g. once the bot has left the voice channel
+    pub(crate) fn cancel_all(&self, guild_id: GuildId) {
+        self.paused.lock().unwrap().remove(&guild_id);
+        self.timers.lock().unwrap().retain(|&(id, _), timer| {
+            if id == guild_id {
+                timer.abort();
+            }
+            id != guild_id
+        });
+    }
+
+    fn schedule(
+        &self,
+        guild_id: GuildId,
+        reason: Reason,
+        timeout: Duration,
+        on_timeout: impl Future<Output = ()> + Send + 'static,
+    ) {
+        let mut timers = self.timers.lock().unwrap();
+        if timers.contains_key(&(guild_id, reason)) {
+            return;
+        }
+
+        let all_timers = self.timers.clone();
+        let task = tokio::spawn(async move {
+            tokio::time::sleep(timeout).await;
+            all_timers.lock().unwrap().remove(&(guild_id, reason));
+            on_timeout.await;
+        });
+        timers.insert((guild_id, reason), task.abort_handle());
+    }
+
+    #[cfg(test)]
+    fn is_running(&self, guild_id: GuildId, reason: Reason) -> bool {
+        self.timers
+            .lock()
+            .unwrap()
+            .contains_key(&(guild_id, reason))
+    }
+}
+
+async fn current_track(ctx: &Context, guild_id: GuildId) -> Option<TrackHandle> {
+    let vc = songbird::get(ctx)
+        .await
+        .expect("Songbird Voice client placed in at initialisation.")
+        .get(guild_id)?;
+    vc.lock().await.queue().current()
+}
+
+/// Disconnects the bot with a goodbye message if it is still idle for the reason
+async fn leave_if_idle(ctx: &Context, data: &Data, guild_id: GuildId, reason: Reason) {
+    let songbird = songbird::get(ctx)
+        .await
+        .expect("Songbird Voice client placed in at initialisation.");
+    let Some(vc) = songbird.get(guild_id) else {
+        return;
+    };
+
+    let still_idle = match reason {
+        Reason::QueueEmpty => vc.lock().await.queue().is_empty(),
+        Reason::Alone => events::bot_left_alone(ctx, guild_id),
+    };
+    if !still_idle {
+        return;
+    }
+
+    let minutes = data.settings.get(guild_id).idle_timeout_minutes;
+    let goodbye = match reason {
+        Reason::QueueEmpty => format!("Nothing was played for {minutes} min, see you later 👋"),
+        Reason::Alone => format!("Everyone left {minutes} min ago, see you later 👋"),
+    };
+    info!("Leaving idle vc in {guild_id}: {reason:?}");
+    events::notify_vc(ctx, guild_id, goodbye).await;
+    let _ = songbird.remove(guild_id).await;
+}
+
+/// Songbird event handler that starts the idle timer once the queue is over and cancels it
+/// once something is playing again
+pub(crate) struct QueueHandler {
+    ctx: Context,
+    data: Arc<Data>,
+    guild_id: GuildId,
+}
+
+impl QueueHandler {
+    pub(crate) fn new(ctx: Context, data: Arc<Data>, guild_id: GuildId) -> Self {
+        Self {
+            ctx,
+            data,
+            guild_id,
+        }
+    }
+}
+
+#[async_trait]
+impl EventHandler for QueueHandler {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        let EventContext::Track(tracks) = ctx else {
+            return None;
+        };
+        let idle = &self.data.idle;
+
+        for (state, _) in tracks.iter() {
+            if let PlayMode::Play = state.playing {
+                idle.cancel(self.guild_id, Reason::QueueEmpty);
+            } else if state.playing.is_done() {
+                let songbird = songbird::get(&self.ctx)
+                    .await
+                    .expect("Songbird Voice client placed in at initialisation.");
+                let Some(vc) = songbird.get(self.guild_id) else {
+                    continue;
+                };
+                // Track end handler of the queue is invoked before this one, so the queue is already updated
+                if vc.lock().await.queue().is_empty() {
+                    idle.start(&self.ctx, &self.data, self.guild_id, Reason::QueueEmpty);
+                }
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const TIMEOUT: Duration = Duration::from_millis(100);
+
+    /// Moves the paused clock forward, letting the timers that are due fire.
+    /// Yields first, so the just scheduled timers start counting from the current time
+    async fn advance(duration: Duration) {
+        tokio::task::yield_now().await;
+        tokio::time::advance(duration).await;
+        tokio::task::yield_now().await;
+    }
+
+    /// Schedules the timer that counts its firings
+    fn schedule(timers: &IdleTimers, guild_id: GuildId, reason: Reason, fired: &Arc<AtomicUsize>) {
+        let fired = fired.clone();
+        timers.schedule(guild_id, reason, TIMEOUT, async move {
+            fired.fetch_add(1, Ordering::SeqCst);
+        });
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn idle_timers() {
+        let timers = IdleTimers::default();
+        let guild_id = GuildId::new(101);
+        let fired = Arc::new(AtomicUsize::new(0));
+
+        schedule(&timers, guild_id, Reason::QueueEmpty, &fired);
+        assert!(timers.is_running(guild_id, Reason::QueueEmpty));
+        assert!(!timers.is_running(guild_id, Reason::Alone));
+        advance(TIMEOUT * 2).await;
+        assert_eq!(fired.load(Ordering::SeqCst), 1);
+        // Fired timers are forgotten, so they can be started again
+        assert!(!timers.is_running(guild_id, Reason::QueueEmpty));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn idle_timers_cancel() {
+        let timers = IdleTimers::default();
+        let guild_id = GuildId::new(101);
+        let another_guild_id = GuildId::new(202);
+        let fired = Arc::new(AtomicUsize::new(0));
+
+        schedule(&timers, guild_id, Reason::QueueEmpty, &fired);
+        schedule(&timers, guild_id, Reason::Alone, &fired);
+        schedule(&timers, another_guild_id, Reason::Alone, &fired);
+        timers.cancel(guild_id, Reason::QueueEmpty);
+        assert!(!timers.is_running(guild_id, Reason::QueueEmpty));
+        assert!(timers.is_running(guild_id, Reason::Alone));
+
+        timers.cancel_all(guild_id);
+        assert!(!timers.is_running(guild_id, Reason::Alone));
+        assert!(timers.is_running(another_guild_id, Reason::Alone));
+
+        // Only the timer of another guild fires
+        advance(TIMEOUT * 2).await;
+        assert_eq!(fired.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn idle_timers_keep_running() {
+        let timers = IdleTimers::default();
+        let guild_id = GuildId::new(101);
+        let fired = Arc::new(AtomicUsize::new(0));
+
+        schedule(&timers, guild_id, Reason::Alone, &fired);
+        // Starting the running timer again neither restarts nor duplicates it
+        advance(TIMEOUT / 2).await;
+        schedule(&timers, guild_id, Reason::Alone, &fired);
+        advance(TIMEOUT * 3 / 4).await;
+        assert_eq!(fired.load(Ordering::SeqCst), 1);
+        advance(TIMEOUT).await;
+        assert_eq!(fired.load(Ordering::SeqCst), 1);
+    }
+}
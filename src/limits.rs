@@ -0,0 +1,235 @@
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serenity::model::id::{GuildId, UserId};
+
+use crate::{guild_settings::GuildSettings, track_info};
+
+/// A limit of the guild that some of the requested tracks have hit
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Limited {
+    /// Only the first `max` tracks of the playlist are taken
+    Playlist { max: u32 },
+    /// `count` tracks are longer than `max_minutes`
+    TrackDuration { count: usize, max_minutes: u32 },
+    /// The user already has `queued` tracks in the queue, so only `added` tracks fit into `max`
+    UserTracks {
+        max: u32,
+        queued: usize,
+        added: usize,
+    },
+}
+
+impl Display for Limited {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::Playlist { max } => {
+                write!(f, "Only the first {max} tracks of a playlist can be added")
+            }
+            Self::TrackDuration {
+                count: 1,
+                max_minutes,
+            } => {
+                write!(f, "The track is longer than {max_minutes} min")
+            }
+            Self::TrackDuration { count, max_minutes } => {
+                write!(f, "{count} tracks are longer than {max_minutes} min")
+            }
+            Self::UserTracks { max, queued, added } => write!(
+                f,
+                "You can have up to {max} tracks in the queue, you already have {queued}, \
+                so {added} more are added"
+            ),
+        }
+    }
+}
+
+/// Drops requested tracks that exceed the guild's limits, keeping the order of the rest.
+/// Returns the limits that were hit
+pub(crate) fn apply<T>(
+    settings: &GuildSettings,
+    items: &mut Vec<(track_info::Metadata, T)>,
+    user_tracks_in_queue: usize,
+) -> Vec<Limited> {
+    let mut limited = vec![];
+
+    let max = settings.playlist_tracks_limit;
+    if max > 0 && items.len() > max as usize {
+        items.truncate(max as usize);
+        limited.push(Limited::Playlist { max });
+    }
+
+    let max_minutes = settings.track_minutes_limit;
+    if max_minutes > 0 {
+        let count = items.len();
+        // Infinite streams have no duration, so only the idle timeout stops them
+        items.retain(|(metadata, _)| {
+            metadata
+                .duration_sec
+                .is_none_or(|duration| duration.get() <= max_minutes * 60)
+        });
+        if items.len() < count {
+            limited.push(Limited::TrackDuration {
+                count: count - items.len(),
+                max_minutes,
+            });
+        }
+    }
+
+    let max = settings.user_tracks_limit;
+    if max > 0 && user_tracks_in_queue + items.len() > max as usize {
+        let added = (max as usize).saturating_sub(user_tracks_in_queue);
+        items.truncate(added);
+        limited.push(Limited::UserTracks {
+            max,
+            queued: user_tracks_in_queue,
+            added,
+        });
+    }
+    limited
+}
+
+/// When users may request tracks again, to limit how often they do it
+#[derive(Default)]
+pub(crate) struct Cooldowns {
+    ends: Mutex<HashMap<(GuildId, UserId), Instant>>,
+}
+
+impl Cooldowns {
+    /// Returns how long the user should wait before requesting tracks again, if at all
+    pub(crate) fn remaining(
+        &self,
+        guild_id: GuildId,
+        user_id: UserId,
+        now: Instant,
+    ) -> Option<Duration> {
+        let mut ends = self.ends.lock().unwrap();
+        // Forget cooldowns that are over, so the map doesn't grow forever
+        ends.retain(|_, end| *end > now);
+        ends.get(&(guild_id, user_id))
+            .map(|end| end.duration_since(now))
+    }
+
+    /// Starts the cooldown once the user's request has added something to the queue
+    pub(crate) fn start(
+        &self,
+        guild_id: GuildId,
+        user_id: UserId,
+        cooldown: Duration,
+        now: Instant,
+    ) {
+        if !cooldown.is_zero() {
+            self.ends
+                .lock()
+                .unwrap()
+                .insert((guild_id, user_id), now + cooldown);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::num::NonZeroU32;
+
+    fn items(durations: &[Option<u32>]) -> Vec<(track_info::Metadata, usize)> {
+        durations
+            .iter()
+            .enumerate()
+            .map(|(i, &duration)| {
+                let metadata = track_info::Metadata {
+                    title: format!("Track {i}").into(),
+                    source_url: format!("https://example.com/{i}").into(),
+                    thumbnail_url: None,
+                    duration_sec: duration.and_then(NonZeroU32::new),
+                };
+                (metadata, i)
+            })
+            .collect()
+    }
+
+    fn indices(items: &[(track_info::Metadata, usize)]) -> Vec<usize> {
+        items.iter().map(|(_, i)| *i).collect()
+    }
+
+    #[test]
+    fn apply_limits() {
+        let mut requested = items(&[Some(60), Some(600), None, Some(120)]);
+
+        // No limits by default
+        let settings = GuildSettings::default();
+        assert_eq!(apply(&settings, &mut requested, 100), vec![]);
+        assert_eq!(indices(&requested), vec![0, 1, 2, 3]);
+
+        // Long tracks are dropped, but streams are kept
+        let settings = GuildSettings {
+            track_minutes_limit: 5,
+            ..Default::default()
+        };
+        assert_eq!(
+            apply(&settings, &mut requested, 0),
+            vec![Limited::TrackDuration {
+                count: 1,
+                max_minutes: 5
+            }]
+        );
+        assert_eq!(indices(&requested), vec![0, 2, 3]);
+
+        // Playlists are cut before counting user's tracks
+        let settings = GuildSettings {
+            playlist_tracks_limit: 2,
+            user_tracks_limit: 3,
+            ..Default::default()
+        };
+        assert_eq!(
+            apply(&settings, &mut requested, 2),
+            vec![
+                Limited::Playlist { max: 2 },
+                Limited::UserTracks {
+                    max: 3,
+                    queued: 2,
+                    added: 1
+                }
+            ]
+        );
+        assert_eq!(indices(&requested), vec![0]);
+
+        // Nothing is added once the user's tracks fill the limit
+        assert_eq!(
+            apply(&settings, &mut requested, 3),
+            vec![Limited::UserTracks {
+                max: 3,
+                queued: 3,
+                added: 0
+            }]
+        );
+        assert!(requested.is_empty());
+    }
+
+    #[test]
+    fn cooldowns() {
+        let cooldowns = Cooldowns::default();
+        let guild_id = GuildId::new(101);
+        let (alice, bob) = (UserId::new(1), UserId::new(2));
+        let cooldown = Duration::from_secs(10);
+        let now = Instant::now();
+
+        assert_eq!(cooldowns.remaining(guild_id, alice, now), None);
+        cooldowns.start(guild_id, alice, cooldown, now);
+        assert_eq!(
+            cooldowns.remaining(guild_id, alice, now + Duration::from_secs(3)),
+            Some(Duration::from_secs(7))
+        );
+        // Cooldowns are per user and per guild
+        assert_eq!(cooldowns.remaining(guild_id, bob, now), None);
+        assert_eq!(cooldowns.remaining(GuildId::new(202), alice, now), None);
+
+        assert_eq!(cooldowns.remaining(guild_id, alice, now + cooldown), None);
+
+        // Zero cooldown means no cooldown
+        cooldowns.start(guild_id, bob, Duration::ZERO, now);
+        assert_eq!(cooldowns.remaining(guild_id, bob, now), None);
+    }
+}
@@ -1,4 +1,5 @@
 use std::env;
+use std::sync::Arc;
 
 use serenity::{
     client::{Client, FullEvent},
@@ -7,13 +8,27 @@ use serenity::{
 use songbird::SerenityInit;
 use tracing::{info, warn};
 
+mod autoplay;
+mod backup;
+mod blocklist;
+mod channels;
 mod commands;
 mod events;
+mod follow;
+mod guild_settings;
+mod history;
+mod idle;
+mod limits;
+mod now_playing;
+mod permissions;
+mod playlist;
 mod radiot;
+mod saved_queue;
 #[cfg(feature = "spotify")]
 mod spotify;
 mod storage;
 mod track_info;
+mod vote_skip;
 mod yt_dlp;
 
 struct Data {
@@ -21,11 +36,21 @@ struct Data {
     radio_t_resolver: radiot::Resolver,
     #[cfg(feature = "spotify")]
     spotify_resolver: spotify::Resolver,
+    settings: guild_settings::Settings,
+    blocklists: blocklist::Blocklists,
+    allowed_channels: channels::AllowedChannels,
+    idle: idle::IdleTimers,
+    now_playing: now_playing::NowPlaying,
+    playlist_storage: Arc<dyn playlist::PlaylistStorage>,
+    history: history::History,
+    queue_storage: Arc<dyn saved_queue::QueueStorage>,
+    vote_skips: vote_skip::VoteSkips,
+    cooldowns: limits::Cooldowns,
 }
-type Context<'a> = poise::Context<'a, Data, anyhow::Error>;
+type Context<'a> = poise::Context<'a, Arc<Data>, anyhow::Error>;
 
 #[tokio::main]
-async fn main() {
+async fn main() -> Result<(), anyhow::Error> {
     tracing_subscriber::fmt::init();
 
     // Load env varialbes from .env if any
@@ -33,6 +58,15 @@ async fn main() {
         info!("Skipping .env file because of {err}");
     }
 
+    // `prospero export` and `prospero import` manage the bot data instead of running the bot.
+    // Arguments are checked before opening the database, so a typo doesn't create or migrate it
+    let args: Vec<String> = env::args().skip(1).collect();
+    let backup_command = if args.is_empty() {
+        None
+    } else {
+        Some(backup::Command::parse(&args)?)
+    };
+
     let data_dir = env::var("DATA_DIR").expect("Expected path to DATA in the environment");
     let data_dir = std::path::PathBuf::from(data_dir);
     if !data_dir.exists() {
@@ -42,12 +76,41 @@ async fn main() {
     let storage =
         storage::Storage::new(data_dir.join("db.sqlite")).expect("Failed to create storage");
 
+    if let Some(command) = backup_command {
+        return backup::run(storage.as_ref(), command).await;
+    }
+
+    // Fuzzy lookup of the yt-dlp query cache is disabled unless the similarity threshold is set
+    let fuzzy_threshold = env::var("QUERY_CACHE_FUZZY_THRESHOLD")
+        .ok()
+        .map(|threshold| {
+            threshold
+                .parse::<f64>()
+                .ok()
+                .filter(|threshold| (0.0..=1.0).contains(threshold))
+                .expect("Expected QUERY_CACHE_FUZZY_THRESHOLD to be a number from 0 to 1")
+        });
+
     let http_client = reqwest::Client::new();
     let bot_data = Data {
         #[cfg(feature = "spotify")]
-        spotify_resolver: spotify::Resolver::new(storage.clone()),
-        yt_dlp_resolver: yt_dlp::Resolver::new(http_client.clone(), storage),
+        spotify_resolver: spotify::Resolver::new(storage.clone(), storage.clone()),
+        yt_dlp_resolver: yt_dlp::Resolver::new(
+            http_client.clone(),
+            storage.clone(),
+            fuzzy_threshold,
+        ),
         radio_t_resolver: radiot::Resolver::new(http_client.clone()),
+        settings: guild_settings::Settings::load(storage.clone()).await,
+        blocklists: blocklist::Blocklists::load(storage.clone()).await,
+        allowed_channels: channels::AllowedChannels::load(storage.clone()).await,
+        idle: idle::IdleTimers::default(),
+        now_playing: now_playing::NowPlaying::default(),
+        playlist_storage: storage.clone(),
+        history: history::History::new(storage.clone()),
+        queue_storage: storage,
+        vote_skips: vote_skip::VoteSkips::default(),
+        cooldowns: limits::Cooldowns::default(),
     };
 
     // Configure the client with your Discord bot token in the environment.
@@ -55,23 +118,34 @@ async fn main() {
 
     let framework = poise::Framework::builder()
         .setup(
-            |ctx, _ready, framework: &poise::Framework<Data, anyhow::Error>| {
+            |ctx, _ready, framework: &poise::Framework<Arc<Data>, anyhow::Error>| {
                 Box::pin(async move {
                     poise::builtins::register_globally(ctx, &framework.options().commands).await?;
-                    Ok(bot_data)
+                    Ok(Arc::new(bot_data))
                 })
             },
         )
         .options(poise::FrameworkOptions {
             commands: vec![
+                commands::autoplay(),
+                commands::blocklist(),
+                commands::cache(),
+                commands::channels(),
+                commands::history(),
                 commands::join(),
                 commands::leave(),
                 commands::ping(),
                 commands::play(),
+                commands::playlist(),
+                commands::remove(),
+                commands::settings(),
                 commands::skip(),
+                commands::stats(),
                 commands::stop(),
                 #[cfg(feature = "spotify")]
                 commands::connect_spotify(),
+                #[cfg(feature = "spotify")]
+                commands::spotify(),
             ],
             event_handler: |ctx, event, _framework, data| {
                 Box::pin(async move {
@@ -87,6 +161,17 @@ async fn main() {
                     Ok(())
                 })
             },
+            on_error: |error| {
+                Box::pin(async move {
+                    // Failed checks have already explained the reason to the invoker
+                    if let poise::FrameworkError::CommandCheckFailed { error: None, .. } = error {
+                        return;
+                    }
+                    if let Err(err) = poise::builtins::on_error(error).await {
+                        warn!("Failed to handle the framework error: {err}");
+                    }
+                })
+            },
             ..Default::default()
         })
         .build();
@@ -101,4 +186,5 @@ async fn main() {
         .start()
         .await
         .map_err(|why| warn!("Client stopped: {:?}", why));
+    Ok(())
 }
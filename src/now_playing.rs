@@ -0,0 +1,169 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use serenity::{
+    client::Context,
+    gateway::ActivityData,
+    model::id::{ChannelId, GuildId},
+};
+use songbird::{Event, EventContext, EventHandler, Songbird, tracks::PlayMode};
+use tracing::warn;
+
+use crate::{Data, track_info};
+
+/// Longest voice channel status Discord accepts
+const MAX_STATUS_CHARS: usize = 500;
+/// Longest activity name Discord accepts
+const MAX_ACTIVITY_CHARS: usize = 128;
+
+/// Shows the current track in the voice channel status and in the bot presence
+#[derive(Default)]
+pub(crate) struct NowPlaying {
+    /// Voice channels whose status is set by the bot, with the status text
+    statuses: Mutex<HashMap<GuildId, (ChannelId, String)>>,
+    /// Guild whose track is shown in the presence, as the presence is shared by all guilds
+    presence_owner: Mutex<Option<GuildId>>,
+}
+
+impl NowPlaying {
+    /// Sets the voice channel status and the bot presence to the track title
+    pub(crate) async fn show(
+        &self,
+        ctx: &Context,
+        guild_id: GuildId,
+        channel_id: ChannelId,
+        title: &str,
+    ) {
+        let status = truncate(title, MAX_STATUS_CHARS);
+        let previous = {
+            let mut statuses = self.statuses.lock().unwrap();
+            let previous = statuses.insert(guild_id, (channel_id, status.clone()));
+            // Resuming after pause starts the same track again
+            if previous.as_ref() == Some(&(channel_id, status.clone())) {
+                return;
+            }
+            previous
+        };
+        // The bot has moved since the status was set
+        if let Some((previous_channel_id, _)) = previous
+            && previous_channel_id != channel_id
+        {
+            set_voice_status(ctx, previous_channel_id, "").await;
+        }
+        set_voice_status(ctx, channel_id, &status).await;
+
+        ctx.set_activity(Some(ActivityData::listening(truncate(
+            title,
+            MAX_ACTIVITY_CHARS,
+        ))));
+        *self.presence_owner.lock().unwrap() = Some(guild_id);
+    }
+
+    /// Clears the voice channel status and the bot presence if it shows the guild's track
+    pub(crate) async fn clear(&self, ctx: &Context, guild_id: GuildId) {
+        {
+            let mut owner = self.presence_owner.lock().unwrap();
+            if *owner == Some(guild_id) {
+                ctx.set_activity(None);
+                *owner = None;
+            }
+        }
+
+        let status = self.statuses.lock().unwrap().remove(&guild_id);
+        if let Some((channel_id, _)) = status {
+            set_voice_status(ctx, channel_id, "").await;
+        }
+    }
+}
+
+async fn set_voice_status(ctx: &Context, channel_id: ChannelId, status: &str) {
+    let body = serde_json::json!({ "status": status });
+    // Requires `Set Voice Channel Status` permission which the bot might lack
+    if let Err(err) = ctx.http.edit_voice_status(channel_id, &body, None).await {
+        warn!("Failed to set status of {channel_id}: {err}");
+    }
+}
+
+/// Cuts the text to fit into `max_chars` characters, marking the cut with an ellipsis
+fn truncate(text: &str, max_chars: usize) -> String {
+    if text.chars().count() <= max_chars {
+        return text.to_owned();
+    }
+    let mut truncated: String = text.chars().take(max_chars - 1).collect();
+    truncated.push('…');
+    truncated
+}
+
+/// Songbird event handler that shows the track once it starts and clears it once the queue is over
+pub(crate) struct TrackHandler {
+    ctx: Context,
+    data: Arc<Data>,
+    songbird: Arc<Songbird>,
+    guild_id: GuildId,
+}
+
+impl TrackHandler {
+    pub(crate) fn new(
+        ctx: Context,
+        data: Arc<Data>,
+        songbird: Arc<Songbird>,
+        guild_id: GuildId,
+    ) -> Self {
+        Self {
+            ctx,
+            data,
+            songbird,
+            guild_id,
+        }
+    }
+}
+
+#[async_trait]
+impl EventHandler for TrackHandler {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        let EventContext::Track(tracks) = ctx else {
+            return None;
+        };
+        let now_playing = &self.data.now_playing;
+
+        for (state, handle) in tracks.iter() {
+            let vc = self.songbird.get(self.guild_id)?;
+            if let PlayMode::Play = state.playing {
+                let Some(channel_id) = vc.lock().await.current_channel() else {
+                    continue;
+                };
+                let track_info = handle.data::<track_info::TrackInfo>();
+                now_playing
+                    .show(
+                        &self.ctx,
+                        self.guild_id,
+                        ChannelId::new(channel_id.0.get()),
+                        &track_info.metadata().title,
+                    )
+                    .await;
+            } else if state.playing.is_done() && vc.lock().await.queue().is_empty() {
+                // Track end handler of the queue is invoked before this one, so the queue is already updated
+                now_playing.clear(&self.ctx, self.guild_id).await;
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncate_test() {
+        assert_eq!(
+            truncate("Never Gonna Give You Up", 100),
+            "Never Gonna Give You Up"
+        );
+        assert_eq!(truncate("Never", 5), "Never");
+        assert_eq!(truncate("Never Gonna", 5), "Neve…");
+        // Doesn't split multibyte characters
+        assert_eq!(truncate("Кино — Группа крови", 5), "Кино…");
+    }
+}
@@ -0,0 +1,305 @@
+use std::time::Duration;
+
+use poise::CreateReply;
+use serenity::builder::{
+    CreateActionRow, CreateButton, CreateInteractionResponse, CreateInteractionResponseMessage,
+};
+use serenity::collector::ComponentInteractionCollector;
+use serenity::model::id::{ChannelId, RoleId};
+use tracing::info;
+
+use crate::{Context, channels, events};
+
+/// How long the offer to move the bot to the invoker's voice channel stays valid
+const MOVE_OFFER_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Whether the invoker may control the bot from their voice channel
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum VcAccess {
+    Allowed,
+    /// The invoker is elsewhere, but the bot isn't busy, so it can be moved to them
+    CanMove(ChannelId),
+    /// The bot would have to join the invoker's voice channel, but it isn't allowed there
+    Restricted(ChannelId),
+    Denied,
+}
+
+/// Decides whether the invoker may control the bot. Everyone may control the bot in their own
+/// voice channel, while DJs may control it from anywhere. The bot only joins allowed channels
+pub(crate) fn vc_access(
+    bot_vc: Option<ChannelId>,
+    author_vc: Option<ChannelId>,
+    author_vc_allowed: bool,
+    dj: bool,
+    bot_idle: bool,
+) -> VcAccess {
+    match (bot_vc, author_vc) {
+        // The bot joins the invoker if needed
+        (None, Some(author_vc)) if !author_vc_allowed => VcAccess::Restricted(author_vc),
+        (None, _) => VcAccess::Allowed,
+        (Some(bot_vc), Some(author_vc)) if bot_vc == author_vc => VcAccess::Allowed,
+        _ if dj => VcAccess::Allowed,
+        (Some(_), Some(author_vc)) if bot_idle && !author_vc_allowed => {
+            VcAccess::Restricted(author_vc)
+        }
+        (Some(_), Some(author_vc)) if bot_idle => VcAccess::CanMove(author_vc),
+        _ => VcAccess::Denied,
+    }
+}
+
+/// Returns whether the member is a DJ, i.e. has the guild's DJ role or is an admin
+pub(crate) fn has_dj_rights(dj_role: Option<RoleId>, member_roles: &[RoleId], admin: bool) -> bool {
+    admin || dj_role.is_some_and(|role| member_roles.contains(&role))
+}
+
+/// Returns whether the invoker is a DJ in the guild
+pub(crate) async fn is_dj(ctx: Context<'_>) -> bool {
+    let dj_role = ctx.data().settings.get(ctx.guild_id().unwrap()).dj_role;
+    let Some(member) = ctx.author_member().await else {
+        return false;
+    };
+    let admin = member
+        .permissions
+        .is_some_and(|permissions| permissions.manage_guild());
+    has_dj_rights(dj_role, &member.roles, admin)
+}
+
+/// Command check that the invoker may stop the bot or touch tracks requested by others.
+/// Everyone may do it unless the guild has a DJ role
+pub(crate) async fn dj_only(ctx: Context<'_>) -> Result<bool, anyhow::Error> {
+    let Some(dj_role) = ctx.data().settings.get(ctx.guild_id().unwrap()).dj_role else {
+        return Ok(true);
+    };
+    if is_dj(ctx).await {
+        return Ok(true);
+    }
+
+    ctx.send(
+        CreateReply::default()
+            .content(format!("Only <@&{dj_role}> can do that"))
+            .ephemeral(true),
+    )
+    .await?;
+    Ok(false)
+}
+
+/// Command check that music commands are used in the guild's music text channels if it has any
+pub(crate) async fn in_music_channel(ctx: Context<'_>) -> Result<bool, anyhow::Error> {
+    let guild_id = ctx.guild_id().unwrap();
+    let allowed_channels = &ctx.data().allowed_channels;
+    if allowed_channels.is_allowed(guild_id, channels::ChannelKind::Text, ctx.channel_id()) {
+        return Ok(true);
+    }
+
+    let allowed = allowed_channels.list(guild_id, channels::ChannelKind::Text);
+    ctx.send(
+        CreateReply::default()
+            .content(format!(
+                "Music commands can be used in {}",
+                channels::mentions(&allowed)
+            ))
+            .ephemeral(true),
+    )
+    .await?;
+    Ok(false)
+}
+
+/// Tells the invoker that the bot may not join the voice channel
+pub(crate) async fn refuse_to_join(
+    ctx: Context<'_>,
+    channel_id: ChannelId,
+) -> Result<(), anyhow::Error> {
+    let allowed = ctx
+        .data()
+        .allowed_channels
+        .list(ctx.guild_id().unwrap(), channels::ChannelKind::Voice);
+    ctx.send(
+        CreateReply::default()
+            .content(format!(
+                "I'm not allowed to join <#{channel_id}>, I can play in {}",
+                channels::mentions(&allowed)
+            ))
+            .ephemeral(true),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Returns whether the bot has nothing to do in its voice channel, so moving it disturbs nobody
+async fn bot_is_idle(ctx: Context<'_>) -> bool {
+    let guild_id = ctx.guild_id().unwrap();
+    let queue_is_empty = match songbird::get(ctx.serenity_context())
+        .await
+        .expect("Songbird Voice client placed in at initialisation.")
+        .get(guild_id)
+    {
+        Some(vc) => vc.lock().await.queue().is_empty(),
+        None => true,
+    };
+    queue_is_empty || events::bot_left_alone(ctx.serenity_context(), guild_id)
+}
+
+/// Command check that the invoker shares the bot's voice channel. If the bot is idle in another
+/// channel, offers to move it to the invoker instead
+pub(crate) async fn in_bot_vc(ctx: Context<'_>) -> Result<bool, anyhow::Error> {
+    let guild_id = ctx.guild_id().unwrap();
+    let bot_vc = events::bot_channel(ctx.serenity_context(), guild_id);
+    let author_vc = ctx
+        .guild()
+        .and_then(|guild| guild.voice_states.get(&ctx.author().id)?.channel_id);
+    let author_vc_allowed = author_vc.is_none_or(|channel_id| {
+        ctx.data()
+            .allowed_channels
+            .is_allowed(guild_id, channels::ChannelKind::Voice, channel_id)
+    });
+    let access = vc_access(
+        bot_vc,
+        author_vc,
+        author_vc_allowed,
+        is_dj(ctx).await,
+        bot_is_idle(ctx).await,
+    );
+
+    let channel_id = match access {
+        VcAccess::Allowed => return Ok(true),
+        VcAccess::Restricted(channel_id) => {
+            refuse_to_join(ctx, channel_id).await?;
+            return Ok(false);
+        }
+        VcAccess::Denied => {
+            let bot_vc = bot_vc.map_or_else(|| "another channel".into(), |id| format!("<#{id}>"));
+            ctx.send(
+                CreateReply::default()
+                    .content(format!("I'm busy in {bot_vc}, join it to control me"))
+                    .ephemeral(true),
+            )
+            .await?;
+            return Ok(false);
+        }
+        VcAccess::CanMove(channel_id) => channel_id,
+    };
+
+    let button_id = format!("{}-move", ctx.id());
+    let reply = ctx
+        .send(
+            CreateReply::default()
+                .content("I'm idle in another voice channel. Should I move to yours?")
+                .components(vec![CreateActionRow::Buttons(vec![
+                    CreateButton::new(&button_id).label("Move here"),
+                ])])
+                .ephemeral(true),
+        )
+        .await?;
+
+    let Some(interaction) = ComponentInteractionCollector::new(ctx.serenity_context())
+        .author_id(ctx.author().id)
+        .custom_ids(vec![button_id])
+        .timeout(MOVE_OFFER_TIMEOUT)
+        .await
+    else {
+        reply
+            .edit(
+                ctx,
+                CreateReply::default()
+                    .content("I've stayed where I was")
+                    .components(vec![]),
+            )
+            .await?;
+        return Ok(false);
+    };
+
+    info!("{} moved the bot to {channel_id}", ctx.author().name);
+    songbird::get(ctx.serenity_context())
+        .await
+        .expect("Songbird Voice client placed in at initialisation.")
+        .join(guild_id, channel_id)
+        .await?;
+    interaction
+        .create_response(
+            ctx,
+            CreateInteractionResponse::UpdateMessage(
+                CreateInteractionResponseMessage::new()
+                    .content(format!("Moved to <#{channel_id}>"))
+                    .components(vec![]),
+            ),
+        )
+        .await?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vc_access_test() {
+        let bot_vc = ChannelId::new(101);
+        let author_vc = ChannelId::new(202);
+
+        // The bot isn't in a voice channel yet or shares it with the invoker
+        assert_eq!(vc_access(None, None, true, false, false), VcAccess::Allowed);
+        assert_eq!(
+            vc_access(None, Some(author_vc), true, false, false),
+            VcAccess::Allowed
+        );
+        assert_eq!(
+            vc_access(Some(bot_vc), Some(bot_vc), true, false, false),
+            VcAccess::Allowed
+        );
+
+        // DJs control the bot from anywhere
+        assert_eq!(
+            vc_access(Some(bot_vc), Some(author_vc), true, true, false),
+            VcAccess::Allowed
+        );
+        assert_eq!(
+            vc_access(Some(bot_vc), None, true, true, false),
+            VcAccess::Allowed
+        );
+
+        // Others may only take the idle bot to their voice channel
+        assert_eq!(
+            vc_access(Some(bot_vc), Some(author_vc), true, false, false),
+            VcAccess::Denied
+        );
+        assert_eq!(
+            vc_access(Some(bot_vc), Some(author_vc), true, false, true),
+            VcAccess::CanMove(author_vc)
+        );
+        assert_eq!(
+            vc_access(Some(bot_vc), None, true, false, true),
+            VcAccess::Denied
+        );
+
+        // The bot doesn't join restricted voice channels, but stays where it is for DJs
+        assert_eq!(
+            vc_access(None, Some(author_vc), false, true, false),
+            VcAccess::Restricted(author_vc)
+        );
+        assert_eq!(
+            vc_access(Some(bot_vc), Some(author_vc), false, false, true),
+            VcAccess::Restricted(author_vc)
+        );
+        assert_eq!(
+            vc_access(Some(bot_vc), Some(author_vc), false, true, true),
+            VcAccess::Allowed
+        );
+    }
+
+    #[test]
+    fn has_dj_rights_test() {
+        let dj_role = RoleId::new(101);
+        let another_role = RoleId::new(202);
+
+        assert!(has_dj_rights(
+            Some(dj_role),
+            &[another_role, dj_role],
+            false
+        ));
+        assert!(!has_dj_rights(Some(dj_role), &[another_role], false));
+        // Admins are always DJs, while nobody else is if there is no DJ role
+        assert!(has_dj_rights(Some(dj_role), &[], true));
+        assert!(has_dj_rights(None, &[], true));
+        assert!(!has_dj_rights(None, &[another_role], false));
+    }
+}
@@ -0,0 +1,59 @@
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serenity::model::id::GuildId;
+
+/// Longest allowed playlist name, in characters
+const MAX_NAME_LEN: usize = 50;
+
+/// A track saved in the playlist. Only the source is kept, so the track is resolved again on load
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct PlaylistTrack {
+    /// Source URL of the track, resolvable by one of the resolvers
+    pub(crate) source_url: String,
+    /// Track title, used to report tracks that failed to resolve
+    pub(crate) title: String,
+}
+
+/// An interface for storing and retrieving per-guild playlists
+#[async_trait]
+pub(crate) trait PlaylistStorage: Send + Sync {
+    /// Saves the playlist for the guild, replacing the one with the same name if any
+    async fn save(
+        &self,
+        guild_id: GuildId,
+        name: &str,
+        tracks: &[PlaylistTrack],
+    ) -> Result<(), anyhow::Error>;
+    /// Loads tracks of the guild's playlist if it exists
+    async fn load(&self, guild_id: GuildId, name: &str) -> Option<Vec<PlaylistTrack>>;
+    /// Returns (name, tracks count) of all the guild's playlists, ordered by name
+    async fn list(&self, guild_id: GuildId) -> Vec<(String, usize)>;
+    /// Deletes the guild's playlist. Returns `false` if there was no such playlist
+    async fn delete(&self, guild_id: GuildId, name: &str) -> Result<bool, anyhow::Error>;
+}
+
+/// Trims the playlist name, returning `None` if it is empty or too long
+pub(crate) fn normalize_name(name: &str) -> Option<&str> {
+    let name = name.trim();
+    (!name.is_empty() && name.chars().count() <= MAX_NAME_LEN).then_some(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_name_test() {
+        assert_eq!(normalize_name("party"), Some("party"));
+        assert_eq!(normalize_name("  friday party \n"), Some("friday party"));
+        assert_eq!(normalize_name("вечеринка"), Some("вечеринка"));
+
+        assert_eq!(normalize_name(""), None);
+        assert_eq!(normalize_name("   "), None);
+        assert_eq!(
+            normalize_name(&"a".repeat(MAX_NAME_LEN)),
+            Some("a".repeat(MAX_NAME_LEN).as_str())
+        );
+        assert_eq!(normalize_name(&"a".repeat(MAX_NAME_LEN + 1)), None);
+    }
+}
@@ -0,0 +1,277 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use futures::stream::{self, StreamExt};
+use serenity::{
+    client::Context,
+    model::id::{ChannelId, GuildId, UserId},
+};
+use smallvec::SmallVec;
+use songbird::{Call, Event, EventContext, EventHandler, Songbird, input::Input, tracks::Track};
+use tracing::{info, warn};
+
+use crate::{Data, channels, commands, track_info};
+
+/// How often the queue is saved to keep the position of the current track up to date
+pub(crate) const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(15);
+/// How many saved tracks are resolved at once
+const RESTORE_CONCURRENCY: usize = 4;
+
+/// A track in the saved queue
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct SavedTrack {
+    pub(crate) source_url: String,
+    pub(crate) title: String,
+    /// Who requested the track
+    pub(crate) requested_by: track_info::Requester,
+    pub(crate) volume: f32,
+}
+
+/// The guild's queue saved to be restored after the bot restart
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct SavedQueue {
+    /// Voice channel the bot was playing in
+    pub(crate) channel_id: ChannelId,
+    /// Position of the current, i.e. the first, track
+    pub(crate) position: Duration,
+    pub(crate) tracks: Vec<SavedTrack>,
+}
+
+/// An interface for storing and retrieving queues of guilds
+#[async_trait]
+pub(crate) trait QueueStorage: Send + Sync {
+    /// Saves the guild's queue, replacing the previous one
+    async fn save(&self, guild_id: GuildId, queue: &SavedQueue) -> Result<(), anyhow::Error>;
+    /// Loads queues of all guilds
+    async fn load_all(&self) -> Vec<(GuildId, SavedQueue)>;
+    /// Removes the guild's queue if any
+    async fn remove(&self, guild_id: GuildId) -> Result<(), anyhow::Error>;
+}
+
+/// Songbird event handler that saves the queue whenever a track starts or ends, and periodically
+pub(crate) struct SnapshotHandler {
+    data: Arc<Data>,
+    songbird: Arc<Songbird>,
+    guild_id: GuildId,
+}
+
+impl SnapshotHandler {
+    pub(crate) fn new(data: Arc<Data>, songbird: Arc<Songbird>, guild_id: GuildId) -> Self {
+        Self {
+            data,
+            songbird,
+            guild_id,
+        }
+    }
+}
+
+#[async_trait]
+impl EventHandler for SnapshotHandler {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        // Periodic events come without tracks
+        let track_ended = matches!(ctx, EventContext::Track(tracks)
+            if tracks.iter().any(|(state, _)| state.playing.is_done()));
+
+        let vc = self.songbird.get(self.guild_id)?;
+        let snapshot = snapshot(&*vc.lock().await).await;
+
+        let storage = &self.data.queue_storage;
+        let result = match snapshot {
+            Some(queue) => storage.save(self.guild_id, &queue).await,
+            // The queue can be temporarily empty while it is being filled, e.g. on restore,
+            // so forget it only once the last track is over
+            None if track_ended => storage.remove(self.guild_id).await,
+            None => Ok(()),
+        };
+        if let Err(err) = result {
+            warn!("Failed to save the queue in {}: {err}", self.guild_id);
+        }
+        None
+    }
+}
+
+/// Saves the queue of the call right away instead of waiting for the next snapshot,
+/// so tracks that were just added aren't lost if the bot restarts
+pub(crate) async fn save(data: &Data, guild_id: GuildId, vc: &Call) {
+    let Some(queue) = snapshot(vc).await else {
+        return;
+    };
+    if let Err(err) = data.queue_storage.save(guild_id, &queue).await {
+        warn!("Failed to save the queue in {guild_id}: {err}");
+    }
+}
+
+/// Captures the queue of the call. Returns `None` if there is nothing to save
+async fn snapshot(vc: &Call) -> Option<SavedQueue> {
+    let channel_id = ChannelId::new(vc.current_channel()?.0.get());
+    let queue = vc.queue().current_queue();
+    let current = queue.first()?;
+    let position = current
+        .get_info()
+        .await
+        .map(|state| state.position)
+        .unwrap_or_default();
+
+    let mut tracks = Vec::with_capacity(queue.len());
+    for handle in &queue {
+        let track_info = handle.data::<track_info::TrackInfo>();
+        let volume = handle.get_info().await.map_or(0.5, |state| state.volume);
+        tracks.push(SavedTrack {
+            source_url: track_info.metadata().source_url.to_string(),
+            title: track_info.metadata().title.to_string(),
+            requested_by: track_info.added_by(),
+            volume,
+        });
+    }
+    Some(SavedQueue {
+        channel_id,
+        position,
+        tracks,
+    })
+}
+
+/// Joins the saved voice channel and enqueues the saved tracks, resuming the current one
+/// from the saved position
+pub(crate) async fn restore(ctx: Context, data: Arc<Data>, guild_id: GuildId, saved: SavedQueue) {
+    info!("Restoring {} tracks in {guild_id}", saved.tracks.len());
+    let songbird = songbird::get(&ctx)
+        .await
+        .expect("Songbird Voice client placed in at initialisation.");
+    // The channel might have been disallowed while the bot was down
+    let allowed =
+        data.allowed_channels
+            .is_allowed(guild_id, channels::ChannelKind::Voice, saved.channel_id);
+    // The bot might be still in the voice channel, see `events::cache_ready`
+    let vc = match songbird.get(guild_id) {
+        Some(vc) => vc,
+        None if !allowed => {
+            info!(
+                "Not restoring the queue in {guild_id}: {} is no longer allowed",
+                saved.channel_id
+            );
+            if let Err(err) = data.queue_storage.remove(guild_id).await {
+                warn!("Failed to forget the queue in {guild_id}: {err}");
+            }
+            return;
+        }
+        None => match songbird.join(guild_id, saved.channel_id).await {
+            Ok(vc) => vc,
+            Err(err) => {
+                warn!(
+                    "Failed to rejoin {} to restore the queue: {err}",
+                    saved.channel_id
+                );
+                return;
+            }
+        },
+    };
+
+    let requests: Vec<(String, Option<UserId>, Duration)> = saved
+        .tracks
+        .iter()
+        .enumerate()
+        .map(|(i, track)| {
+            let user_id = match track.requested_by {
+                track_info::Requester::User(user_id) => Some(user_id),
+                track_info::Requester::Autoplay => None,
+            };
+            // Only the current track is resumed from the saved position
+            let position = if i == 0 {
+                saved.position
+            } else {
+                Duration::ZERO
+            };
+            (track.source_url.clone(), user_id, position)
+        })
+        .collect();
+    let resolved: Vec<_> = stream::iter(requests)
+        .map(|(source_url, user_id, position)| {
+            let data = data.clone();
+            async move { resolve(&data, guild_id, user_id, &source_url, position).await }
+        })
+        .buffered(RESTORE_CONCURRENCY)
+        .collect()
+        .await;
+
+    let mut vc = vc.lock().await;
+    for (i, (track, result)) in saved.tracks.into_iter().zip(resolved).enumerate() {
+        let (mut items, seek) = match result {
+            Ok((items, seek)) => (items.into_vec(), seek),
+            Err(err) => {
+                warn!("Failed to restore '{}' in {guild_id}: {err}", track.title);
+                continue;
+            }
+        };
+        // The blocklist might have changed while the bot was down
+        let requester_blocked = match track.requested_by {
+            track_info::Requester::User(user_id) => {
+                data.blocklists.is_user_blocked(guild_id, user_id)
+            }
+            track_info::Requester::Autoplay => false,
+        };
+        if requester_blocked {
+            info!(
+                "Not restoring '{}' in {guild_id}: its requester is blocked",
+                track.title
+            );
+            continue;
+        }
+        for blocked in data.blocklists.apply(guild_id, &mut items) {
+            info!("Not restoring '{}' in {guild_id}. {blocked}", track.title);
+        }
+        for (metadata, input) in items {
+            let handle = vc
+                .enqueue(
+                    Track::new_with_data(
+                        input,
+                        Arc::new(track_info::TrackInfo::new(metadata, track.requested_by)),
+                    )
+                    .volume(track.volume),
+                )
+                .await;
+            if i == 0 && seek {
+                let position = saved.position;
+                let seek = handle.seek(position);
+                let title = track.title.clone();
+                // The seek completes only once the track is created, so don't hold the call for it
+                tokio::spawn(async move {
+                    if let Err(err) = seek.result_async().await {
+                        warn!("Failed to resume '{title}' in {guild_id} from {position:?}: {err}");
+                    }
+                });
+            }
+        }
+    }
+}
+
+/// Resolves the saved track as if its requester requested it again, using their linked Spotify
+/// account. Spotify tracks can't seek, so they are started right from the position instead.
+/// Returns whether the tracks still have to be sought to the position
+async fn resolve(
+    data: &Data,
+    guild_id: GuildId,
+    user_id: Option<UserId>,
+    source_url: &str,
+    position: Duration,
+) -> Result<(SmallVec<[(track_info::Metadata, Input); 1]>, bool), String> {
+    #[cfg(feature = "spotify")]
+    if let Some(result) = data
+        .spotify_resolver
+        .resolve(guild_id, user_id, source_url)
+        .await
+    {
+        let tracks = result.map_err(|err| err.to_string())?;
+        let tracks = tracks
+            .into_iter()
+            .map(|track| {
+                let track = track.starting_at(position);
+                (track.metadata().clone(), track.into())
+            })
+            .collect();
+        return Ok((tracks, false));
+    }
+
+    let items = commands::resolve_query(data, guild_id, user_id, source_url).await?;
+    Ok((items, !position.is_zero()))
+}
@@ -1,4 +1,5 @@
 use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
 use std::io;
 use std::sync::Arc;
 
@@ -26,60 +27,203 @@ use librespot::playback::{
     mixer::NoOpVolume,
     player,
 };
-use serenity::all::GuildId;
+use serde::Deserialize;
+use serenity::all::{GuildId, UserId};
 use sha1::{Digest, Sha1};
 use smallvec::{smallvec, SmallVec};
 use songbird::input::{
     core::io::MediaSource, AudioStream, AudioStreamError, AuxMetadata, Compose, Input,
 };
 use tokio::sync::RwLock;
-use tracing::info;
+use tracing::{info, warn};
 
 use crate::track_info;
 
-/// An interface for storing and retrieving Spotify credentials for the guild
+/// An interface for storing and retrieving Spotify credentials of guilds and users
+#[async_trait]
 pub(crate) trait CredentialsStorage: Send + Sync {
-    /// Saves (username, password) pair for the provided guild
-    fn save(&self, guild_id: GuildId, username: &str, password: &str) -> Result<(), anyhow::Error>;
-    /// Resolves (username, password) pair for the provided guild if any
-    fn load(&self, guild_id: GuildId) -> Option<(String, String)>;
+    /// Saves (username, password) pair for the provided owner
+    async fn save(
+        &self,
+        owner: CredentialsOwner,
+        username: &str,
+        password: &str,
+    ) -> Result<(), anyhow::Error>;
+    /// Resolves (username, password) pair for the provided owner if any
+    async fn load(&self, owner: CredentialsOwner) -> Option<(String, String)>;
+    /// Removes credentials of the provided owner if any
+    async fn remove(&self, owner: CredentialsOwner) -> Result<(), anyhow::Error>;
+}
+
+/// An interface for storing and retrieving Spotify playback settings for the guild
+#[async_trait]
+pub(crate) trait SettingsStorage: Send + Sync {
+    /// Saves playback settings for the provided guild
+    async fn save(&self, guild_id: GuildId, settings: &Settings) -> Result<(), anyhow::Error>;
+    /// Resolves playback settings for the provided guild if they were ever changed
+    async fn load(&self, guild_id: GuildId) -> Option<Settings>;
+}
+
+/// Audio quality of the Spotify stream
+#[derive(Clone, Copy, Debug, Default, PartialEq, poise::ChoiceParameter)]
+pub(crate) enum Bitrate {
+    #[name = "96 kbps"]
+    Kbps96,
+    #[default]
+    #[name = "160 kbps"]
+    Kbps160,
+    #[name = "320 kbps"]
+    Kbps320,
+}
+
+impl Bitrate {
+    pub(crate) const fn from_kbps(kbps: u32) -> Option<Self> {
+        match kbps {
+            96 => Some(Self::Kbps96),
+            160 => Some(Self::Kbps160),
+            320 => Some(Self::Kbps320),
+            _ => None,
+        }
+    }
+
+    pub(crate) const fn kbps(self) -> u32 {
+        match self {
+            Self::Kbps96 => 96,
+            Self::Kbps160 => 160,
+            Self::Kbps320 => 320,
+        }
+    }
+}
+
+/// Per-guild Spotify playback settings, applied when a new Spotify session is created
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Settings {
+    /// Audio quality of the stream
+    pub(crate) bitrate: Bitrate,
+    /// Whether librespot should normalise the volume across tracks
+    pub(crate) normalisation: bool,
+    /// Pregain in dB applied by the normalisation
+    pub(crate) normalisation_pregain_db: f64,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            bitrate: Bitrate::default(),
+            normalisation: false,
+            normalisation_pregain_db: 0.0,
+        }
+    }
+}
+
+impl From<Settings> for PlayerConfig {
+    fn from(settings: Settings) -> Self {
+        Self {
+            bitrate: match settings.bitrate {
+                Bitrate::Kbps96 => librespot::playback::config::Bitrate::Bitrate96,
+                Bitrate::Kbps160 => librespot::playback::config::Bitrate::Bitrate160,
+                Bitrate::Kbps320 => librespot::playback::config::Bitrate::Bitrate320,
+            },
+            normalisation: settings.normalisation,
+            normalisation_pregain_db: settings.normalisation_pregain_db,
+            // Treat each track as a separate one in the songbird queue
+            gapless: false,
+            ..Default::default()
+        }
+    }
+}
+
+/// Owner of the Spotify account credentials
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum CredentialsOwner {
+    /// Account connected to the whole guild, used when the requester has no linked account
+    Guild(GuildId),
+    /// Account linked by a Discord user, used for tracks requested by this user
+    User(UserId),
+}
+
+/// Spotify player of the account along with the guild it streams to
+#[derive(Clone)]
+struct ActivePlayer {
+    player: SharedPlayer,
+    /// Owner of the credentials used by the player
+    owner: CredentialsOwner,
+    /// Guild the account streams to. Spotify allows only one stream per account at a time
+    guild_id: GuildId,
 }
 
 /// Spotify players manager, responsible for handling player's lifetime and storing credentials
 pub(crate) struct Resolver {
-    /// Active Spotify players
-    players: RwLock<HashMap<GuildId, Player>>,
+    /// Active Spotify players by Spotify username
+    players: RwLock<HashMap<String, ActivePlayer>>,
     /// Spotify credentials storage
     storage: Arc<dyn CredentialsStorage>,
+    /// Spotify playback settings storage
+    settings_storage: Arc<dyn SettingsStorage>,
 }
 
 impl Resolver {
-    pub(crate) fn new(storage: Arc<dyn CredentialsStorage>) -> Self {
+    pub(crate) fn new(
+        storage: Arc<dyn CredentialsStorage>,
+        settings_storage: Arc<dyn SettingsStorage>,
+    ) -> Self {
         Self {
             players: RwLock::new(HashMap::new()),
             storage,
+            settings_storage,
         }
     }
 
-    /// Connects to Spotify with provided username and password for the provided guild.
+    /// Connects to Spotify with provided username and password for the provided owner,
+    /// initiated from the provided guild.
     /// Credentials can be obtained at https://www.spotify.com/us/account/set-device-password/
+    /// Fails with [`Error::AccountBusy`] if the account is streaming to another guild.
     pub(crate) async fn connect(
         &self,
+        owner: CredentialsOwner,
         guild_id: GuildId,
         username: String,
         password: String,
     ) -> Result<(), anyhow::Error> {
-        let player = Player::new(username.clone(), password.clone()).await?;
-        self.storage.save(guild_id, &username, &password)?;
-        self.players.write().await.insert(guild_id, player);
+        // Don't interrupt the account if it is already streaming somewhere else
+        let busy = self
+            .players
+            .read()
+            .await
+            .get(&username)
+            .is_some_and(|active| active.guild_id != guild_id);
+        if busy {
+            return Err(Error::AccountBusy.into());
+        }
+
+        let settings = self.settings(guild_id).await;
+        let player = Player::new(username.clone(), password.clone(), settings).await?;
+        self.storage.save(owner, &username, &password).await?;
+        self.install(owner, guild_id, username, player).await;
         Ok(())
     }
 
-    /// Resolves a Spotify canonical URI or URL to Spotify to a track, album or playlist
+    /// Removes the Spotify account linked by the user
+    pub(crate) async fn unlink(&self, user_id: UserId) -> Result<(), anyhow::Error> {
+        let owner = CredentialsOwner::User(user_id);
+        self.storage.remove(owner).await?;
+        // Already queued tracks keep their player, so they can still be played
+        self.players
+            .write()
+            .await
+            .retain(|_, active| active.owner != owner);
+        Ok(())
+    }
+
+    /// Resolves a Spotify canonical URI or URL to Spotify to a track, album or playlist.
+    /// Account linked by the requester is preferred over the one connected to the guild.
+    /// Returns `None` if the query is not a Spotify one.
+    ///
     /// Example URIs:
     /// - track - `spotify:track:6rqhFgbbKwnb9MLmUQDhG6`
     /// - album - `spotify:album:6G9fHYDCoyEErUkHrFYfs4`
     /// - playlist - `spotify:playlist:37i9dQZF1DXcBWIGoYBM5M`
+    ///
     /// Example URLs:
     /// - track - `https://open.spotify.com/track/6rqhFgbbKwnb9MLmUQDhG6`
     /// - album - `https://open.spotify.com/album/6G9fHYDCoyEErUkHrFYfs4`
@@ -87,29 +231,216 @@ impl Resolver {
     pub(crate) async fn resolve(
         &self,
         guild_id: GuildId,
+        requester: Option<UserId>,
         query: &str,
-    ) -> Option<SmallVec<[Track; 1]>> {
+    ) -> Option<Result<SmallVec<[Track; 1]>, Error>> {
         // Parse Spotify ID first to avoid unnecessary requests if it is something else
         let spotify_id = parse_spotify_id(query)?;
 
-        // Cloning the player is cheap as it's just bunch of Arcs.
-        // Perform separate read and write locks to avoid deadlocks
-        let player = self.players.read().await.get(&guild_id).cloned();
-        let player = match player {
-            Some(player) => player,
-            None => {
-                let (username, password) = self.storage.load(guild_id)?;
-                let player = Player::new(username, password).await.ok()?;
-                self.players.write().await.insert(guild_id, player.clone());
-                player
+        let (username, player) = match self.player(guild_id, requester).await {
+            Ok(player) => player,
+            Err(err) => return Some(Err(err)),
+        };
+        let result = match player.fetch(spotify_id).await {
+            // Session might have been dropped by Spotify in the meantime, so try once again with a fresh one
+            Err(Error::SessionExpired) => {
+                info!("Spotify session of '{username}' has expired, reconnecting");
+                match self.restore_session(&username).await {
+                    Ok(()) => player.fetch(spotify_id).await,
+                    Err(err) => Err(err),
+                }
             }
+            result => result,
         };
-        Some(player.fetch(spotify_id).await)
+        Some(result)
     }
 
-    /// Handles bot disconnection from the voice channel
+    /// Resolves a few tracks similar to the provided Spotify track using Spotify radio.
+    /// Returns `None` if the seed is not a Spotify track.
+    pub(crate) async fn recommend(
+        &self,
+        guild_id: GuildId,
+        seed: &str,
+        count: usize,
+    ) -> Option<Result<SmallVec<[Track; 1]>, Error>> {
+        let seed_id = parse_spotify_id(seed).filter(|id| id.item_type == SpotifyItemType::Track)?;
+
+        let result = async {
+            // Prefer the account that already streams to this guild
+            let active = self
+                .players
+                .read()
+                .await
+                .values()
+                .find(|active| active.guild_id == guild_id && active.player.is_valid())
+                .map(|active| active.player.clone());
+            let player = match active {
+                Some(player) => player,
+                None => self.player(guild_id, None).await?.1,
+            };
+
+            let radio_id = player.get().radio(seed_id).await?;
+            let mut tracks = player.fetch(radio_id).await?;
+            // Radio usually starts with the seed track itself
+            tracks.retain(|track| track.id != seed_id);
+            tracks.truncate(count);
+            Ok(tracks)
+        };
+        Some(result.await)
+    }
+
+    /// Returns playback settings of the guild
+    pub(crate) async fn settings(&self, guild_id: GuildId) -> Settings {
+        self.settings_storage
+            .load(guild_id)
+            .await
+            .unwrap_or_default()
+    }
+
+    /// Saves playback settings of the guild. They are applied with the next Spotify session,
+    /// as replacing the active player would interrupt the current track.
+    pub(crate) async fn update_settings(
+        &self,
+        guild_id: GuildId,
+        settings: Settings,
+    ) -> Result<(), anyhow::Error> {
+        self.settings_storage.save(guild_id, &settings).await
+    }
+
+    /// Returns (guild, Spotify username) pairs of active players whose sessions were invalidated by Spotify
+    pub(crate) async fn invalidated_sessions(&self) -> Vec<(GuildId, String)> {
+        self.players
+            .read()
+            .await
+            .iter()
+            .filter(|(_, active)| !active.player.is_valid())
+            .map(|(username, active)| (active.guild_id, username.clone()))
+            .collect()
+    }
+
+    /// Re-establishes Spotify session of the account using stored credentials.
+    /// On failure the player is dropped, as there is no point to retry until the account is reconnected.
+    pub(crate) async fn restore_session(&self, username: &str) -> Result<(), Error> {
+        let Some(active) = self.players.read().await.get(username).cloned() else {
+            return Err(Error::NotConnected);
+        };
+
+        let settings = self.settings(active.guild_id).await;
+        let player = match self.storage.load(active.owner).await {
+            // Credentials might have been changed or removed since the player was created
+            Some((stored_username, password)) if stored_username == username => {
+                Player::new(stored_username, password, settings)
+                    .await
+                    .map_err(|err| {
+                        warn!("Failed to reconnect to Spotify as '{username}': {err:#}");
+                        login_error(&err)
+                    })
+            }
+            _ => Err(Error::NotConnected),
+        };
+
+        match player {
+            Ok(player) => {
+                active.player.replace(player);
+                Ok(())
+            }
+            Err(err) => {
+                self.players.write().await.remove(username);
+                Err(err)
+            }
+        }
+    }
+
+    /// Re-creates the track that was playing when the account's session was invalidated.
+    /// Returns `None` if the account was playing something else.
+    pub(crate) async fn reload(&self, username: &str, source_url: &str) -> Option<Track> {
+        let id = parse_spotify_id(source_url)?;
+        let player = self.players.read().await.get(username)?.player.clone();
+        if player.current() != Some(id) {
+            return None;
+        }
+        player.fetch(id).await.ok()?.pop()
+    }
+
+    /// Returns the Spotify username and a player for the request in the guild,
+    /// (re)connecting to Spotify if there is no valid session
+    async fn player(
+        &self,
+        guild_id: GuildId,
+        requester: Option<UserId>,
+    ) -> Result<(String, SharedPlayer), Error> {
+        let owners = requester
+            .map(CredentialsOwner::User)
+            .into_iter()
+            .chain([CredentialsOwner::Guild(guild_id)]);
+
+        let mut busy = false;
+        for owner in owners {
+            let Some((username, password)) = self.storage.load(owner).await else {
+                continue;
+            };
+
+            // Perform separate read and write locks to avoid deadlocks
+            let active = self.players.read().await.get(&username).cloned();
+            match active {
+                Some(active) if active.guild_id != guild_id => {
+                    busy = true;
+                }
+                Some(active) if active.player.is_valid() => return Ok((username, active.player)),
+                _ => {
+                    let settings = self.settings(guild_id).await;
+                    let player = Player::new(username.clone(), password, settings)
+                        .await
+                        .map_err(|err| {
+                            warn!("Failed to connect to Spotify as '{username}': {err:#}");
+                            login_error(&err)
+                        })?;
+                    let player = self
+                        .install(owner, guild_id, username.clone(), player)
+                        .await;
+                    return Ok((username, player));
+                }
+            }
+        }
+        Err(if busy {
+            Error::AccountBusy
+        } else {
+            Error::NotConnected
+        })
+    }
+
+    /// Makes the player active for the account. Already resolved tracks switch to the new player as well
+    async fn install(
+        &self,
+        owner: CredentialsOwner,
+        guild_id: GuildId,
+        username: String,
+        player: Player,
+    ) -> SharedPlayer {
+        let mut players = self.players.write().await;
+        if let Some(active) = players.get_mut(&username) {
+            active.player.replace(player);
+            active.owner = owner;
+            active.guild_id = guild_id;
+            active.player.clone()
+        } else {
+            let shared = SharedPlayer::new(player);
+            let active = ActivePlayer {
+                player: shared.clone(),
+                owner,
+                guild_id,
+            };
+            players.insert(username, active);
+            shared
+        }
+    }
+
+    /// Handles bot disconnection from the voice channel, releasing accounts that streamed to the guild
     pub(crate) async fn disconnect(&self, guild_id: GuildId) {
-        self.players.write().await.remove(&guild_id);
+        self.players
+            .write()
+            .await
+            .retain(|_, active| active.guild_id != guild_id);
     }
 }
 
@@ -128,7 +459,11 @@ struct Player {
 }
 
 impl Player {
-    async fn new(username: String, password: String) -> Result<Self, anyhow::Error> {
+    async fn new(
+        username: String,
+        password: String,
+        settings: Settings,
+    ) -> Result<Self, anyhow::Error> {
         let device_id = hex::encode(Sha1::digest(username.as_bytes()));
 
         let credentials = Credentials::with_password(username, password);
@@ -147,11 +482,7 @@ impl Player {
         let (track_channels_tx, track_channels_rx) = flume::unbounded();
 
         let player = librespot::playback::player::Player::new(
-            PlayerConfig {
-                // Treat each track as a separate one in the songbird queue
-                gapless: false,
-                ..Default::default()
-            },
+            settings.into(),
             session.clone(),
             Box::new(NoOpVolume),
             move || Box::new(MediaSink::new(track_channels_rx)),
@@ -164,49 +495,161 @@ impl Player {
         })
     }
 
-    /// Resolves a Spotify canonical URI or URL to Spotify to a track, album or playlist
-    /// Example URIs:
-    /// - track - `spotify:track:6rqhFgbbKwnb9MLmUQDhG6`
-    /// - album - `spotify:album:6G9fHYDCoyEErUkHrFYfs4`
-    /// - playlist - `spotify:playlist:37i9dQZF1DXcBWIGoYBM5M`
-    /// Example URLs:
-    /// - track - `https://open.spotify.com/track/6rqhFgbbKwnb9MLmUQDhG6`
-    /// - album - `https://open.spotify.com/album/6G9fHYDCoyEErUkHrFYfs4`
-    /// - playlist - `https://open.spotify.com/playlist/37i9dQZF1DXcBWIGoYBM5M`
-    async fn fetch(&self, id: SpotifyId) -> SmallVec<[Track; 1]> {
+    /// Resolves a Spotify ID of a track, album or playlist to a list of playable track IDs with metadata.
+    /// Tracks that are not available in the account's country are skipped.
+    async fn fetch(
+        &self,
+        id: SpotifyId,
+    ) -> Result<SmallVec<[(SpotifyId, track_info::Metadata); 1]>, Error> {
         let begin = std::time::Instant::now();
-        let tracks: SmallVec<[_; 1]> = match id.item_type {
+        let ids: SmallVec<[_; 1]> = match id.item_type {
             SpotifyItemType::Track => smallvec![id],
             SpotifyItemType::Album => {
-                let album = metadata::Album::get(&self.session, &id).await.unwrap();
+                let album = metadata::Album::get(&self.session, &id)
+                    .await
+                    .map_err(|err| self.error(err))?;
                 album.tracks().copied().collect()
             }
             SpotifyItemType::Playlist => {
-                let playlist = metadata::Playlist::get(&self.session, &id).await.unwrap();
+                let playlist = metadata::Playlist::get(&self.session, &id)
+                    .await
+                    .map_err(|err| self.error(err))?;
                 playlist.tracks().copied().collect()
             }
-            _ => Default::default(),
+            _ => return Err(Error::Unsupported),
         };
+        if ids.is_empty() {
+            return Err(Error::NotFound);
+        }
 
-        let tracks = stream::iter(tracks)
+        let country = self.session.country();
+        let results = stream::iter(ids)
             .map(|id| async move { metadata::Track::get(&self.session, &id).await })
             .buffered(16)
-            .filter_map(|result| async { result.ok() })
-            .map(|track| Track {
-                id: track.id,
-                player: self.player.clone(),
-                track_channels: self.track_channels.clone(),
-                metadata: extract_metadata(&track),
-            })
-            .collect::<SmallVec<_>>()
+            .collect::<Vec<_>>()
             .await;
+
+        let mut tracks = SmallVec::new();
+        let mut first_error = None;
+        for result in results {
+            match result {
+                Ok(track) if is_available_in(&track.restrictions, &country) => {
+                    tracks.push((track.id, extract_metadata(&track)));
+                }
+                Ok(_) => {
+                    first_error.get_or_insert(Error::RegionLocked);
+                }
+                Err(err) => {
+                    first_error.get_or_insert_with(|| self.error(err));
+                }
+            }
+        }
         info!(
             "Resolved {id} into {} tracks in {}ms",
             tracks.len(),
             begin.elapsed().as_millis()
         );
 
-        tracks
+        // Report an error only if nothing can be played, as albums and playlists might be partially available
+        match first_error {
+            Some(err) if tracks.is_empty() => Err(err),
+            _ => Ok(tracks),
+        }
+    }
+
+    /// Returns ID of the radio playlist seeded by the provided track
+    async fn radio(&self, seed: SpotifyId) -> Result<SpotifyId, Error> {
+        let response = self
+            .session
+            .spclient()
+            .get_radio_for_track(&seed)
+            .await
+            .map_err(|err| self.error(err))?;
+        serde_json::from_slice::<RadioResponse>(&response)
+            .inspect_err(|err| warn!("Failed to parse Spotify radio response: {err}"))
+            .ok()
+            .and_then(|radio| radio.media_items.into_iter().next())
+            .and_then(|item| SpotifyId::from_uri(&item.uri).ok())
+            .ok_or(Error::NotFound)
+    }
+
+    /// Converts a librespot error into [`Error`], taking the session state into account
+    fn error(&self, err: librespot::core::Error) -> Error {
+        if self.session.is_invalid() {
+            Error::SessionExpired
+        } else {
+            err.into()
+        }
+    }
+}
+
+/// Response of the Spotify radio endpoint, referring to the generated playlist
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RadioResponse {
+    media_items: Vec<RadioMediaItem>,
+}
+
+#[derive(Deserialize)]
+struct RadioMediaItem {
+    /// Spotify URI of the radio playlist
+    uri: String,
+}
+
+/// A handle to the account's [`Player`], shared with all the tracks resolved by it.
+/// It allows to replace the player after reconnect without touching already queued tracks.
+#[derive(Clone)]
+struct SharedPlayer {
+    player: Arc<std::sync::RwLock<Player>>,
+    /// The last track loaded to the player, i.e. the one that is playing now
+    current: Arc<std::sync::Mutex<Option<SpotifyId>>>,
+}
+
+impl SharedPlayer {
+    fn new(player: Player) -> Self {
+        Self {
+            player: Arc::new(std::sync::RwLock::new(player)),
+            current: Default::default(),
+        }
+    }
+
+    /// Returns the current player. Cloning the player is cheap as it's just bunch of Arcs
+    fn get(&self) -> Player {
+        self.player.read().unwrap().clone()
+    }
+
+    fn replace(&self, player: Player) {
+        *self.player.write().unwrap() = player;
+    }
+
+    /// Checks whether the player's session is still alive
+    fn is_valid(&self) -> bool {
+        !self.player.read().unwrap().session.is_invalid()
+    }
+
+    /// Returns the track that was loaded the last
+    fn current(&self) -> Option<SpotifyId> {
+        *self.current.lock().unwrap()
+    }
+
+    /// Starts playing the track from the provided position
+    fn load(&self, id: SpotifyId, position_ms: u32) {
+        self.get().player.load(id, true, position_ms);
+        *self.current.lock().unwrap() = Some(id);
+    }
+
+    /// Resolves a Spotify ID of a track, album or playlist to a list of playable tracks
+    async fn fetch(&self, id: SpotifyId) -> Result<SmallVec<[Track; 1]>, Error> {
+        let tracks = self.get().fetch(id).await?;
+        Ok(tracks
+            .into_iter()
+            .map(|(id, metadata)| Track {
+                id,
+                player: self.clone(),
+                position_ms: 0,
+                metadata,
+            })
+            .collect())
     }
 }
 
@@ -374,10 +817,10 @@ impl MediaSource for MediaStream {
 pub(crate) struct Track {
     /// Spotify ID of the track
     id: SpotifyId,
-    /// Inner Spotify player
-    player: Arc<player::Player>,
-    /// A channel to establish a separate connection between Spotify player and songbird for each track
-    track_channels: flume::Sender<ByteSink>,
+    /// Spotify player of the account used to resolve the track
+    player: SharedPlayer,
+    /// Position in milliseconds to start playback from
+    position_ms: u32,
     /// Track metadata
     metadata: track_info::Metadata,
 }
@@ -387,6 +830,12 @@ impl Track {
     pub(crate) const fn metadata(&self) -> &track_info::Metadata {
         &self.metadata
     }
+
+    /// Makes the track start playing from the provided position instead of the beginning
+    pub(crate) fn starting_at(mut self, position: std::time::Duration) -> Self {
+        self.position_ms = position.as_millis().try_into().unwrap_or(u32::MAX);
+        self
+    }
 }
 
 impl From<Track> for Input {
@@ -410,8 +859,9 @@ impl Compose for Track {
     ) -> Result<AudioStream<Box<dyn MediaSource>>, AudioStreamError> {
         // MediaStream should be created before the player starts playing the track to avoid possible race condition,
         // as the corresponding track byte channel should be created before the player starts playing the track
-        let stream = MediaStream::new(&self.track_channels).ok_or(AudioStreamError::Unsupported)?;
-        self.player.load(self.id, true, 0);
+        let stream = MediaStream::new(&self.player.get().track_channels)
+            .ok_or(AudioStreamError::Unsupported)?;
+        self.player.load(self.id, self.position_ms);
 
         Ok(AudioStream {
             input: Box::new(stream),
@@ -424,6 +874,89 @@ impl Compose for Track {
     }
 }
 
+/// Reasons why a Spotify item could not be resolved
+#[derive(Debug)]
+pub(crate) enum Error {
+    /// Neither the requester nor the guild has a Spotify account connected
+    NotConnected,
+    /// All suitable Spotify accounts are streaming to other guilds.
+    /// Spotify allows only one stream per account at a time
+    AccountBusy,
+    /// Spotify session has expired and couldn't be re-established with the stored credentials
+    SessionExpired,
+    /// Spotify rejected the stored credentials, e.g. the password was changed
+    LoginFailed,
+    /// The item is private or the account has no access to it
+    Private,
+    /// The item is not available in the account's country
+    RegionLocked,
+    /// The item doesn't exist
+    NotFound,
+    /// The item type is not supported, e.g. artists or podcasts
+    Unsupported,
+    /// Any other failure, e.g. network issues on the Spotify side
+    Other(librespot::core::Error),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotConnected => write!(f, "no Spotify account is connected"),
+            Self::AccountBusy => write!(f, "Spotify account is busy in another server"),
+            Self::SessionExpired => write!(f, "Spotify session has expired"),
+            Self::LoginFailed => write!(f, "Spotify rejected the account credentials"),
+            Self::Private => write!(f, "the item is private"),
+            Self::RegionLocked => write!(f, "the item is not available in this region"),
+            Self::NotFound => write!(f, "the item is not found"),
+            Self::Unsupported => write!(f, "only tracks, albums and playlists are supported"),
+            Self::Other(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<librespot::core::Error> for Error {
+    fn from(err: librespot::core::Error) -> Self {
+        use librespot::core::error::ErrorKind;
+
+        match err.kind {
+            ErrorKind::NotFound => Self::NotFound,
+            ErrorKind::PermissionDenied => Self::Private,
+            ErrorKind::Unauthenticated => Self::SessionExpired,
+            _ => Self::Other(err),
+        }
+    }
+}
+
+/// Converts a failure to log in while creating a player into [`Error`]
+fn login_error(err: &anyhow::Error) -> Error {
+    use librespot::core::error::ErrorKind;
+
+    match err
+        .downcast_ref::<librespot::core::Error>()
+        .map(|err| err.kind)
+    {
+        Some(ErrorKind::PermissionDenied | ErrorKind::Unauthenticated) => Error::LoginFailed,
+        _ => Error::SessionExpired,
+    }
+}
+
+/// Checks whether the item with provided restrictions can be played in the provided country
+fn is_available_in(restrictions: &[metadata::restriction::Restriction], country: &str) -> bool {
+    restrictions.iter().all(|restriction| {
+        let allowed = restriction
+            .countries_allowed
+            .as_ref()
+            .is_none_or(|allowed| allowed.iter().any(|c| c == country));
+        let forbidden = restriction
+            .countries_forbidden
+            .as_ref()
+            .is_some_and(|forbidden| forbidden.iter().any(|c| c == country));
+        allowed && !forbidden
+    })
+}
+
 /// Parses Spotify URI or URL and returns [`SpotifyId`] if possible
 fn parse_spotify_id(src: &str) -> Option<SpotifyId> {
     if let Some(remaining) = src.strip_prefix("https://open.spotify.com/") {
@@ -576,6 +1109,108 @@ mod tests {
         // invalid Spotify URL
     }
 
+    #[test]
+    fn radio_response_test() {
+        let response = br#"{
+            "total": 1,
+            "mediaItems": [{"uri": "spotify:playlist:37i9dQZF1E8UXBoz02kGID"}]
+        }"#;
+        let radio: RadioResponse = serde_json::from_slice(response).unwrap();
+        assert_eq!(radio.media_items.len(), 1);
+        assert_eq!(
+            SpotifyId::from_uri(&radio.media_items[0].uri).unwrap(),
+            parse_spotify_id("spotify:playlist:37i9dQZF1E8UXBoz02kGID").unwrap()
+        );
+    }
+
+    #[test]
+    fn bitrate_test() {
+        for bitrate in [Bitrate::Kbps96, Bitrate::Kbps160, Bitrate::Kbps320] {
+            assert_eq!(Bitrate::from_kbps(bitrate.kbps()), Some(bitrate));
+        }
+        assert_eq!(Bitrate::from_kbps(0), None);
+        assert_eq!(Bitrate::from_kbps(128), None);
+
+        // Default bitrate should match librespot's one
+        let config = PlayerConfig::from(Settings::default());
+        assert_eq!(
+            config.bitrate,
+            librespot::playback::config::Bitrate::default()
+        );
+        assert!(!config.gapless);
+    }
+
+    #[test]
+    fn error_from_librespot_test() {
+        use librespot::core::Error as LibrespotError;
+
+        assert!(matches!(
+            Error::from(LibrespotError::not_found("404")),
+            Error::NotFound
+        ));
+        assert!(matches!(
+            Error::from(LibrespotError::permission_denied("403")),
+            Error::Private
+        ));
+        assert!(matches!(
+            Error::from(LibrespotError::unauthenticated("401")),
+            Error::SessionExpired
+        ));
+        assert!(matches!(
+            Error::from(LibrespotError::unavailable("503")),
+            Error::Other(_)
+        ));
+    }
+
+    #[test]
+    fn login_error_test() {
+        use librespot::core::Error as LibrespotError;
+
+        let err = anyhow::Error::from(LibrespotError::permission_denied("bad credentials"))
+            .context("Session connection failed");
+        assert!(matches!(login_error(&err), Error::LoginFailed));
+
+        let err = anyhow::Error::from(LibrespotError::unavailable("no connection"))
+            .context("Session connection failed");
+        assert!(matches!(login_error(&err), Error::SessionExpired));
+    }
+
+    #[test]
+    fn is_available_in_test() {
+        use librespot::metadata::restriction::{Restriction, RestrictionCatalogues};
+
+        let restriction = |allowed: Option<&[&str]>, forbidden: Option<&[&str]>| Restriction {
+            catalogues: RestrictionCatalogues(vec![]),
+            restriction_type: Default::default(),
+            catalogue_strs: vec![],
+            countries_allowed: allowed.map(|c| c.iter().map(|c| c.to_string()).collect()),
+            countries_forbidden: forbidden.map(|c| c.iter().map(|c| c.to_string()).collect()),
+        };
+
+        // No restrictions at all
+        assert!(is_available_in(&[], "DE"));
+
+        // Whitelist
+        let restrictions = [restriction(Some(&["DE", "PL"]), None)];
+        assert!(is_available_in(&restrictions, "DE"));
+        assert!(is_available_in(&restrictions, "PL"));
+        assert!(!is_available_in(&restrictions, "US"));
+
+        // Blacklist
+        let restrictions = [restriction(None, Some(&["US"]))];
+        assert!(is_available_in(&restrictions, "DE"));
+        assert!(!is_available_in(&restrictions, "US"));
+
+        // All restrictions should be satisfied
+        let restrictions = [
+            restriction(Some(&["DE", "US"]), None),
+            restriction(None, Some(&["US"])),
+        ];
+        assert!(is_available_in(&restrictions, "DE"));
+        assert!(!is_available_in(&restrictions, "US"));
+        assert!(!is_available_in(&restrictions, "PL"));
+    }
+
     #[test]
     fn media_sink_test() {
         let (track_channels_tx, track_channels_rx) = flume::unbounded();
@@ -599,7 +1234,7 @@ mod tests {
         assert!(sink.start().is_ok());
         assert!(sink
             .write(
-                AudioPacket::Samples(vec![1.0; 16].into()),
+                AudioPacket::Samples(vec![1.0; 16]),
                 &mut Converter::new(None)
             )
             .is_ok());
@@ -607,7 +1242,7 @@ mod tests {
         drop(stream);
         assert!(sink
             .write(
-                AudioPacket::Samples(vec![0.0; 16].into()),
+                AudioPacket::Samples(vec![0.0; 16]),
                 &mut Converter::new(None)
             )
             .is_err());
@@ -619,7 +1254,7 @@ mod tests {
         assert!(sink.start().is_ok());
         assert!(sink
             .write(
-                AudioPacket::Samples(vec![1.0; 16].into()),
+                AudioPacket::Samples(vec![1.0; 16]),
                 &mut Converter::new(None)
             )
             .is_ok());
@@ -627,7 +1262,7 @@ mod tests {
         // Sink is disconnected, write should fail now
         assert!(sink
             .write(
-                AudioPacket::Samples(vec![0.0; 16].into()),
+                AudioPacket::Samples(vec![0.0; 16]),
                 &mut Converter::new(None)
             )
             .is_err());
@@ -661,7 +1296,7 @@ mod tests {
         sink.start().unwrap();
         sink.write(
             // remember that we send f32 samples, each is 4 bytes long
-            AudioPacket::Samples(vec![1.0; 128].into()),
+            AudioPacket::Samples(vec![1.0; 128]),
             &mut Converter::new(None),
         )
         .unwrap();
@@ -679,7 +1314,7 @@ mod tests {
         let mut stream = MediaStream::new(&track_channels_tx).unwrap();
         sink.start().unwrap();
         sink.write(
-            AudioPacket::Samples(vec![0.0; 64].into()),
+            AudioPacket::Samples(vec![0.0; 64]),
             &mut Converter::new(None),
         )
         .unwrap();
@@ -698,12 +1333,12 @@ mod tests {
         assert_eq!(&buf[..8], b"SbirdRaw");
         sink.start().unwrap();
         sink.write(
-            AudioPacket::Samples(vec![0.0; 16].into()),
+            AudioPacket::Samples(vec![0.0; 16]),
             &mut Converter::new(None),
         )
         .unwrap();
         sink.write(
-            AudioPacket::Samples(vec![1.0; 16].into()),
+            AudioPacket::Samples(vec![1.0; 16]),
             &mut Converter::new(None),
         )
         .unwrap();
@@ -711,7 +1346,7 @@ mod tests {
         assert_eq!(stream.read(&mut buf).unwrap(), (16 + 16) * 4);
 
         sink.write(
-            AudioPacket::Samples(vec![1.0; 128].into()),
+            AudioPacket::Samples(vec![1.0; 128]),
             &mut Converter::new(None),
         )
         // read by portions
@@ -735,7 +1370,7 @@ mod tests {
                 let stream = MediaStream::new(&track_channels_tx).unwrap();
                 sink.start().unwrap();
                 sink.write(
-                    AudioPacket::Samples(vec![0.0; 16 + i].into()),
+                    AudioPacket::Samples(vec![0.0; 16 + i]),
                     &mut Converter::new(None),
                 )
                 .unwrap();
@@ -759,21 +1394,24 @@ mod tests {
         dotenv::dotenv().expect("Set up .env file for this test");
         let _ = tracing_subscriber::fmt::try_init();
 
-        let player = Player::new(
-            env::var("SPOTIFY_USERNAME").expect("Spotify username is not set"),
-            env::var("SPOTIFY_PASSWORD").expect("Spotify password is not set"),
-        )
-        .await
-        .unwrap();
+        let player = SharedPlayer::new(
+            Player::new(
+                env::var("SPOTIFY_USERNAME").expect("Spotify username is not set"),
+                env::var("SPOTIFY_PASSWORD").expect("Spotify password is not set"),
+                Settings::default(),
+            )
+            .await
+            .unwrap(),
+        );
 
         let mut tracks = player
             .fetch(parse_spotify_id("spotify:track:6rqhFgbbKwnb9MLmUQDhG6").unwrap())
-            .await;
+            .await
+            .unwrap();
         assert_eq!(tracks.len(), 1);
 
         let Input::Lazy(mut lazy) = Input::from(tracks.pop().unwrap()) else {
-            assert!(false, "Expected Lazy input");
-            return;
+            panic!("Expected Lazy input");
         };
         assert_eq!(lazy.should_create_async(), true);
         assert!(lazy.create().is_err());
@@ -789,11 +1427,11 @@ mod tests {
         // The next stream created via `play` + `create_async` should interrupt the previous one via empty read
         let mut tracks = player
             .fetch(parse_spotify_id("spotify:track:0X0q97XtaZHwJsYiDqyxWC").unwrap())
-            .await;
+            .await
+            .unwrap();
         assert_eq!(tracks.len(), 1);
         let Input::Lazy(mut lazy) = Input::from(tracks.pop().unwrap()) else {
-            assert!(false, "Expected Lazy input");
-            return;
+            panic!("Expected Lazy input");
         };
         let mut next_stream = lazy.create_async().await.unwrap();
 
@@ -828,18 +1466,21 @@ mod tests {
         dotenv::dotenv().expect("Set up .env file for this test");
         let _ = tracing_subscriber::fmt::try_init();
 
-        let player = Player::new(
-            env::var("SPOTIFY_USERNAME").expect("Spotify username is not set"),
-            env::var("SPOTIFY_PASSWORD").expect("Spotify password is not set"),
-        )
-        .await
-        .unwrap();
+        let player = SharedPlayer::new(
+            Player::new(
+                env::var("SPOTIFY_USERNAME").expect("Spotify username is not set"),
+                env::var("SPOTIFY_PASSWORD").expect("Spotify password is not set"),
+                Settings::default(),
+            )
+            .await
+            .unwrap(),
+        );
 
         let id = parse_spotify_id(
             "https://open.spotify.com/album/1bwbZJ6khPJyVpOaqgKsoZ?si=09ea457c18c54b88",
         )
         .unwrap();
-        let tracks = player.fetch(id).await;
+        let tracks = player.fetch(id).await.unwrap();
         assert!(!tracks.is_empty());
         for track in tracks {
             assert!(matches!(Input::from(track), Input::Lazy(_)));
@@ -852,16 +1493,19 @@ mod tests {
         dotenv::dotenv().expect("Set up .env file for this test");
         let _ = tracing_subscriber::fmt::try_init();
 
-        let player = Player::new(
-            env::var("SPOTIFY_USERNAME").expect("Spotify username is not set"),
-            env::var("SPOTIFY_PASSWORD").expect("Spotify password is not set"),
-        )
-        .await
-        .unwrap();
+        let player = SharedPlayer::new(
+            Player::new(
+                env::var("SPOTIFY_USERNAME").expect("Spotify username is not set"),
+                env::var("SPOTIFY_PASSWORD").expect("Spotify password is not set"),
+                Settings::default(),
+            )
+            .await
+            .unwrap(),
+        );
 
         let id =
             parse_spotify_id("https://open.spotify.com/playlist/37i9dQZF1DWZqd5JICZI0u").unwrap();
-        let tracks = player.fetch(id).await;
+        let tracks = player.fetch(id).await.unwrap();
         assert!(!tracks.is_empty());
         for track in tracks {
             assert!(matches!(Input::from(track), Input::Lazy(_)));
@@ -874,19 +1518,21 @@ mod tests {
         dotenv::dotenv().expect("Set up .env file for this test");
         let _ = tracing_subscriber::fmt::try_init();
 
-        let player = Player::new(
-            env::var("SPOTIFY_USERNAME").expect("Spotify username is not set"),
-            env::var("SPOTIFY_PASSWORD").expect("Spotify password is not set"),
-        )
-        .await
-        .unwrap();
+        let player = SharedPlayer::new(
+            Player::new(
+                env::var("SPOTIFY_USERNAME").expect("Spotify username is not set"),
+                env::var("SPOTIFY_PASSWORD").expect("Spotify password is not set"),
+                Settings::default(),
+            )
+            .await
+            .unwrap(),
+        );
 
-        let resolved_empty = [
+        let unsupported = [
             "spotify:unknown:1bwbZJ6khPJyVpOaqgKsoZ",
             "spotify:local:6rqhFgbbKwnb9MLmUQDhG6",
             "https://open.spotify.com/artist/0kq4QvLGV5t1ZoE6ittrLQ",
             "spotify:artist:0kq4QvLGV5t1ZoE6ittrLQ",
-            "spotify:track:0kq4QvLGV5t1ZoE6ittrLQ",
             // lex fridman podcast
             "spotify:show:2MAi0BvDc6GTFvKFPXnkCL",
             "https://open.spotify.com/show/2MAi0BvDc6GTFvKFPXnkCL?si=82e4b652d0de4dee",
@@ -895,9 +1541,13 @@ mod tests {
             "https://open.spotify.com/show/6bdZFtHJdaa1mGUa7LfbPZ?si=aedb61f9fa6f4c30",
         ];
 
-        for query in &resolved_empty {
+        for query in &unsupported {
             let id = parse_spotify_id(query).unwrap();
-            assert!(player.fetch(id).await.is_empty());
+            assert!(matches!(player.fetch(id).await, Err(Error::Unsupported)));
         }
+
+        // Artist ID is used as a track ID
+        let id = parse_spotify_id("spotify:track:0kq4QvLGV5t1ZoE6ittrLQ").unwrap();
+        assert!(matches!(player.fetch(id).await, Err(Error::NotFound)));
     }
 }
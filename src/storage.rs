@@ -1,98 +1,1276 @@
 use std::path::Path;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, mpsc};
+use std::time::Duration;
 
-#[cfg(feature = "spotify")]
-use serenity::all::GuildId;
+use async_trait::async_trait;
+use rusqlite::{OptionalExtension, functions::FunctionFlags};
+use serenity::all::{ChannelId, GuildId, RoleId, UserId};
+use tokio::sync::oneshot;
+use tracing::{debug, info};
 
+use crate::backup;
+use crate::blocklist;
+use crate::channels;
+use crate::guild_settings;
+use crate::history;
+use crate::playlist;
+use crate::saved_queue;
 #[cfg(feature = "spotify")]
 use crate::spotify;
+use crate::track_info;
 use crate::yt_dlp;
 
-pub(crate) struct Storage(Mutex<rusqlite::Connection>);
+/// Database schema migrations, applied in order. The schema version is stored in `PRAGMA user_version`
+/// and equals to the number of applied migrations, so new steps should only be appended to the end.
+/// The first 3 steps must stay idempotent, as databases created before versioning have `user_version = 0`
+/// with some of the tables already in place.
+const MIGRATIONS: &[&str] = &[
+    // 1: initial schema
+    "CREATE TABLE IF NOT EXISTS spotify_credentials (
+        guild_id INTEGER PRIMARY KEY,
+        username TEXT,
+        password TEXT
+    );
+    CREATE TABLE IF NOT EXISTS yt_dlp_queries (
+        query TEXT NOT NULL PRIMARY KEY,
+        webpage_url TEXT NOT NULL
+    );",
+    // 2: per-guild Spotify playback settings
+    "CREATE TABLE IF NOT EXISTS spotify_settings (
+        guild_id INTEGER PRIMARY KEY,
+        bitrate INTEGER NOT NULL,
+        normalisation INTEGER NOT NULL,
+        normalisation_pregain REAL NOT NULL
+    );",
+    // 3: Spotify accounts linked by users
+    "CREATE TABLE IF NOT EXISTS spotify_user_credentials (
+        user_id INTEGER PRIMARY KEY,
+        username TEXT,
+        password TEXT
+    );",
+    // 4: per-guild playlists
+    "CREATE TABLE playlists (
+        id INTEGER PRIMARY KEY,
+        guild_id INTEGER NOT NULL,
+        name TEXT NOT NULL,
+        UNIQUE (guild_id, name)
+    );
+    CREATE TABLE playlist_tracks (
+        playlist_id INTEGER NOT NULL REFERENCES playlists (id) ON DELETE CASCADE,
+        position INTEGER NOT NULL,
+        source_url TEXT NOT NULL,
+        title TEXT NOT NULL,
+        PRIMARY KEY (playlist_id, position)
+    );",
+    // 5: play history
+    "CREATE TABLE play_history (
+        id INTEGER PRIMARY KEY,
+        guild_id INTEGER NOT NULL,
+        source_url TEXT NOT NULL,
+        title TEXT NOT NULL,
+        requested_by TEXT NOT NULL,
+        started_at INTEGER NOT NULL,
+        played_secs INTEGER
+    );
+    CREATE INDEX play_history_guild_started_at ON play_history (guild_id, started_at);",
+    // 6: skipped tracks in play history
+    "ALTER TABLE play_history ADD COLUMN skipped INTEGER NOT NULL DEFAULT 0;",
+    // 7: queues to restore after restart
+    "CREATE TABLE saved_queues (
+        guild_id INTEGER PRIMARY KEY,
+        channel_id INTEGER NOT NULL,
+        current_position_ms INTEGER NOT NULL
+    );
+    CREATE TABLE saved_queue_tracks (
+        guild_id INTEGER NOT NULL REFERENCES saved_queues (guild_id) ON DELETE CASCADE,
+        position INTEGER NOT NULL,
+        source_url TEXT NOT NULL,
+        title TEXT NOT NULL,
+        requested_by TEXT NOT NULL,
+        volume REAL NOT NULL,
+        PRIMARY KEY (guild_id, position)
+    );",
+    // 8: yt-dlp query cache expiration and hit counts
+    "ALTER TABLE yt_dlp_queries ADD COLUMN created_at INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE yt_dlp_queries ADD COLUMN hits INTEGER NOT NULL DEFAULT 0;
+    -- Treat existing entries as fresh ones, so they don't expire all at once
+    UPDATE yt_dlp_queries SET created_at = unixepoch();",
+    // 9: per-guild settings
+    "CREATE TABLE guild_settings (
+        guild_id INTEGER PRIMARY KEY,
+        volume INTEGER NOT NULL,
+        bitrate INTEGER NOT NULL,
+        autoplay INTEGER NOT NULL,
+        leave_when_alone INTEGER NOT NULL,
+        radio_t INTEGER NOT NULL
+    );",
+    // 10: idle timeout in guild settings
+    "ALTER TABLE guild_settings ADD COLUMN idle_timeout_minutes INTEGER NOT NULL DEFAULT 5;",
+    // 11: DJ role in guild settings
+    "ALTER TABLE guild_settings ADD COLUMN dj_role_id INTEGER;",
+    // 12: vote skip in guild settings
+    "ALTER TABLE guild_settings ADD COLUMN vote_skip_percent INTEGER NOT NULL DEFAULT 0;",
+    // 13: per-user limits in guild settings
+    "ALTER TABLE guild_settings ADD COLUMN user_tracks_limit INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE guild_settings ADD COLUMN track_minutes_limit INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE guild_settings ADD COLUMN playlist_tracks_limit INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE guild_settings ADD COLUMN play_cooldown_secs INTEGER NOT NULL DEFAULT 0;",
+    // 14: blocklists
+    "CREATE TABLE blocklist (
+        guild_id INTEGER NOT NULL,
+        kind TEXT NOT NULL,
+        value TEXT NOT NULL,
+        PRIMARY KEY (guild_id, kind, value)
+    );",
+    // 15: channels the bot is restricted to
+    "CREATE TABLE allowed_channels (
+        guild_id INTEGER NOT NULL,
+        channel_id INTEGER NOT NULL,
+        kind TEXT NOT NULL,
+        PRIMARY KEY (guild_id, channel_id)
+    );",
+    // 16: voice-follow in guild settings
+    "ALTER TABLE guild_settings ADD COLUMN follow_requester INTEGER NOT NULL DEFAULT 0;",
+    // 17: normalized yt-dlp query cache keys, see `yt_dlp::normalize_query`.
+    // Of the queries that normalize to the same key, the most recently cached one is kept
+    "DELETE FROM yt_dlp_queries WHERE rowid NOT IN (
+        SELECT rowid FROM (
+            SELECT rowid, row_number() OVER (
+                PARTITION BY normalize_query(query) ORDER BY created_at DESC, hits DESC
+            ) AS place
+            FROM yt_dlp_queries
+        )
+        WHERE place = 1
+    );
+    UPDATE yt_dlp_queries SET query = normalize_query(query);",
+];
+
+/// A database request executed on the storage thread
+type Job = Box<dyn FnOnce(&mut rusqlite::Connection) + Send>;
+
+/// SQLite-backed storage. The connection is owned by a dedicated thread, so async code awaits
+/// database requests instead of blocking tokio workers on the disk IO.
+pub(crate) struct Storage {
+    jobs: mpsc::Sender<Job>,
+}
 
 impl Storage {
     pub(crate) fn new<P: AsRef<Path>>(db_path: P) -> Result<Arc<Self>, anyhow::Error> {
-        let db_conn = rusqlite::Connection::open(db_path)?;
-        db_conn.execute(
-            "CREATE TABLE IF NOT EXISTS spotify_credentials (
-                guild_id INTEGER PRIMARY KEY,
-                username TEXT,
-                password TEXT
-            )",
-            (),
-        )?;
-        db_conn.execute(
-            "CREATE TABLE IF NOT EXISTS yt_dlp_queries (
-                query TEXT NOT NULL PRIMARY KEY,
-                webpage_url TEXT NOT NULL
-            )",
-            (),
-        )?;
-        Ok(Arc::new(Self(Mutex::new(db_conn))))
+        let mut db_conn = rusqlite::Connection::open(db_path)?;
+        // WAL makes writes cheaper and doesn't block readers. In-memory databases stay in "memory" mode
+        let journal_mode: String =
+            db_conn.pragma_update_and_check(None, "journal_mode", "WAL", |row| row.get(0))?;
+        debug!("Database journal mode: {journal_mode}");
+        db_conn.pragma_update(None, "synchronous", "NORMAL")?;
+        // Required for `ON DELETE CASCADE`
+        db_conn.pragma_update(None, "foreign_keys", true)?;
+        migrate(&mut db_conn)?;
+
+        let (jobs, jobs_rx) = mpsc::channel::<Job>();
+        // The thread exits once the storage is dropped and all the pending jobs are done
+        std::thread::Builder::new()
+            .name("storage".into())
+            .spawn(move || {
+                for job in jobs_rx {
+                    job(&mut db_conn);
+                }
+            })?;
+        Ok(Arc::new(Self { jobs }))
+    }
+
+    /// Runs the request on the storage thread and waits for its result
+    async fn call<R, F>(&self, request: F) -> Result<R, anyhow::Error>
+    where
+        R: Send + 'static,
+        F: FnOnce(&mut rusqlite::Connection) -> rusqlite::Result<R> + Send + 'static,
+    {
+        let (result_tx, result_rx) = oneshot::channel();
+        self.jobs
+            .send(Box::new(move |db_conn| {
+                let _ = result_tx.send(request(db_conn));
+            }))
+            .map_err(|_| anyhow::anyhow!("Storage thread has stopped"))?;
+        Ok(result_rx.await??)
+    }
+}
+
+/// Brings the database schema to the latest version, applying each missing migration in its own transaction
+fn migrate(db_conn: &mut rusqlite::Connection) -> Result<(), anyhow::Error> {
+    let version: usize = db_conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    if version > MIGRATIONS.len() {
+        anyhow::bail!(
+            "Database schema version {version} is newer than the latest known {}",
+            MIGRATIONS.len()
+        );
+    }
+
+    add_functions(db_conn)?;
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let tx = db_conn.transaction()?;
+        tx.execute_batch(migration)?;
+        tx.pragma_update(None, "user_version", index + 1)?;
+        tx.commit()?;
+        info!("Migrated database schema to version {}", index + 1);
+    }
+    Ok(())
+}
+
+/// Makes the bot's functions available to migrations, as some can't be expressed in SQL
+fn add_functions(db_conn: &rusqlite::Connection) -> rusqlite::Result<()> {
+    db_conn.create_scalar_function(
+        "normalize_query",
+        1,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| Ok(yt_dlp::normalize_query(&ctx.get::<String>(0)?)),
+    )
+}
+
+/// Returns the table, its key column and the key value for the credentials owner
+#[cfg(feature = "spotify")]
+fn credentials_key(owner: spotify::CredentialsOwner) -> (&'static str, &'static str, i64) {
+    match owner {
+        spotify::CredentialsOwner::Guild(guild_id) => {
+            ("spotify_credentials", "guild_id", guild_id.get() as i64)
+        }
+        spotify::CredentialsOwner::User(user_id) => {
+            ("spotify_user_credentials", "user_id", user_id.get() as i64)
+        }
     }
 }
 
 #[cfg(feature = "spotify")]
+#[async_trait]
 impl spotify::CredentialsStorage for Storage {
-    fn save(&self, guild_id: GuildId, username: &str, password: &str) -> Result<(), anyhow::Error> {
-        self.0.lock().unwrap().execute(
-            "INSERT OR REPLACE INTO spotify_credentials (
-                guild_id, username, password
-            ) VALUES (?1, ?2, ?3)",
-            (guild_id.get() as i64, username, password),
-        )?;
+    async fn save(
+        &self,
+        owner: spotify::CredentialsOwner,
+        username: &str,
+        password: &str,
+    ) -> Result<(), anyhow::Error> {
+        let (table, key, id) = credentials_key(owner);
+        let (username, password) = (username.to_owned(), password.to_owned());
+        self.call(move |db| {
+            db.prepare_cached(&format!(
+                "INSERT OR REPLACE INTO {table} (
+                    {key}, username, password
+                ) VALUES (?1, ?2, ?3)"
+            ))?
+            .execute((id, username, password))
+        })
+        .await?;
         Ok(())
     }
 
-    fn load(&self, guild_id: GuildId) -> Option<(String, String)> {
-        let db = self.0.lock().unwrap();
-        let mut stmt = db
-            .prepare(
+    async fn load(&self, owner: spotify::CredentialsOwner) -> Option<(String, String)> {
+        let (table, key, id) = credentials_key(owner);
+        self.call(move |db| {
+            db.prepare_cached(&format!(
                 "SELECT username, password
-                    FROM spotify_credentials
+                    FROM {table}
+                    WHERE {key} = ?1"
+            ))?
+            .query_row([id], |row| Ok((row.get(0)?, row.get(1)?)))
+            .optional()
+        })
+        .await
+        .ok()?
+    }
+
+    async fn remove(&self, owner: spotify::CredentialsOwner) -> Result<(), anyhow::Error> {
+        let (table, key, id) = credentials_key(owner);
+        self.call(move |db| {
+            db.prepare_cached(&format!("DELETE FROM {table} WHERE {key} = ?1"))?
+                .execute([id])
+        })
+        .await?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "spotify")]
+#[async_trait]
+impl spotify::SettingsStorage for Storage {
+    async fn save(
+        &self,
+        guild_id: GuildId,
+        settings: &spotify::Settings,
+    ) -> Result<(), anyhow::Error> {
+        let settings = *settings;
+        self.call(move |db| {
+            db.prepare_cached(
+                "INSERT OR REPLACE INTO spotify_settings (
+                    guild_id, bitrate, normalisation, normalisation_pregain
+                ) VALUES (?1, ?2, ?3, ?4)",
+            )?
+            .execute((
+                guild_id.get() as i64,
+                settings.bitrate.kbps(),
+                settings.normalisation,
+                settings.normalisation_pregain_db,
+            ))
+        })
+        .await?;
+        Ok(())
+    }
+
+    async fn load(&self, guild_id: GuildId) -> Option<spotify::Settings> {
+        self.call(move |db| {
+            db.prepare_cached(
+                "SELECT bitrate, normalisation, normalisation_pregain
+                    FROM spotify_settings
                     WHERE guild_id = ?1",
-            )
-            .expect("Failed to prepare SELECT statement");
-        let mut rows = stmt
-            .query_map([guild_id.get() as i64], |row| {
-                Ok((row.get(0)?, row.get(1)?))
+            )?
+            .query_row([guild_id.get() as i64], |row| {
+                Ok(spotify::Settings {
+                    bitrate: spotify::Bitrate::from_kbps(row.get(0)?).unwrap_or_default(),
+                    normalisation: row.get(1)?,
+                    normalisation_pregain_db: row.get(2)?,
+                })
             })
-            .ok()?;
-        rows.next().transpose().ok()?
+            .optional()
+        })
+        .await
+        .ok()?
     }
 }
 
+#[async_trait]
 impl yt_dlp::QueryCache for Storage {
-    fn save(&self, query: &str, webpage_url: &str) -> Result<(), anyhow::Error> {
-        self.0.lock().unwrap().execute(
-            "INSERT OR REPLACE INTO yt_dlp_queries (
-                query, webpage_url
-            ) VALUES (?1, ?2)",
-            (query, webpage_url),
-        )?;
+    async fn save(&self, query: &str, webpage_url: &str) -> Result<(), anyhow::Error> {
+        let (query, webpage_url) = (query.to_owned(), webpage_url.to_owned());
+        self.call(move |db| {
+            db.prepare_cached(
+                "INSERT OR REPLACE INTO yt_dlp_queries (
+                    query, webpage_url, created_at, hits
+                ) VALUES (?1, ?2, unixepoch(), 0)",
+            )?
+            .execute((query, webpage_url))
+        })
+        .await?;
         Ok(())
     }
 
-    fn load(&self, query: &str) -> Option<String> {
-        let db = self.0.lock().unwrap();
-        let mut stmt = db
-            .prepare(
-                "SELECT webpage_url
+    async fn load(&self, query: &str, max_age: Duration) -> Option<String> {
+        let query = query.to_owned();
+        self.call(move |db| {
+            db.prepare_cached(
+                "UPDATE yt_dlp_queries
+                    SET hits = hits + 1
+                    WHERE query = ?1 AND unixepoch() - created_at <= ?2
+                    RETURNING webpage_url",
+            )?
+            .query_row((query, max_age.as_secs() as i64), |row| row.get(0))
+            .optional()
+        })
+        .await
+        .ok()?
+    }
+
+    async fn remove(&self, query: &str) -> Result<bool, anyhow::Error> {
+        let query = query.to_owned();
+        let removed = self
+            .call(move |db| {
+                db.prepare_cached("DELETE FROM yt_dlp_queries WHERE query = ?1")?
+                    .execute([query])
+            })
+            .await?;
+        Ok(removed > 0)
+    }
+
+    async fn stats(&self, max_age: Duration, limit: usize) -> yt_dlp::CacheStats {
+        self.call(move |db| {
+            let (entries, expired, hits) = db
+                .prepare_cached(
+                    "SELECT
+                        COUNT(*),
+                        COALESCE(SUM(unixepoch() - created_at > ?1), 0),
+                        COALESCE(SUM(hits), 0)
+                    FROM yt_dlp_queries",
+                )?
+                .query_row([max_age.as_secs() as i64], |row| {
+                    Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+                })?;
+            let top_queries = db
+                .prepare_cached(
+                    "SELECT query, hits
+                        FROM yt_dlp_queries
+                        WHERE hits > 0
+                        ORDER BY hits DESC, query
+                        LIMIT ?1",
+                )?
+                .query_map([limit as i64], |row| Ok((row.get(0)?, row.get(1)?)))?
+                .collect::<Result<_, _>>()?;
+            Ok(yt_dlp::CacheStats {
+                entries,
+                expired,
+                hits,
+                top_queries,
+            })
+        })
+        .await
+        .unwrap_or_default()
+    }
+
+    async fn load_all(&self, max_age: Duration) -> Vec<(String, String)> {
+        self.call(move |db| {
+            db.prepare_cached(
+                "SELECT query, webpage_url
                     FROM yt_dlp_queries
-                    WHERE query = ?1",
-            )
-            .expect("Failed to prepare SELECT statement");
-        let mut rows = stmt.query_map([query], |row| row.get(0)).ok()?;
-        rows.next().transpose().ok()?
+                    WHERE unixepoch() - created_at <= ?1
+                    ORDER BY query",
+            )?
+            .query_map([max_age.as_secs() as i64], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })?
+            .collect()
+        })
+        .await
+        .unwrap_or_default()
+    }
+}
+
+#[async_trait]
+impl playlist::PlaylistStorage for Storage {
+    async fn save(
+        &self,
+        guild_id: GuildId,
+        name: &str,
+        tracks: &[playlist::PlaylistTrack],
+    ) -> Result<(), anyhow::Error> {
+        let name = name.to_owned();
+        let tracks = tracks.to_vec();
+        self.call(move |db| {
+            let tx = db.transaction()?;
+            // Tracks of the replaced playlist are removed via `ON DELETE CASCADE`
+            tx.prepare_cached("DELETE FROM playlists WHERE guild_id = ?1 AND name = ?2")?
+                .execute((guild_id.get() as i64, &name))?;
+            let playlist_id: i64 = tx
+                .prepare_cached(
+                    "INSERT INTO playlists (guild_id, name) VALUES (?1, ?2) RETURNING id",
+                )?
+                .query_row((guild_id.get() as i64, &name), |row| row.get(0))?;
+            {
+                let mut stmt = tx.prepare_cached(
+                    "INSERT INTO playlist_tracks (
+                        playlist_id, position, source_url, title
+                    ) VALUES (?1, ?2, ?3, ?4)",
+                )?;
+                for (position, track) in tracks.iter().enumerate() {
+                    stmt.execute((playlist_id, position, &track.source_url, &track.title))?;
+                }
+            }
+            tx.commit()
+        })
+        .await?;
+        Ok(())
+    }
+
+    async fn load(&self, guild_id: GuildId, name: &str) -> Option<Vec<playlist::PlaylistTrack>> {
+        let name = name.to_owned();
+        self.call(move |db| {
+            let Some(playlist_id) = db
+                .prepare_cached("SELECT id FROM playlists WHERE guild_id = ?1 AND name = ?2")?
+                .query_row((guild_id.get() as i64, name), |row| row.get::<_, i64>(0))
+                .optional()?
+            else {
+                return Ok(None);
+            };
+            db.prepare_cached(
+                "SELECT source_url, title
+                    FROM playlist_tracks
+                    WHERE playlist_id = ?1
+                    ORDER BY position",
+            )?
+            .query_map([playlist_id], |row| {
+                Ok(playlist::PlaylistTrack {
+                    source_url: row.get(0)?,
+                    title: row.get(1)?,
+                })
+            })?
+            .collect::<Result<_, _>>()
+            .map(Some)
+        })
+        .await
+        .ok()?
+    }
+
+    async fn list(&self, guild_id: GuildId) -> Vec<(String, usize)> {
+        self.call(move |db| {
+            db.prepare_cached(
+                "SELECT name, COUNT(playlist_tracks.playlist_id)
+                    FROM playlists
+                    LEFT JOIN playlist_tracks ON playlist_tracks.playlist_id = playlists.id
+                    WHERE guild_id = ?1
+                    GROUP BY playlists.id
+                    ORDER BY name",
+            )?
+            .query_map([guild_id.get() as i64], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })?
+            .collect()
+        })
+        .await
+        .unwrap_or_default()
+    }
+
+    async fn delete(&self, guild_id: GuildId, name: &str) -> Result<bool, anyhow::Error> {
+        let name = name.to_owned();
+        let deleted = self
+            .call(move |db| {
+                db.prepare_cached("DELETE FROM playlists WHERE guild_id = ?1 AND name = ?2")?
+                    .execute((guild_id.get() as i64, name))
+            })
+            .await?;
+        Ok(deleted > 0)
     }
+}
 
-    fn load_all(&self) -> Vec<String> {
-        let db = self.0.lock().unwrap();
-        let mut stmt = db
-            .prepare("SELECT DISTINCT webpage_url FROM yt_dlp_queries")
-            .expect("Failed to prepare SELECT statement");
-        let rows = stmt.query_map([], |row| row.get(0)).ok();
-        rows.into_iter().flatten().flatten().collect()
+/// Stores the requester as the user id or `autoplay`
+fn requester_to_sql(requester: track_info::Requester) -> String {
+    match requester {
+        track_info::Requester::User(user_id) => user_id.to_string(),
+        track_info::Requester::Autoplay => "autoplay".into(),
+    }
+}
+
+/// Parses the stored requester. Tracks requested before user ids were stored have user names
+/// instead, which can't be parsed
+fn requester_from_sql(requested_by: &str) -> Option<track_info::Requester> {
+    match requested_by {
+        "autoplay" => Some(track_info::Requester::Autoplay),
+        _ => requested_by
+            .parse()
+            .ok()
+            .filter(|&id| id != 0)
+            .map(|id| track_info::Requester::User(UserId::new(id))),
+    }
+}
+
+/// Formats the stored requester for display, keeping user names of old records as they are
+fn display_requester(requested_by: String) -> String {
+    requester_from_sql(&requested_by).map_or(requested_by, |requester| requester.to_string())
+}
+
+#[async_trait]
+impl history::HistoryStorage for Storage {
+    async fn record_start(
+        &self,
+        guild_id: GuildId,
+        metadata: &track_info::Metadata,
+        requested_by: track_info::Requester,
+        started_at: u64,
+    ) -> Result<i64, anyhow::Error> {
+        let source_url = metadata.source_url.to_string();
+        let title = metadata.title.to_string();
+        let requested_by = requester_to_sql(requested_by);
+        let id = self
+            .call(move |db| {
+                db.prepare_cached(
+                    "INSERT INTO play_history (
+                        guild_id, source_url, title, requested_by, started_at
+                    ) VALUES (?1, ?2, ?3, ?4, ?5) RETURNING id",
+                )?
+                .query_row(
+                    (
+                        guild_id.get() as i64,
+                        source_url,
+                        title,
+                        requested_by,
+                        started_at as i64,
+                    ),
+                    |row| row.get(0),
+                )
+            })
+            .await?;
+        Ok(id)
+    }
+
+    async fn record_end(
+        &self,
+        id: i64,
+        played: Duration,
+        skipped: bool,
+    ) -> Result<(), anyhow::Error> {
+        self.call(move |db| {
+            db.prepare_cached(
+                "UPDATE play_history SET played_secs = ?2, skipped = ?3 WHERE id = ?1",
+            )?
+            .execute((id, played.as_secs() as i64, skipped))
+        })
+        .await?;
+        Ok(())
+    }
+
+    async fn recent(
+        &self,
+        guild_id: GuildId,
+        requested_by: Option<UserId>,
+        limit: usize,
+    ) -> Vec<history::HistoryEntry> {
+        let requested_by =
+            requested_by.map(|user_id| requester_to_sql(track_info::Requester::User(user_id)));
+        self.call(move |db| {
+            db.prepare_cached(
+                "SELECT source_url, title, requested_by, started_at, played_secs
+                    FROM play_history
+                    WHERE guild_id = ?1 AND (?2 IS NULL OR requested_by = ?2)
+                    ORDER BY started_at DESC, id DESC
+                    LIMIT ?3",
+            )?
+            .query_map((guild_id.get() as i64, requested_by, limit as i64), |row| {
+                Ok(history::HistoryEntry {
+                    source_url: row.get(0)?,
+                    title: row.get(1)?,
+                    requested_by: display_requester(row.get(2)?),
+                    started_at: row.get::<_, i64>(3)? as u64,
+                    played: row
+                        .get::<_, Option<i64>>(4)?
+                        .map(|secs| Duration::from_secs(secs as u64)),
+                })
+            })?
+            .collect()
+        })
+        .await
+        .unwrap_or_default()
+    }
+
+    async fn stats(&self, guild_id: GuildId, since: u64, limit: usize) -> history::Stats {
+        let params = (guild_id.get() as i64, since as i64, limit as i64);
+        let track_count = |row: &rusqlite::Row| {
+            Ok(history::TrackCount {
+                source_url: row.get(0)?,
+                title: row.get(1)?,
+                count: row.get(2)?,
+            })
+        };
+        self.call(move |db| {
+            let top_tracks = db
+                .prepare_cached(
+                    "SELECT source_url, MAX(title), COUNT(*) AS plays
+                        FROM play_history
+                        WHERE guild_id = ?1 AND started_at >= ?2
+                        GROUP BY source_url
+                        ORDER BY plays DESC, MAX(started_at) DESC
+                        LIMIT ?3",
+                )?
+                .query_map(params, track_count)?
+                .collect::<Result<_, _>>()?;
+            let top_requesters = db
+                .prepare_cached(
+                    "SELECT requested_by, COUNT(*) AS requests
+                        FROM play_history
+                        WHERE guild_id = ?1 AND started_at >= ?2
+                        GROUP BY requested_by
+                        ORDER BY requests DESC, requested_by
+                        LIMIT ?3",
+                )?
+                .query_map(params, |row| {
+                    Ok((display_requester(row.get(0)?), row.get(1)?))
+                })?
+                .collect::<Result<_, _>>()?;
+            let most_skipped = db
+                .prepare_cached(
+                    "SELECT source_url, MAX(title), COUNT(*) AS skips
+                        FROM play_history
+                        WHERE guild_id = ?1 AND started_at >= ?2 AND skipped
+                        GROUP BY source_url
+                        ORDER BY skips DESC, MAX(started_at) DESC
+                        LIMIT ?3",
+                )?
+                .query_map(params, track_count)?
+                .collect::<Result<_, _>>()?;
+            let listened_secs: i64 = db
+                .prepare_cached(
+                    "SELECT COALESCE(SUM(played_secs), 0)
+                        FROM play_history
+                        WHERE guild_id = ?1 AND started_at >= ?2",
+                )?
+                .query_row((params.0, params.1), |row| row.get(0))?;
+            Ok(history::Stats {
+                top_tracks,
+                top_requesters,
+                most_skipped,
+                listened: Duration::from_secs(listened_secs as u64),
+            })
+        })
+        .await
+        .unwrap_or_default()
+    }
+}
+
+#[async_trait]
+impl saved_queue::QueueStorage for Storage {
+    async fn save(
+        &self,
+        guild_id: GuildId,
+        queue: &saved_queue::SavedQueue,
+    ) -> Result<(), anyhow::Error> {
+        let queue = queue.clone();
+        self.call(move |db| {
+            let tx = db.transaction()?;
+            // Tracks of the previous queue are removed via `ON DELETE CASCADE`
+            tx.prepare_cached("DELETE FROM saved_queues WHERE guild_id = ?1")?
+                .execute([guild_id.get() as i64])?;
+            tx.prepare_cached(
+                "INSERT INTO saved_queues (
+                    guild_id, channel_id, current_position_ms
+                ) VALUES (?1, ?2, ?3)",
+            )?
+            .execute((
+                guild_id.get() as i64,
+                queue.channel_id.get() as i64,
+                queue.position.as_millis() as i64,
+            ))?;
+            {
+                let mut stmt = tx.prepare_cached(
+                    "INSERT INTO saved_queue_tracks (
+                        guild_id, position, source_url, title, requested_by, volume
+                    ) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                )?;
+                for (position, track) in queue.tracks.iter().enumerate() {
+                    stmt.execute((
+                        guild_id.get() as i64,
+                        position,
+                        &track.source_url,
+                        &track.title,
+                        requester_to_sql(track.requested_by),
+                        track.volume,
+                    ))?;
+                }
+            }
+            tx.commit()
+        })
+        .await?;
+        Ok(())
+    }
+
+    async fn load_all(&self) -> Vec<(GuildId, saved_queue::SavedQueue)> {
+        self.call(|db| {
+            let queues: Vec<(i64, i64, i64)> = db
+                .prepare_cached(
+                    "SELECT guild_id, channel_id, current_position_ms FROM saved_queues",
+                )?
+                .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+                .collect::<Result<_, _>>()?;
+
+            let mut stmt = db.prepare_cached(
+                "SELECT source_url, title, requested_by, volume
+                    FROM saved_queue_tracks
+                    WHERE guild_id = ?1
+                    ORDER BY position",
+            )?;
+            queues
+                .into_iter()
+                // Zero ids are invalid in Discord and can't be stored by the bot anyway
+                .filter(|&(guild_id, channel_id, _)| guild_id != 0 && channel_id != 0)
+                .map(|(guild_id, channel_id, position_ms)| {
+                    let tracks = stmt
+                        .query_map([guild_id], |row| {
+                            Ok(saved_queue::SavedTrack {
+                                source_url: row.get(0)?,
+                                title: row.get(1)?,
+                                // Tracks of queues saved before user ids were stored belong to nobody
+                                requested_by: requester_from_sql(&row.get::<_, String>(2)?)
+                                    .unwrap_or(track_info::Requester::Autoplay),
+                                volume: row.get(3)?,
+                            })
+                        })?
+                        .collect::<Result<_, _>>()?;
+                    let queue = saved_queue::SavedQueue {
+                        channel_id: ChannelId::new(channel_id as u64),
+                        position: Duration::from_millis(position_ms as u64),
+                        tracks,
+                    };
+                    Ok((GuildId::new(guild_id as u64), queue))
+                })
+                .collect()
+        })
+        .await
+        .unwrap_or_default()
+    }
+
+    async fn remove(&self, guild_id: GuildId) -> Result<(), anyhow::Error> {
+        self.call(move |db| {
+            db.prepare_cached("DELETE FROM saved_queues WHERE guild_id = ?1")?
+                .execute([guild_id.get() as i64])
+        })
+        .await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl guild_settings::GuildSettingsStorage for Storage {
+    async fn save(
+        &self,
+        guild_id: GuildId,
+        settings: &guild_settings::GuildSettings,
+    ) -> Result<(), anyhow::Error> {
+        let settings = *settings;
+        self.call(move |db| save_guild_settings(db, guild_id, &settings))
+            .await?;
+        Ok(())
+    }
+
+    async fn load_all(&self) -> Vec<(GuildId, guild_settings::GuildSettings)> {
+        self.call(|db| load_guild_settings(db))
+            .await
+            .unwrap_or_default()
+    }
+}
+
+// Guild settings are shared with the backup, which has to run within its transaction
+fn load_guild_settings(
+    db: &rusqlite::Connection,
+) -> rusqlite::Result<Vec<(GuildId, guild_settings::GuildSettings)>> {
+    db.prepare_cached(
+        "SELECT
+                guild_id, volume, bitrate, autoplay, leave_when_alone, radio_t, idle_timeout_minutes,
+                dj_role_id, vote_skip_percent, user_tracks_limit, track_minutes_limit,
+                playlist_tracks_limit, play_cooldown_secs, follow_requester
+            FROM guild_settings
+            WHERE guild_id != 0
+            ORDER BY guild_id",
+    )?
+    .query_map([], |row| {
+        let settings = guild_settings::GuildSettings {
+            volume: row.get(1)?,
+            bitrate_kbps: row.get(2)?,
+            autoplay: row.get(3)?,
+            leave_when_alone: row.get(4)?,
+            radio_t: row.get(5)?,
+            idle_timeout_minutes: row.get(6)?,
+            dj_role: row
+                .get::<_, Option<u64>>(7)?
+                .filter(|&id| id != 0)
+                .map(RoleId::new),
+            vote_skip_percent: row.get(8)?,
+            user_tracks_limit: row.get(9)?,
+            track_minutes_limit: row.get(10)?,
+            playlist_tracks_limit: row.get(11)?,
+            play_cooldown_secs: row.get(12)?,
+            follow_requester: row.get(13)?,
+        };
+        Ok((GuildId::new(row.get(0)?), settings))
+    })?
+    .collect()
+}
+
+fn save_guild_settings(
+    db: &rusqlite::Connection,
+    guild_id: GuildId,
+    settings: &guild_settings::GuildSettings,
+) -> rusqlite::Result<usize> {
+    db.prepare_cached(
+        "INSERT OR REPLACE INTO guild_settings (
+            guild_id, volume, bitrate, autoplay, leave_when_alone, radio_t, idle_timeout_minutes,
+            dj_role_id, vote_skip_percent, user_tracks_limit, track_minutes_limit,
+            playlist_tracks_limit, play_cooldown_secs, follow_requester
+        ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)",
+    )?
+    .execute((
+        guild_id.get() as i64,
+        settings.volume,
+        settings.bitrate_kbps,
+        settings.autoplay,
+        settings.leave_when_alone,
+        settings.radio_t,
+        settings.idle_timeout_minutes,
+        settings.dj_role.map(|id| id.get() as i64),
+        settings.vote_skip_percent,
+        settings.user_tracks_limit,
+        settings.track_minutes_limit,
+        settings.playlist_tracks_limit,
+        settings.play_cooldown_secs,
+        settings.follow_requester,
+    ))
+}
+
+#[async_trait]
+impl blocklist::BlocklistStorage for Storage {
+    async fn add(&self, guild_id: GuildId, rule: &blocklist::Rule) -> Result<bool, anyhow::Error> {
+        let rule = rule.clone();
+        let added = self
+            .call(move |db| {
+                db.prepare_cached(
+                    "INSERT OR IGNORE INTO blocklist (guild_id, kind, value) VALUES (?1, ?2, ?3)",
+                )?
+                .execute((guild_id.get() as i64, rule.kind.key(), &rule.value))
+            })
+            .await?;
+        Ok(added > 0)
+    }
+
+    async fn remove(
+        &self,
+        guild_id: GuildId,
+        rule: &blocklist::Rule,
+    ) -> Result<bool, anyhow::Error> {
+        let rule = rule.clone();
+        let removed = self
+            .call(move |db| {
+                db.prepare_cached(
+                    "DELETE FROM blocklist WHERE guild_id = ?1 AND kind = ?2 AND value = ?3",
+                )?
+                .execute((guild_id.get() as i64, rule.kind.key(), &rule.value))
+            })
+            .await?;
+        Ok(removed > 0)
+    }
+
+    async fn load_all(&self) -> Vec<(GuildId, blocklist::Rule)> {
+        self.call(|db| load_blocklist(db)).await.unwrap_or_default()
+    }
+}
+
+// Blocklists are shared with the backup as well
+fn load_blocklist(db: &rusqlite::Connection) -> rusqlite::Result<Vec<(GuildId, blocklist::Rule)>> {
+    let rows: Vec<(u64, String, String)> = db
+        .prepare_cached(
+            "SELECT guild_id, kind, value
+                FROM blocklist
+                WHERE guild_id != 0
+                ORDER BY guild_id, kind, value",
+        )?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+        .collect::<Result<_, _>>()?;
+    // Rules of unknown kinds can only come from a newer version of the bot, so they are skipped
+    Ok(rows
+        .into_iter()
+        .filter_map(|(guild_id, kind, value)| {
+            let kind = blocklist::RuleKind::from_key(&kind)?;
+            Some((GuildId::new(guild_id), blocklist::Rule { kind, value }))
+        })
+        .collect())
+}
+
+#[async_trait]
+impl channels::AllowedChannelsStorage for Storage {
+    async fn allow(
+        &self,
+        guild_id: GuildId,
+        kind: channels::ChannelKind,
+        channel_id: ChannelId,
+    ) -> Result<bool, anyhow::Error> {
+        let added = self
+            .call(move |db| {
+                db.prepare_cached(
+                    "INSERT OR IGNORE INTO allowed_channels (guild_id, channel_id, kind)
+                        VALUES (?1, ?2, ?3)",
+                )?
+                .execute((
+                    guild_id.get() as i64,
+                    channel_id.get() as i64,
+                    kind.key(),
+                ))
+            })
+            .await?;
+        Ok(added > 0)
+    }
+
+    async fn disallow(
+        &self,
+        guild_id: GuildId,
+        channel_id: ChannelId,
+    ) -> Result<bool, anyhow::Error> {
+        let removed = self
+            .call(move |db| {
+                db.prepare_cached(
+                    "DELETE FROM allowed_channels WHERE guild_id = ?1 AND channel_id = ?2",
+                )?
+                .execute((guild_id.get() as i64, channel_id.get() as i64))
+            })
+            .await?;
+        Ok(removed > 0)
+    }
+
+    async fn load_all(&self) -> Vec<(GuildId, channels::ChannelKind, ChannelId)> {
+        self.call(|db| load_allowed_channels(db))
+            .await
+            .unwrap_or_default()
+    }
+}
+
+// Allowed channels are shared with the backup as well
+fn load_allowed_channels(
+    db: &rusqlite::Connection,
+) -> rusqlite::Result<Vec<(GuildId, channels::ChannelKind, ChannelId)>> {
+    let rows: Vec<(u64, u64, String)> = db
+        .prepare_cached(
+            "SELECT guild_id, channel_id, kind
+                FROM allowed_channels
+                WHERE guild_id != 0 AND channel_id != 0
+                ORDER BY guild_id, channel_id",
+        )?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+        .collect::<Result<_, _>>()?;
+    Ok(rows
+        .into_iter()
+        .filter_map(|(guild_id, channel_id, kind)| {
+            let kind = channels::ChannelKind::from_key(&kind)?;
+            Some((GuildId::new(guild_id), kind, ChannelId::new(channel_id)))
+        })
+        .collect())
+}
+
+#[async_trait]
+impl backup::BackupStorage for Storage {
+    async fn export(&self, include_credentials: bool) -> Result<backup::Backup, anyhow::Error> {
+        let backup = self
+            .call(move |db| {
+                let tx = db.transaction()?;
+                let guild_settings = load_guild_settings(&tx)?
+                    .into_iter()
+                    .map(|(guild_id, settings)| backup::GuildSettings {
+                        guild_id,
+                        settings,
+                    })
+                    .collect();
+                // Zero ids are invalid in Discord and can't be stored by the bot anyway
+                let spotify_settings = tx
+                    .prepare_cached(
+                        "SELECT guild_id, bitrate, normalisation, normalisation_pregain
+                            FROM spotify_settings
+                            WHERE guild_id != 0
+                            ORDER BY guild_id",
+                    )?
+                    .query_map([], |row| {
+                        Ok(backup::SpotifySettings {
+                            guild_id: GuildId::new(row.get(0)?),
+                            bitrate_kbps: row.get(1)?,
+                            normalisation: row.get(2)?,
+                            normalisation_pregain_db: row.get(3)?,
+                        })
+                    })?
+                    .collect::<Result<_, _>>()?;
+
+                let playlists: Vec<(i64, u64, String)> = tx
+                    .prepare_cached(
+                        "SELECT id, guild_id, name
+                            FROM playlists
+                            WHERE guild_id != 0
+                            ORDER BY guild_id, name",
+                    )?
+                    .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+                    .collect::<Result<_, _>>()?;
+                let mut stmt = tx.prepare_cached(
+                    "SELECT source_url, title
+                        FROM playlist_tracks
+                        WHERE playlist_id = ?1
+                        ORDER BY position",
+                )?;
+                let playlists = playlists
+                    .into_iter()
+                    .map(|(playlist_id, guild_id, name)| {
+                        let tracks = stmt
+                            .query_map([playlist_id], |row| {
+                                Ok(playlist::PlaylistTrack {
+                                    source_url: row.get(0)?,
+                                    title: row.get(1)?,
+                                })
+                            })?
+                            .collect::<Result<_, _>>()?;
+                        Ok(backup::Playlist {
+                            guild_id: GuildId::new(guild_id),
+                            name,
+                            tracks,
+                        })
+                    })
+                    .collect::<Result<_, rusqlite::Error>>()?;
+                drop(stmt);
+
+                let query_cache = tx
+                    .prepare_cached(
+                        "SELECT query, webpage_url, created_at, hits
+                            FROM yt_dlp_queries
+                            ORDER BY query",
+                    )?
+                    .query_map([], |row| {
+                        Ok(backup::CachedQuery {
+                            query: row.get(0)?,
+                            webpage_url: row.get(1)?,
+                            created_at: row.get(2)?,
+                            hits: row.get(3)?,
+                        })
+                    })?
+                    .collect::<Result<_, _>>()?;
+
+                let blocklist = load_blocklist(&tx)?
+                    .into_iter()
+                    .map(|(guild_id, rule)| backup::BlocklistRule { guild_id, rule })
+                    .collect();
+                let allowed_channels = load_allowed_channels(&tx)?
+                    .into_iter()
+                    .map(|(guild_id, kind, channel_id)| backup::AllowedChannel {
+                        guild_id,
+                        kind,
+                        channel_id,
+                    })
+                    .collect();
+
+                let credentials = if include_credentials {
+                    let guilds = tx
+                        .prepare_cached(
+                            "SELECT guild_id, username, password
+                                FROM spotify_credentials
+                                WHERE guild_id != 0 AND username IS NOT NULL AND password IS NOT NULL
+                                ORDER BY guild_id",
+                        )?
+                        .query_map([], |row| {
+                            Ok(backup::Credentials {
+                                owner: backup::CredentialsOwner::Guild(GuildId::new(row.get(0)?)),
+                                username: row.get(1)?,
+                                password: row.get(2)?,
+                            })
+                        })?
+                        .collect::<Result<Vec<_>, _>>()?;
+                    let users = tx
+                        .prepare_cached(
+                            "SELECT user_id, username, password
+                                FROM spotify_user_credentials
+                                WHERE user_id != 0 AND username IS NOT NULL AND password IS NOT NULL
+                                ORDER BY user_id",
+                        )?
+                        .query_map([], |row| {
+                            Ok(backup::Credentials {
+                                owner: backup::CredentialsOwner::User(UserId::new(row.get(0)?)),
+                                username: row.get(1)?,
+                                password: row.get(2)?,
+                            })
+                        })?
+                        .collect::<Result<Vec<_>, _>>()?;
+                    Some(guilds.into_iter().chain(users).collect())
+                } else {
+                    None
+                };
+
+                Ok(backup::Backup {
+                    version: backup::BACKUP_VERSION,
+                    guild_settings,
+                    spotify_settings,
+                    playlists,
+                    query_cache,
+                    blocklist,
+                    allowed_channels,
+                    credentials,
+                })
+            })
+            .await?;
+        Ok(backup)
+    }
+
+    async fn import(&self, backup: backup::Backup) -> Result<(), anyhow::Error> {
+        self.call(move |db| {
+            let tx = db.transaction()?;
+            for settings in &backup.guild_settings {
+                save_guild_settings(&tx, settings.guild_id, &settings.settings)?;
+            }
+            {
+                let mut stmt = tx.prepare_cached(
+                    "INSERT OR REPLACE INTO spotify_settings (
+                        guild_id, bitrate, normalisation, normalisation_pregain
+                    ) VALUES (?1, ?2, ?3, ?4)",
+                )?;
+                for settings in &backup.spotify_settings {
+                    stmt.execute((
+                        settings.guild_id.get() as i64,
+                        settings.bitrate_kbps,
+                        settings.normalisation,
+                        settings.normalisation_pregain_db,
+                    ))?;
+                }
+            }
+            {
+                let mut delete_stmt =
+                    tx.prepare_cached("DELETE FROM playlists WHERE guild_id = ?1 AND name = ?2")?;
+                let mut playlist_stmt = tx.prepare_cached(
+                    "INSERT INTO playlists (guild_id, name) VALUES (?1, ?2) RETURNING id",
+                )?;
+                let mut track_stmt = tx.prepare_cached(
+                    "INSERT INTO playlist_tracks (
+                        playlist_id, position, source_url, title
+                    ) VALUES (?1, ?2, ?3, ?4)",
+                )?;
+                for playlist in &backup.playlists {
+                    let guild_id = playlist.guild_id.get() as i64;
+                    // Tracks of the replaced playlist are removed via `ON DELETE CASCADE`
+                    delete_stmt.execute((guild_id, &playlist.name))?;
+                    let playlist_id: i64 =
+                        playlist_stmt.query_row((guild_id, &playlist.name), |row| row.get(0))?;
+                    for (position, track) in playlist.tracks.iter().enumerate() {
+                        track_stmt.execute((
+                            playlist_id,
+                            position,
+                            &track.source_url,
+                            &track.title,
+                        ))?;
+                    }
+                }
+            }
+            {
+                let mut stmt = tx.prepare_cached(
+                    "INSERT OR REPLACE INTO yt_dlp_queries (
+                        query, webpage_url, created_at, hits
+                    ) VALUES (?1, ?2, ?3, ?4)",
+                )?;
+                // Keys from older backups might be not normalized. Of the queries sharing a key,
+                // the most recently cached one is inserted last and kept, like migration 17 does
+                let mut queries: Vec<_> = backup.query_cache.iter().collect();
+                queries.sort_by_key(|cached| (cached.created_at, cached.hits));
+                for cached in queries {
+                    stmt.execute((
+                        yt_dlp::normalize_query(&cached.query),
+                        &cached.webpage_url,
+                        cached.created_at as i64,
+                        cached.hits as i64,
+                    ))?;
+                }
+            }
+            {
+                let mut stmt = tx.prepare_cached(
+                    "INSERT OR IGNORE INTO blocklist (guild_id, kind, value) VALUES (?1, ?2, ?3)",
+                )?;
+                for blocked in &backup.blocklist {
+                    stmt.execute((
+                        blocked.guild_id.get() as i64,
+                        blocked.rule.kind.key(),
+                        &blocked.rule.value,
+                    ))?;
+                }
+            }
+            {
+                let mut stmt = tx.prepare_cached(
+                    "INSERT OR REPLACE INTO allowed_channels (guild_id, channel_id, kind)
+                        VALUES (?1, ?2, ?3)",
+                )?;
+                for allowed in &backup.allowed_channels {
+                    stmt.execute((
+                        allowed.guild_id.get() as i64,
+                        allowed.channel_id.get() as i64,
+                        allowed.kind.key(),
+                    ))?;
+                }
+            }
+            for credentials in backup.credentials.iter().flatten() {
+                let (table, key, id) = match credentials.owner {
+                    backup::CredentialsOwner::Guild(guild_id) => {
+                        ("spotify_credentials", "guild_id", guild_id.get() as i64)
+                    }
+                    backup::CredentialsOwner::User(user_id) => {
+                        ("spotify_user_credentials", "user_id", user_id.get() as i64)
+                    }
+                };
+                tx.prepare_cached(&format!(
+                    "INSERT OR REPLACE INTO {table} (
+                        {key}, username, password
+                    ) VALUES (?1, ?2, ?3)"
+                ))?
+                .execute((id, &credentials.username, &credentials.password))?;
+            }
+            tx.commit()
+        })
+        .await?;
+        Ok(())
     }
 }
 
@@ -101,69 +1279,952 @@ mod tests {
     use super::*;
     use pretty_assertions::assert_eq;
 
+    /// Schema that `Storage::new` created in the first release, before migrations were introduced.
+    /// Kept verbatim rather than taken from `MIGRATIONS`, so changes to them can't go unnoticed
+    const BASELINE_SCHEMA: &str = "
+        CREATE TABLE IF NOT EXISTS spotify_credentials (
+            guild_id INTEGER PRIMARY KEY,
+            username TEXT,
+            password TEXT
+        );
+        CREATE TABLE IF NOT EXISTS yt_dlp_queries (
+            query TEXT NOT NULL PRIMARY KEY,
+            webpage_url TEXT NOT NULL
+        );";
+
+    fn schema_version(db_conn: &rusqlite::Connection) -> usize {
+        db_conn
+            .pragma_query_value(None, "user_version", |row| row.get(0))
+            .unwrap()
+    }
+
+    fn tables(db_conn: &rusqlite::Connection) -> Vec<String> {
+        let mut stmt = db_conn
+            .prepare("SELECT name FROM sqlite_master WHERE type = 'table' ORDER BY name")
+            .unwrap();
+        stmt.query_map([], |row| row.get(0))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect()
+    }
+
+    fn latest_tables() -> Vec<String> {
+        let mut db_conn = rusqlite::Connection::open_in_memory().unwrap();
+        migrate(&mut db_conn).unwrap();
+        tables(&db_conn)
+    }
+
+    /// Creates a database with the schema of the provided version, as it was migrated to by that version of the bot
+    fn historical_db(version: usize) -> rusqlite::Connection {
+        let db_conn = rusqlite::Connection::open_in_memory().unwrap();
+        add_functions(&db_conn).unwrap();
+        for migration in &MIGRATIONS[..version] {
+            db_conn.execute_batch(migration).unwrap();
+        }
+        db_conn
+            .execute(
+                "INSERT INTO yt_dlp_queries (query, webpage_url) VALUES ('query', 'webpage_url')",
+                (),
+            )
+            .unwrap();
+        db_conn
+    }
+
+    #[test]
+    fn migrate_fresh_db() {
+        let mut db_conn = rusqlite::Connection::open_in_memory().unwrap();
+        migrate(&mut db_conn).unwrap();
+
+        assert_eq!(schema_version(&db_conn), MIGRATIONS.len());
+        assert_eq!(
+            tables(&db_conn),
+            vec![
+                "allowed_channels",
+                "blocklist",
+                "guild_settings",
+                "play_history",
+                "playlist_tracks",
+                "playlists",
+                "saved_queue_tracks",
+                "saved_queues",
+                "spotify_credentials",
+                "spotify_settings",
+                "spotify_user_credentials",
+                "yt_dlp_queries",
+            ]
+        );
+
+        // Migrating the latest schema is a no-op
+        migrate(&mut db_conn).unwrap();
+        assert_eq!(schema_version(&db_conn), MIGRATIONS.len());
+    }
+
+    #[test]
+    fn migrate_historical_schemas() {
+        // Databases created before versioning was introduced have `user_version = 0`
+        let mut db_conn = rusqlite::Connection::open_in_memory().unwrap();
+        db_conn.execute_batch(BASELINE_SCHEMA).unwrap();
+        db_conn
+            .execute(
+                "INSERT INTO yt_dlp_queries (query, webpage_url) VALUES ('query', 'webpage_url')",
+                (),
+            )
+            .unwrap();
+        assert_eq!(schema_version(&db_conn), 0);
+
+        migrate(&mut db_conn).unwrap();
+        assert_eq!(schema_version(&db_conn), MIGRATIONS.len());
+        assert_eq!(tables(&db_conn), latest_tables());
+
+        // Existing data is preserved
+        let webpage_url: String = db_conn
+            .query_row("SELECT webpage_url FROM yt_dlp_queries", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(webpage_url, "webpage_url");
+
+        // Versioned databases only get the missing migrations
+        for version in 1..=MIGRATIONS.len() {
+            let mut db_conn = historical_db(version);
+            db_conn
+                .pragma_update(None, "user_version", version)
+                .unwrap();
+
+            migrate(&mut db_conn).unwrap();
+            assert_eq!(schema_version(&db_conn), MIGRATIONS.len());
+            assert_eq!(tables(&db_conn), latest_tables());
+        }
+    }
+
+    #[test]
+    fn migrate_query_cache_keys() {
+        let version = 16;
+        let mut db_conn = historical_db(version);
+        db_conn
+            .pragma_update(None, "user_version", version)
+            .unwrap();
+        db_conn
+            .execute_batch(
+                "INSERT INTO yt_dlp_queries (query, webpage_url, created_at, hits) VALUES
+                    ('Rick  Astley', 'old', 100, 5),
+                    ('rick astley', 'new', 200, 1),
+                    (' RICK ASTLEY ', 'oldest', 50, 9),
+                    ('Feofan', 'feofan', 100, 0);",
+            )
+            .unwrap();
+
+        migrate(&mut db_conn).unwrap();
+        let mut stmt = db_conn
+            .prepare("SELECT query, webpage_url FROM yt_dlp_queries ORDER BY query")
+            .unwrap();
+        let queries: Vec<(String, String)> = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(
+            queries,
+            vec![
+                ("feofan".to_owned(), "feofan".to_owned()),
+                ("query".to_owned(), "webpage_url".to_owned()),
+                // The most recently cached duplicate is kept
+                ("rick astley".to_owned(), "new".to_owned()),
+            ]
+        );
+    }
+
     #[test]
+    fn migrate_newer_schema() {
+        let mut db_conn = rusqlite::Connection::open_in_memory().unwrap();
+        db_conn
+            .pragma_update(None, "user_version", MIGRATIONS.len() + 1)
+            .unwrap();
+        assert!(migrate(&mut db_conn).is_err());
+    }
+
+    #[tokio::test]
     #[cfg(feature = "spotify")]
-    fn spotify_credentials_storage() {
+    async fn spotify_credentials_storage() {
         let storage: Arc<dyn spotify::CredentialsStorage> = Storage::new(":memory:").unwrap();
 
-        let first_guild_id = GuildId::new(101);
-        assert!(storage
-            .save(first_guild_id, "my username", "my password")
-            .is_ok());
+        let first_guild_id = spotify::CredentialsOwner::Guild(GuildId::new(101));
+        assert!(
+            storage
+                .save(first_guild_id, "my username", "my password")
+                .await
+                .is_ok()
+        );
         assert_eq!(
-            storage.load(first_guild_id),
+            storage.load(first_guild_id).await,
             Some(("my username".into(), "my password".into()))
         );
 
         // store same Spotify username for another guild
-        let guild_id = GuildId::new(202);
-        assert!(storage
-            .save(guild_id, "my username", "another password")
-            .is_ok());
+        let guild_id = spotify::CredentialsOwner::Guild(GuildId::new(202));
+        assert!(
+            storage
+                .save(guild_id, "my username", "another password")
+                .await
+                .is_ok()
+        );
         assert_eq!(
-            storage.load(guild_id),
+            storage.load(guild_id).await,
             Some(("my username".into(), "another password".into()))
         );
 
         // update the username and password
-        assert!(storage
-            .save(guild_id, "another username", "third password")
-            .is_ok());
+        assert!(
+            storage
+                .save(guild_id, "another username", "third password")
+                .await
+                .is_ok()
+        );
         assert_eq!(
-            storage.load(guild_id),
+            storage.load(guild_id).await,
             Some(("another username".into(), "third password".into()))
         );
 
         // First guild should not be affected
         assert_eq!(
-            storage.load(first_guild_id),
+            storage.load(first_guild_id).await,
             Some(("my username".into(), "my password".into()))
         );
 
         // Non-existing guild
-        assert_eq!(storage.load(GuildId::new(303)), None);
+        let missing_guild_id = spotify::CredentialsOwner::Guild(GuildId::new(303));
+        assert_eq!(storage.load(missing_guild_id).await, None);
+
+        // Users with the same id as a guild don't share credentials with it
+        let user_id = spotify::CredentialsOwner::User(serenity::all::UserId::new(101));
+        assert_eq!(storage.load(user_id).await, None);
+        assert!(storage.save(user_id, "user", "user password").await.is_ok());
+        assert_eq!(
+            storage.load(user_id).await,
+            Some(("user".into(), "user password".into()))
+        );
+        assert_eq!(
+            storage.load(first_guild_id).await,
+            Some(("my username".into(), "my password".into()))
+        );
+
+        // Unlink the user account, guild one stays
+        assert!(storage.remove(user_id).await.is_ok());
+        assert_eq!(storage.load(user_id).await, None);
+        assert_eq!(
+            storage.load(first_guild_id).await,
+            Some(("my username".into(), "my password".into()))
+        );
+
+        // Removing non-existing credentials is fine
+        assert!(storage.remove(missing_guild_id).await.is_ok());
     }
 
-    #[test]
-    fn yt_dlp_query_cache() {
+    #[tokio::test]
+    #[cfg(feature = "spotify")]
+    async fn spotify_settings_storage() {
+        let storage: Arc<dyn spotify::SettingsStorage> = Storage::new(":memory:").unwrap();
+
+        let guild_id = GuildId::new(101);
+        assert_eq!(storage.load(guild_id).await, None);
+
+        let settings = spotify::Settings {
+            bitrate: spotify::Bitrate::Kbps320,
+            normalisation: true,
+            normalisation_pregain_db: -3.5,
+        };
+        assert!(storage.save(guild_id, &settings).await.is_ok());
+        assert_eq!(storage.load(guild_id).await, Some(settings));
+
+        // Update settings
+        let settings = spotify::Settings {
+            bitrate: spotify::Bitrate::Kbps96,
+            ..settings
+        };
+        assert!(storage.save(guild_id, &settings).await.is_ok());
+        assert_eq!(storage.load(guild_id).await, Some(settings));
+
+        // Other guilds are not affected
+        assert_eq!(storage.load(GuildId::new(202)).await, None);
+    }
+
+    #[tokio::test]
+    async fn import_query_cache() {
+        let db = Storage::new(":memory:").unwrap();
+        let storage: Arc<dyn backup::BackupStorage> = db.clone();
+        let cached = |query: &str, webpage_url: &str, created_at| backup::CachedQuery {
+            query: query.into(),
+            webpage_url: webpage_url.into(),
+            created_at,
+            hits: 0,
+        };
+        let backup = backup::Backup {
+            version: backup::BACKUP_VERSION,
+            query_cache: vec![
+                cached("Rick Astley", "new", 1_700_000_100),
+                cached("RICK  ASTLEY", "old", 1_700_000_000),
+            ],
+            ..Default::default()
+        };
+        assert!(backup.validate().is_ok());
+        storage.import(backup).await.unwrap();
+
+        // Keys are normalized, keeping the most recently cached query
+        let query_cache: Arc<dyn yt_dlp::QueryCache> = db.clone();
+        let max_age = Duration::from_secs(u32::MAX.into());
+        assert_eq!(
+            query_cache.load("rick astley", max_age).await.as_deref(),
+            Some("new")
+        );
+        assert_eq!(query_cache.load_all(max_age).await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn backup_storage() {
+        let db = Storage::new(":memory:").unwrap();
+        let storage: Arc<dyn backup::BackupStorage> = db.clone();
+        let guild_id = GuildId::new(101);
+
+        let track = playlist::PlaylistTrack {
+            source_url: "https://example.com/1".into(),
+            title: "Track 1".into(),
+        };
+        let playlists: Arc<dyn playlist::PlaylistStorage> = db.clone();
+        playlists
+            .save(guild_id, "party", std::slice::from_ref(&track))
+            .await
+            .unwrap();
+        let query_cache: Arc<dyn yt_dlp::QueryCache> = db.clone();
+        query_cache.save("query", "webpage_url").await.unwrap();
+        let settings: Arc<dyn guild_settings::GuildSettingsStorage> = db.clone();
+        settings
+            .save(
+                guild_id,
+                &guild_settings::GuildSettings {
+                    volume: 30,
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        db.call(|db| {
+            db.execute_batch(
+                "INSERT INTO spotify_settings VALUES (101, 320, 1, -2.0);
+                INSERT INTO spotify_credentials VALUES (101, 'guild username', 'guild password');
+                INSERT INTO spotify_user_credentials VALUES (303, 'user username', 'user password');
+                UPDATE yt_dlp_queries SET created_at = 1700000000, hits = 3;",
+            )
+        })
+        .await
+        .unwrap();
+        let blocked = blocklist::Rule::new(blocklist::RuleKind::Domain, "example.org").unwrap();
+        let blocklists: Arc<dyn blocklist::BlocklistStorage> = db.clone();
+        blocklists.add(guild_id, &blocked).await.unwrap();
+        let allowed_channels: Arc<dyn channels::AllowedChannelsStorage> = db.clone();
+        allowed_channels
+            .allow(guild_id, channels::ChannelKind::Voice, ChannelId::new(404))
+            .await
+            .unwrap();
+
+        let expected = backup::Backup {
+            version: backup::BACKUP_VERSION,
+            guild_settings: vec![backup::GuildSettings {
+                guild_id,
+                settings: guild_settings::GuildSettings {
+                    volume: 30,
+                    ..Default::default()
+                },
+            }],
+            spotify_settings: vec![backup::SpotifySettings {
+                guild_id,
+                bitrate_kbps: 320,
+                normalisation: true,
+                normalisation_pregain_db: -2.0,
+            }],
+            playlists: vec![backup::Playlist {
+                guild_id,
+                name: "party".into(),
+                tracks: vec![track.clone()],
+            }],
+            query_cache: vec![backup::CachedQuery {
+                query: "query".into(),
+                webpage_url: "webpage_url".into(),
+                created_at: 1_700_000_000,
+                hits: 3,
+            }],
+            blocklist: vec![backup::BlocklistRule {
+                guild_id,
+                rule: blocked,
+            }],
+            allowed_channels: vec![backup::AllowedChannel {
+                guild_id,
+                kind: channels::ChannelKind::Voice,
+                channel_id: ChannelId::new(404),
+            }],
+            credentials: Some(vec![
+                backup::Credentials {
+                    owner: backup::CredentialsOwner::Guild(guild_id),
+                    username: "guild username".into(),
+                    password: "guild password".into(),
+                },
+                backup::Credentials {
+                    owner: backup::CredentialsOwner::User(UserId::new(303)),
+                    username: "user username".into(),
+                    password: "user password".into(),
+                },
+            ]),
+        };
+        let exported = storage.export(true).await.unwrap();
+        assert_eq!(exported, expected);
+        assert!(exported.validate().is_ok());
+        assert_eq!(
+            storage.export(false).await.unwrap(),
+            backup::Backup {
+                credentials: None,
+                ..expected.clone()
+            }
+        );
+
+        // Import to a fresh database restores everything
+        let another_db = Storage::new(":memory:").unwrap();
+        let another: Arc<dyn backup::BackupStorage> = another_db.clone();
+        another.import(exported.clone()).await.unwrap();
+        assert_eq!(another.export(true).await.unwrap(), expected);
+
+        // Import keeps existing entries, replacing ones with the same keys
+        let another_playlists: Arc<dyn playlist::PlaylistStorage> = another_db.clone();
+        another_playlists
+            .save(guild_id, "chill", &[])
+            .await
+            .unwrap();
+        another_playlists
+            .save(guild_id, "party", &[track.clone(), track.clone()])
+            .await
+            .unwrap();
+        another.import(exported).await.unwrap();
+        assert_eq!(
+            another_playlists.list(guild_id).await,
+            vec![("chill".into(), 0), ("party".into(), 1)]
+        );
+
+        // Import is atomic. NaN is stored as NULL and violates the constraint
+        let before = another.export(true).await.unwrap();
+        let mut invalid = expected.clone();
+        invalid.spotify_settings[0].bitrate_kbps = 96;
+        invalid.spotify_settings.push(backup::SpotifySettings {
+            guild_id: GuildId::new(202),
+            normalisation_pregain_db: f64::NAN,
+            ..invalid.spotify_settings[0].clone()
+        });
+        assert!(another.import(invalid).await.is_err());
+        assert_eq!(another.export(true).await.unwrap(), before);
+    }
+
+    #[tokio::test]
+    async fn blocklist_storage() {
+        let storage: Arc<dyn blocklist::BlocklistStorage> = Storage::new(":memory:").unwrap();
+        assert_eq!(storage.load_all().await, vec![]);
+
+        let guild_id = GuildId::new(101);
+        let domain = blocklist::Rule::new(blocklist::RuleKind::Domain, "example.com").unwrap();
+        let user = blocklist::Rule::new(blocklist::RuleKind::User, "303").unwrap();
+        assert!(storage.add(guild_id, &domain).await.unwrap());
+        assert!(!storage.add(guild_id, &domain).await.unwrap());
+        assert!(storage.add(guild_id, &user).await.unwrap());
+        assert!(storage.add(GuildId::new(202), &user).await.unwrap());
+        assert_eq!(
+            storage.load_all().await,
+            vec![
+                (guild_id, domain.clone()),
+                (guild_id, user.clone()),
+                (GuildId::new(202), user.clone())
+            ]
+        );
+
+        assert!(storage.remove(guild_id, &user).await.unwrap());
+        assert!(!storage.remove(guild_id, &user).await.unwrap());
+        assert_eq!(
+            storage.load_all().await,
+            vec![(guild_id, domain), (GuildId::new(202), user)]
+        );
+    }
+
+    #[tokio::test]
+    async fn allowed_channels_storage() {
+        let storage: Arc<dyn channels::AllowedChannelsStorage> = Storage::new(":memory:").unwrap();
+        assert_eq!(storage.load_all().await, vec![]);
+
+        let guild_id = GuildId::new(101);
+        let (text, voice) = (ChannelId::new(1), ChannelId::new(2));
+        assert!(
+            storage
+                .allow(guild_id, channels::ChannelKind::Text, text)
+                .await
+                .unwrap()
+        );
+        assert!(
+            !storage
+                .allow(guild_id, channels::ChannelKind::Text, text)
+                .await
+                .unwrap()
+        );
+        assert!(
+            storage
+                .allow(guild_id, channels::ChannelKind::Voice, voice)
+                .await
+                .unwrap()
+        );
+        assert_eq!(
+            storage.load_all().await,
+            vec![
+                (guild_id, channels::ChannelKind::Text, text),
+                (guild_id, channels::ChannelKind::Voice, voice)
+            ]
+        );
+
+        assert!(storage.disallow(guild_id, text).await.unwrap());
+        assert!(!storage.disallow(guild_id, text).await.unwrap());
+        assert_eq!(
+            storage.load_all().await,
+            vec![(guild_id, channels::ChannelKind::Voice, voice)]
+        );
+    }
+
+    #[tokio::test]
+    async fn guild_settings_storage() {
+        let storage: Arc<dyn guild_settings::GuildSettingsStorage> =
+            Storage::new(":memory:").unwrap();
+        assert_eq!(storage.load_all().await, vec![]);
+
+        let guild_id = GuildId::new(101);
+        let settings = guild_settings::GuildSettings {
+            volume: 30,
+            bitrate_kbps: 128,
+            autoplay: true,
+            leave_when_alone: false,
+            idle_timeout_minutes: 30,
+            radio_t: false,
+            dj_role: Some(RoleId::new(303)),
+            vote_skip_percent: 50,
+            user_tracks_limit: 10,
+            track_minutes_limit: 15,
+            playlist_tracks_limit: 50,
+            play_cooldown_secs: 5,
+            follow_requester: true,
+        };
+        assert!(storage.save(guild_id, &settings).await.is_ok());
+        assert_eq!(storage.load_all().await, vec![(guild_id, settings)]);
+
+        // Saving again replaces the settings
+        let another_guild_id = GuildId::new(202);
+        let default = guild_settings::GuildSettings::default();
+        assert!(storage.save(guild_id, &default).await.is_ok());
+        assert!(storage.save(another_guild_id, &settings).await.is_ok());
+        assert_eq!(
+            storage.load_all().await,
+            vec![(guild_id, default), (another_guild_id, settings)]
+        );
+    }
+
+    #[tokio::test]
+    async fn playlist_storage() {
+        let db = Storage::new(":memory:").unwrap();
+        let storage: Arc<dyn playlist::PlaylistStorage> = db.clone();
+        let track = |i: usize| playlist::PlaylistTrack {
+            source_url: format!("https://example.com/{i}"),
+            title: format!("Track {i}"),
+        };
+
+        let guild_id = GuildId::new(101);
+        assert_eq!(storage.load(guild_id, "party").await, None);
+        assert_eq!(storage.list(guild_id).await, vec![]);
+
+        let party = vec![track(1), track(2), track(3)];
+        assert!(storage.save(guild_id, "party", &party).await.is_ok());
+        assert_eq!(storage.load(guild_id, "party").await, Some(party));
+
+        // Tracks keep their order
+        let chill = vec![track(3), track(1)];
+        assert!(storage.save(guild_id, "chill", &chill).await.is_ok());
+        assert_eq!(storage.load(guild_id, "chill").await, Some(chill));
+        assert_eq!(
+            storage.list(guild_id).await,
+            vec![("chill".into(), 2), ("party".into(), 3)]
+        );
+
+        // Saving with the same name replaces the playlist
+        let party = vec![track(4)];
+        assert!(storage.save(guild_id, "party", &party).await.is_ok());
+        assert_eq!(storage.load(guild_id, "party").await, Some(party));
+
+        // Empty playlists are fine too
+        assert!(storage.save(guild_id, "empty", &[]).await.is_ok());
+        assert_eq!(storage.load(guild_id, "empty").await, Some(vec![]));
+
+        // Other guilds have their own playlists
+        let another_guild_id = GuildId::new(202);
+        assert_eq!(storage.load(another_guild_id, "party").await, None);
+        assert_eq!(storage.list(another_guild_id).await, vec![]);
+        assert!(!storage.delete(another_guild_id, "party").await.unwrap());
+
+        assert!(storage.delete(guild_id, "party").await.unwrap());
+        assert_eq!(storage.load(guild_id, "party").await, None);
+        assert!(!storage.delete(guild_id, "party").await.unwrap());
+        assert_eq!(
+            storage.list(guild_id).await,
+            vec![("chill".into(), 2), ("empty".into(), 0)]
+        );
+
+        // Tracks of replaced and deleted playlists are removed as well
+        let tracks_count: usize = db
+            .call(|db| db.query_row("SELECT COUNT(*) FROM playlist_tracks", [], |row| row.get(0)))
+            .await
+            .unwrap();
+        assert_eq!(tracks_count, 2);
+    }
+
+    #[tokio::test]
+    async fn history_storage() {
+        let db = Storage::new(":memory:").unwrap();
+        let storage: Arc<dyn history::HistoryStorage> = db.clone();
+        let (alice, bob) = (UserId::new(1), UserId::new(2));
+        let user = track_info::Requester::User;
+        let metadata = |i: usize| track_info::Metadata {
+            title: format!("Track {i}").into(),
+            source_url: format!("https://example.com/{i}").into(),
+            thumbnail_url: None,
+            duration_sec: None,
+        };
+
+        let guild_id = GuildId::new(101);
+        assert_eq!(storage.recent(guild_id, None, 10).await, vec![]);
+
+        let first = storage
+            .record_start(guild_id, &metadata(1), user(alice), 1000)
+            .await
+            .unwrap();
+        storage
+            .record_end(first, Duration::from_secs(90), false)
+            .await
+            .unwrap();
+        let _second = storage
+            .record_start(guild_id, &metadata(2), user(bob), 1100)
+            .await
+            .unwrap();
+        storage
+            .record_start(GuildId::new(202), &metadata(3), user(alice), 1200)
+            .await
+            .unwrap();
+
+        let entry = |i: usize, requested_by: &str, started_at, played_secs: Option<u64>| {
+            history::HistoryEntry {
+                source_url: format!("https://example.com/{i}"),
+                title: format!("Track {i}"),
+                requested_by: requested_by.into(),
+                started_at,
+                played: played_secs.map(Duration::from_secs),
+            }
+        };
+        // The most recent first, the second one is still playing
+        assert_eq!(
+            storage.recent(guild_id, None, 10).await,
+            vec![
+                entry(2, "<@2>", 1100, None),
+                entry(1, "<@1>", 1000, Some(90))
+            ]
+        );
+        assert_eq!(
+            storage.recent(guild_id, None, 1).await,
+            vec![entry(2, "<@2>", 1100, None)]
+        );
+        assert_eq!(
+            storage.recent(guild_id, Some(alice), 10).await,
+            vec![entry(1, "<@1>", 1000, Some(90))]
+        );
+        assert_eq!(
+            storage.recent(guild_id, Some(UserId::new(3)), 10).await,
+            vec![]
+        );
+
+        // Autoplay tracks are requested by nobody, while older records have user names
+        storage
+            .record_start(
+                guild_id,
+                &metadata(3),
+                track_info::Requester::Autoplay,
+                1300,
+            )
+            .await
+            .unwrap();
+        db.call(|db| {
+            db.execute(
+                "INSERT INTO play_history (guild_id, source_url, title, requested_by, started_at)
+                    VALUES (101, 'https://example.com/4', 'Track 4', 'carol', 1400)",
+                [],
+            )
+        })
+        .await
+        .unwrap();
+        assert_eq!(
+            storage.recent(guild_id, None, 2).await,
+            vec![
+                entry(4, "carol", 1400, None),
+                entry(3, "autoplay", 1300, None)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn history_stats() {
+        let storage: Arc<dyn history::HistoryStorage> = Storage::new(":memory:").unwrap();
+        let metadata = |i: usize| track_info::Metadata {
+            title: format!("Track {i}").into(),
+            source_url: format!("https://example.com/{i}").into(),
+            thumbnail_url: None,
+            duration_sec: None,
+        };
+        let track_count = |i: usize, count| history::TrackCount {
+            source_url: format!("https://example.com/{i}"),
+            title: format!("Track {i}"),
+            count,
+        };
+
+        let guild_id = GuildId::new(101);
+        assert_eq!(
+            storage.stats(guild_id, 0, 3).await,
+            history::Stats::default()
+        );
+
+        // (track, requested_by, started_at, played_secs, skipped)
+        let plays = [
+            (1, 1, 100, Some(180), false),
+            (2, 2, 200, Some(10), true),
+            (1, 2, 300, Some(180), false),
+            (3, 1, 400, Some(5), true),
+            (2, 1, 500, Some(20), true),
+            (1, 3, 600, None, false),
+        ];
+        for (track, requested_by, started_at, played_secs, skipped) in plays {
+            let id = storage
+                .record_start(
+                    guild_id,
+                    &metadata(track),
+                    track_info::Requester::User(UserId::new(requested_by)),
+                    started_at,
+                )
+                .await
+                .unwrap();
+            if let Some(played_secs) = played_secs {
+                storage
+                    .record_end(id, Duration::from_secs(played_secs), skipped)
+                    .await
+                    .unwrap();
+            }
+        }
+        // Other guilds are not counted
+        storage
+            .record_start(
+                GuildId::new(202),
+                &metadata(3),
+                track_info::Requester::User(UserId::new(4)),
+                100,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(
+            storage.stats(guild_id, 0, 2).await,
+            history::Stats {
+                top_tracks: vec![track_count(1, 3), track_count(2, 2)],
+                top_requesters: vec![("<@1>".into(), 3), ("<@2>".into(), 2)],
+                most_skipped: vec![track_count(2, 2), track_count(3, 1)],
+                listened: Duration::from_secs(395),
+            }
+        );
+
+        // Only tracks started since the timestamp are counted
+        assert_eq!(
+            storage.stats(guild_id, 350, 5).await,
+            history::Stats {
+                top_tracks: vec![track_count(1, 1), track_count(2, 1), track_count(3, 1)],
+                top_requesters: vec![("<@1>".into(), 2), ("<@3>".into(), 1)],
+                most_skipped: vec![track_count(2, 1), track_count(3, 1)],
+                listened: Duration::from_secs(25),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn saved_queue_storage() {
+        let storage: Arc<dyn saved_queue::QueueStorage> = Storage::new(":memory:").unwrap();
+        let track = |i: usize| saved_queue::SavedTrack {
+            source_url: format!("https://example.com/{i}"),
+            title: format!("Track {i}"),
+            requested_by: track_info::Requester::User(UserId::new(i as u64)),
+            volume: 0.5,
+        };
+        assert_eq!(storage.load_all().await, vec![]);
+
+        let guild_id = GuildId::new(101);
+        let queue = saved_queue::SavedQueue {
+            channel_id: ChannelId::new(1),
+            position: Duration::from_millis(12_345),
+            tracks: vec![track(1), track(2), track(3)],
+        };
+        assert!(storage.save(guild_id, &queue).await.is_ok());
+        assert_eq!(storage.load_all().await, vec![(guild_id, queue)]);
+
+        // Saving again replaces the queue
+        let queue = saved_queue::SavedQueue {
+            channel_id: ChannelId::new(2),
+            position: Duration::ZERO,
+            tracks: vec![track(3)],
+        };
+        assert!(storage.save(guild_id, &queue).await.is_ok());
+        assert_eq!(storage.load_all().await, vec![(guild_id, queue.clone())]);
+
+        let another_guild_id = GuildId::new(202);
+        let another_queue = saved_queue::SavedQueue {
+            channel_id: ChannelId::new(3),
+            position: Duration::from_secs(1),
+            tracks: vec![track(4), track(5)],
+        };
+        assert!(storage.save(another_guild_id, &another_queue).await.is_ok());
+        let mut queues = storage.load_all().await;
+        queues.sort_by_key(|(guild_id, _)| *guild_id);
+        assert_eq!(
+            queues,
+            vec![(guild_id, queue), (another_guild_id, another_queue.clone())]
+        );
+
+        assert!(storage.remove(guild_id).await.is_ok());
+        assert_eq!(
+            storage.load_all().await,
+            vec![(another_guild_id, another_queue)]
+        );
+        // Removing a missing queue is fine
+        assert!(storage.remove(guild_id).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn concurrent_requests() {
         let storage: Arc<dyn yt_dlp::QueryCache> = Storage::new(":memory:").unwrap();
 
-        assert_eq!(storage.load("query"), None);
-        assert_eq!(storage.load_all(), Vec::<String>::new());
+        let requests = (0..50).map(|i| {
+            let storage = storage.clone();
+            tokio::spawn(async move {
+                storage
+                    .save(&format!("query {i}"), &format!("url {i}"))
+                    .await
+                    .unwrap();
+                storage
+                    .load(&format!("query {i}"), Duration::from_secs(60))
+                    .await
+            })
+        });
+        for (i, loaded) in futures::future::join_all(requests)
+            .await
+            .into_iter()
+            .enumerate()
+        {
+            assert_eq!(loaded.unwrap(), Some(format!("url {i}")));
+        }
+        assert_eq!(storage.load_all(Duration::from_secs(60)).await.len(), 50);
+    }
+
+    #[tokio::test]
+    async fn yt_dlp_query_cache() {
+        let db = Storage::new(":memory:").unwrap();
+        let storage: Arc<dyn yt_dlp::QueryCache> = db.clone();
+        let max_age = Duration::from_secs(60);
 
-        assert!(storage.save("query", "webpage_url").is_ok());
-        assert_eq!(storage.load("query"), Some("webpage_url".into()));
-        assert_eq!(storage.load_all(), vec!["webpage_url"]);
+        assert_eq!(storage.load("query", max_age).await, None);
+        assert_eq!(storage.load_all(max_age).await, vec![]);
 
-        assert_eq!(storage.load("another query"), None);
-        assert!(storage.save("another query", "another url").is_ok());
-        assert_eq!(storage.load("another query"), Some("another url".into()));
-        assert_eq!(storage.load_all(), vec!["webpage_url", "another url"]);
+        assert!(storage.save("query", "webpage_url").await.is_ok());
+        assert_eq!(
+            storage.load("query", max_age).await,
+            Some("webpage_url".into())
+        );
+        assert_eq!(
+            storage.load_all(max_age).await,
+            vec![("query".into(), "webpage_url".into())]
+        );
+
+        assert_eq!(storage.load("another query", max_age).await, None);
+        assert!(storage.save("another query", "another url").await.is_ok());
+        assert_eq!(
+            storage.load("another query", max_age).await,
+            Some("another url".into())
+        );
+        assert_eq!(
+            storage.load_all(max_age).await,
+            vec![
+                ("another query".into(), "another url".into()),
+                ("query".into(), "webpage_url".into())
+            ]
+        );
 
         // Update the query
-        assert!(storage.save("query", "another url").is_ok());
-        assert_eq!(storage.load("query"), Some("another url".into()));
-        // Duplicate entries should be merged
-        assert_eq!(storage.load_all(), vec!["another url"]);
+        assert!(storage.save("query", "another url").await.is_ok());
+        assert_eq!(
+            storage.load("query", max_age).await,
+            Some("another url".into())
+        );
+        assert_eq!(
+            storage.load_all(max_age).await,
+            vec![
+                ("another query".into(), "another url".into()),
+                ("query".into(), "another url".into())
+            ]
+        );
+
+        // Entries older than `max_age` are not loaded
+        db.call(|db| {
+            db.execute(
+                "UPDATE yt_dlp_queries SET created_at = unixepoch() - 120 WHERE query = 'query'",
+                (),
+            )
+        })
+        .await
+        .unwrap();
+        assert_eq!(storage.load("query", max_age).await, None);
+        assert_eq!(
+            storage.load_all(max_age).await,
+            vec![("another query".into(), "another url".into())]
+        );
+        assert_eq!(
+            storage.load("query", Duration::from_secs(180)).await,
+            Some("another url".into())
+        );
+
+        // Hits are counted only for loaded entries and reset on save
+        assert_eq!(
+            storage.stats(max_age, 10).await,
+            yt_dlp::CacheStats {
+                entries: 2,
+                expired: 1,
+                hits: 3,
+                top_queries: vec![("query".into(), 2), ("another query".into(), 1)],
+            }
+        );
+        assert!(storage.save("query", "webpage_url").await.is_ok());
+        assert_eq!(
+            storage.stats(max_age, 1).await,
+            yt_dlp::CacheStats {
+                entries: 2,
+                expired: 0,
+                hits: 1,
+                top_queries: vec![("another query".into(), 1)],
+            }
+        );
+
+        assert!(storage.remove("query").await.unwrap());
+        assert_eq!(storage.load("query", max_age).await, None);
+        assert!(!storage.remove("query").await.unwrap());
+        assert_eq!(
+            storage.load_all(max_age).await,
+            vec![("another query".into(), "another url".into())]
+        );
     }
 }
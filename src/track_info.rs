@@ -1,11 +1,12 @@
 use std::{
     fmt::{self, Display, Formatter},
     num::NonZeroU32,
+    sync::atomic::{AtomicBool, Ordering},
 };
 
 use serenity::{
-    builder::{CreateEmbed, CreateEmbedFooter},
-    model::Colour,
+    builder::CreateEmbed,
+    model::{Colour, id::UserId},
 };
 use songbird::input::AuxMetadata;
 
@@ -50,31 +51,71 @@ impl From<Metadata> for AuxMetadata {
     }
 }
 
-#[cfg_attr(test, derive(Debug, PartialEq))]
+/// Who added the track to the queue
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Requester {
+    /// Author of the command that added the track
+    User(UserId),
+    /// The track was added by autoplay once the queue was over
+    Autoplay,
+}
+
+impl Display for Requester {
+    /// Mentions the user, which Discord renders as their name
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::User(user_id) => write!(f, "<@{user_id}>"),
+            Self::Autoplay => write!(f, "autoplay"),
+        }
+    }
+}
+
+#[cfg_attr(test, derive(Debug))]
 pub(crate) struct TrackInfo {
     /// Track metadata
     metadata: Metadata,
-    /// Author of the /play command
-    added_by: Box<str>,
+    /// Who added the track
+    added_by: Requester,
+    /// Whether the track was skipped by `/skip` or by vote, rather than ended or stopped otherwise
+    skipped: AtomicBool,
 }
 
 impl TrackInfo {
-    pub(crate) fn new(metadata: Metadata, added_by: String) -> Self {
+    pub(crate) fn new(metadata: Metadata, added_by: Requester) -> Self {
         Self {
             metadata,
-            added_by: added_by.into_boxed_str(),
+            added_by,
+            skipped: AtomicBool::new(false),
         }
     }
 
+    /// Provides track metadata
+    pub(crate) const fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    /// Provides who requested the track
+    pub(crate) const fn added_by(&self) -> Requester {
+        self.added_by
+    }
+
+    /// Marks the track as skipped. Call before stopping the track, so its end is recorded as a skip
+    pub(crate) fn mark_skipped(&self) {
+        self.skipped.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns whether the track was skipped
+    pub(crate) fn skipped(&self) -> bool {
+        self.skipped.load(Ordering::Relaxed)
+    }
+
     /// Creates Discord embed with the track info
     pub(crate) fn build_embed(&self) -> CreateEmbed {
         let mut embed = CreateEmbed::default()
             .description(format!("{self}"))
             .color(Colour::RED)
-            .footer(CreateEmbedFooter::new(format!(
-                "Added by {}",
-                self.added_by
-            )));
+            // Footers don't render mentions, unlike fields
+            .field("Added by", self.added_by.to_string(), true);
         if let Some(thumbnail_url) = &self.metadata.thumbnail_url {
             embed = embed.thumbnail(thumbnail_url.clone());
         }
@@ -106,7 +147,8 @@ mod tests {
                         thumbnail_url: None,
                         duration_sec: NonZeroU32::new(123),
                     },
-                    added_by: "TestUser".into(),
+                    added_by: Requester::User(UserId::new(101)),
+                    skipped: AtomicBool::new(false),
                 }
             ),
             "[Test](https://example.com) 2:03"
@@ -123,7 +165,8 @@ mod tests {
                         thumbnail_url: None,
                         duration_sec: None,
                     },
-                    added_by: "TestUser".into(),
+                    added_by: Requester::User(UserId::new(101)),
+                    skipped: AtomicBool::new(false),
                 }
             ),
             "[Test](https://example.com)"
@@ -140,10 +183,17 @@ mod tests {
                         thumbnail_url: None,
                         duration_sec: NonZeroU32::new(210),
                     },
-                    added_by: "TestUser".into(),
+                    added_by: Requester::User(UserId::new(101)),
+                    skipped: AtomicBool::new(false),
                 }
             ),
             "[Нейромонах Феофан — Притоптать | Neuromonakh Feofan](https://www.youtube.com/watch?v=HNpLuXOg7xQ) 3:30"
         );
     }
+
+    #[test]
+    fn requester_display() {
+        assert_eq!(Requester::User(UserId::new(101)).to_string(), "<@101>");
+        assert_eq!(Requester::Autoplay.to_string(), "autoplay");
+    }
 }
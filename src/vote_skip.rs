@@ -0,0 +1,129 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+
+use serenity::model::id::{GuildId, UserId};
+
+/// Votes to skip the current track in the guild
+struct Vote {
+    /// UUID of the track handle being voted for
+    track: u128,
+    voters: HashSet<UserId>,
+}
+
+/// Ongoing votes to skip the current track in all guilds
+#[derive(Default)]
+pub(crate) struct VoteSkips {
+    votes: Mutex<HashMap<GuildId, Vote>>,
+}
+
+/// How many votes are needed to skip the track
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Tally {
+    pub(crate) votes: usize,
+    pub(crate) required: usize,
+}
+
+impl Tally {
+    pub(crate) fn passed(&self) -> bool {
+        self.votes >= self.required
+    }
+}
+
+impl VoteSkips {
+    /// Adds the user's vote to skip the track, dropping votes for previous tracks.
+    /// Only votes of the current listeners count, so leaving the voice channel takes the vote back
+    pub(crate) fn vote(
+        &self,
+        guild_id: GuildId,
+        track: u128,
+        user_id: UserId,
+        listeners: &[UserId],
+        percent: u8,
+    ) -> Tally {
+        let mut votes = self.votes.lock().unwrap();
+        let vote = votes.entry(guild_id).or_insert_with(|| Vote {
+            track,
+            voters: HashSet::new(),
+        });
+        if vote.track != track {
+            vote.track = track;
+            vote.voters.clear();
+        }
+        vote.voters.insert(user_id);
+
+        let tally = Tally {
+            votes: listeners
+                .iter()
+                .filter(|listener| vote.voters.contains(listener))
+                .count(),
+            required: required_votes(listeners.len(), percent),
+        };
+        if tally.passed() {
+            votes.remove(&guild_id);
+        }
+        tally
+    }
+}
+
+/// Returns how many of the listeners should vote to skip the track, rounding up
+pub(crate) fn required_votes(listeners: usize, percent: u8) -> usize {
+    (listeners * usize::from(percent)).div_ceil(100).max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn required_votes_test() {
+        assert_eq!(required_votes(4, 50), 2);
+        assert_eq!(required_votes(5, 50), 3);
+        assert_eq!(required_votes(3, 100), 3);
+        // At least one vote is needed even in an empty channel
+        assert_eq!(required_votes(1, 10), 1);
+        assert_eq!(required_votes(0, 50), 1);
+    }
+
+    #[test]
+    fn vote_skips() {
+        let vote_skips = VoteSkips::default();
+        let guild_id = GuildId::new(101);
+        let [alice, bob, carol, dave] = [1, 2, 3, 4].map(UserId::new);
+        let listeners = [alice, bob, carol, dave];
+        let tally = |votes, required| Tally { votes, required };
+
+        assert_eq!(
+            vote_skips.vote(guild_id, 1, alice, &listeners, 75),
+            tally(1, 3)
+        );
+        // Voting twice doesn't count
+        assert_eq!(
+            vote_skips.vote(guild_id, 1, alice, &listeners, 75),
+            tally(1, 3)
+        );
+        // Votes are per guild
+        assert_eq!(
+            vote_skips.vote(GuildId::new(202), 1, bob, &listeners, 75),
+            tally(1, 3)
+        );
+        assert_eq!(
+            vote_skips.vote(guild_id, 1, bob, &listeners, 75),
+            tally(2, 3)
+        );
+        // Votes of those who left don't count
+        assert_eq!(
+            vote_skips.vote(guild_id, 1, carol, &[bob, carol], 75),
+            tally(2, 2)
+        );
+
+        // Passed votes are forgotten, as are votes for previous tracks
+        assert_eq!(
+            vote_skips.vote(guild_id, 1, dave, &listeners, 75),
+            tally(1, 3)
+        );
+        assert_eq!(
+            vote_skips.vote(guild_id, 2, alice, &listeners, 75),
+            tally(1, 3)
+        );
+    }
+}
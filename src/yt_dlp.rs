@@ -1,10 +1,11 @@
-use std::borrow::Cow;
 use std::collections::HashMap;
 use std::io::ErrorKind;
 use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
 
 use async_trait::async_trait;
+use futures::stream::{self, StreamExt};
 use reqwest::{
     Client,
     header::{HeaderName, HeaderValue},
@@ -14,6 +15,7 @@ use songbird::input::{AudioStream, AudioStreamError, AuxMetadata, Compose, HttpR
 use symphonia::core::io::MediaSource;
 use tokio::{process::Command, sync::RwLock};
 use tracing::{info, warn};
+use unicode_normalization::UnicodeNormalization;
 
 use crate::track_info;
 
@@ -93,13 +95,7 @@ impl YtDlp {
             .args(ytdl_args)
             .output()
             .await
-            .map_err(|e| {
-                AudioStreamError::Fail(if e.kind() == ErrorKind::NotFound {
-                    format!("could not find executable '{YOUTUBE_DL_COMMAND}' on path").into()
-                } else {
-                    Box::new(e)
-                })
-            })?;
+            .map_err(command_error)?;
 
         let yt_dlp_output: YtDlpOutput = serde_json::from_slice(&command.stdout).map_err(|e| {
             let output = String::from_utf8_lossy(&command.stdout);
@@ -110,12 +106,48 @@ impl YtDlp {
         Ok(yt_dlp_output)
     }
 
+    /// Lists URLs of up to `count` videos from the YouTube mix of the provided video
+    async fn mix(video_id: &str, count: usize) -> Result<Vec<String>, AudioStreamError> {
+        let mix_url = format!("https://www.youtube.com/watch?v={video_id}&list=RD{video_id}");
+        // The first entry of the mix is the video itself
+        let playlist_items = format!("2:{}", count + 1);
+        let ytdl_args = [
+            mix_url.as_str(),
+            "-j",
+            "--flat-playlist",
+            "--playlist-items",
+            &playlist_items,
+        ];
+
+        let command = Command::new(YOUTUBE_DL_COMMAND)
+            .args(ytdl_args)
+            .output()
+            .await
+            .map_err(command_error)?;
+
+        // With `--flat-playlist` yt-dlp prints one JSON object per entry
+        let urls = String::from_utf8_lossy(&command.stdout)
+            .lines()
+            .filter_map(|line| serde_json::from_str::<YtDlpFlatEntry>(line).ok())
+            .map(|entry| entry.url)
+            .collect();
+        Ok(urls)
+    }
+
     /// Provides track metadata
     pub(crate) const fn metadata(&self) -> &track_info::Metadata {
         &self.metadata
     }
 }
 
+fn command_error(e: std::io::Error) -> AudioStreamError {
+    AudioStreamError::Fail(if e.kind() == ErrorKind::NotFound {
+        format!("could not find executable '{YOUTUBE_DL_COMMAND}' on path").into()
+    } else {
+        Box::new(e)
+    })
+}
+
 impl From<YtDlp> for Input {
     fn from(val: YtDlp) -> Self {
         Input::Lazy(Box::new(val))
@@ -160,15 +192,39 @@ pub(crate) struct YtDlpOutput {
     webpage_url: Option<String>,
 }
 
+/// An entry of the `--flat-playlist` yt-dlp output
+#[derive(Deserialize)]
+struct YtDlpFlatEntry {
+    url: String,
+}
+
 /// Query cache for yt-dlp that helps to reduce time spent on searching YouTube
+#[async_trait]
 pub(crate) trait QueryCache: Send + Sync {
-    /// Saves found webpage_url for the query
-    fn save(&self, query: &str, webpage_url: &str) -> Result<(), anyhow::Error>;
-    /// Loads found webpage_url for the query if it is known
-    fn load(&self, query: &str) -> Option<String>;
-    /// Returns all known webpage_urls
-    #[allow(unused)]
-    fn load_all(&self) -> Vec<String>;
+    /// Saves found webpage_url for the query. Queries are expected to be normalized via [`normalize_query`]
+    async fn save(&self, query: &str, webpage_url: &str) -> Result<(), anyhow::Error>;
+    /// Loads found webpage_url for the query if it is known and was saved not earlier than `max_age` ago.
+    /// Counts a hit for the query
+    async fn load(&self, query: &str, max_age: Duration) -> Option<String>;
+    /// Returns (query, webpage_url) of all queries saved not earlier than `max_age` ago
+    async fn load_all(&self, max_age: Duration) -> Vec<(String, String)>;
+    /// Removes the query from the cache. Returns `false` if it wasn't cached
+    async fn remove(&self, query: &str) -> Result<bool, anyhow::Error>;
+    /// Returns cache statistics with up to `limit` most hit queries
+    async fn stats(&self, max_age: Duration, limit: usize) -> CacheStats;
+}
+
+/// Usage statistics of the query cache
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct CacheStats {
+    /// Number of cached queries
+    pub(crate) entries: usize,
+    /// Number of cached queries that are too old to be used
+    pub(crate) expired: usize,
+    /// Total number of cache hits
+    pub(crate) hits: u64,
+    /// The most hit queries along with their hits
+    pub(crate) top_queries: Vec<(String, u64)>,
 }
 
 #[derive(Clone)]
@@ -184,35 +240,94 @@ pub(crate) struct Resolver {
     cache: RwLock<HashMap<String, CacheEntry>>,
 
     http_client: reqwest::Client,
+    /// Minimal similarity of a cached query to be used for a query that is not cached as is.
+    /// `None` disables fuzzy lookup
+    fuzzy_threshold: Option<f64>,
 }
 
 impl Resolver {
     const CACHE_EXPIRATION: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+    /// Search results change over time, so cached queries are searched again once in a while
+    const QUERY_CACHE_TTL: Duration = Duration::from_secs(30 * 24 * 60 * 60);
 
     /// Creates a new yt-dlp resolver with a cache file
-    pub(crate) fn new(http_client: reqwest::Client, cache: Arc<dyn QueryCache>) -> Self {
+    pub(crate) fn new(
+        http_client: reqwest::Client,
+        cache: Arc<dyn QueryCache>,
+        fuzzy_threshold: Option<f64>,
+    ) -> Self {
         Self {
             query_cache: cache,
             http_client,
             cache: RwLock::new(HashMap::new()),
+            fuzzy_threshold,
         }
     }
 
     /// Resolves a query to a yt-dlp instance, caching the result
     pub(crate) async fn resolve(&self, query: &str) -> Option<YtDlp> {
+        if query.starts_with("http") {
+            return self.fetch_cached(query).await;
+        }
+
         // For non-URL queries, check the cache first
-        let query = if !query.starts_with("http") {
-            if let Some(webpage_url) = self.query_cache.load(query) {
-                Cow::from(webpage_url)
-            } else {
-                Cow::from(query)
+        let key = normalize_query(query);
+        if let Some((cached_key, webpage_url)) = self.load_cached_query(&key).await {
+            if let Some(yt_dlp) = self.fetch_cached(&webpage_url).await {
+                return Some(yt_dlp);
             }
-        } else {
-            Cow::from(query)
-        };
+            // The video might have been taken down, so forget it and search again
+            info!("Cached '{webpage_url}' for '{cached_key}' failed to resolve, invalidating");
+            if let Err(err) = self.query_cache.remove(&cached_key).await {
+                warn!("Failed to remove yt-dlp query '{cached_key}' from cache: {err}");
+            }
+        }
+
+        let yt_dlp = self.fetch_cached(query).await?;
+        if let Err(err) = self
+            .query_cache
+            .save(&key, &yt_dlp.metadata.source_url)
+            .await
+        {
+            warn!("Failed to save yt-dlp query '{key}' to cache: {err}");
+        }
+        Some(yt_dlp)
+    }
 
+    /// Removes the query from the query cache, so it will be searched again.
+    /// Returns `false` if the query wasn't cached
+    pub(crate) async fn forget(&self, query: &str) -> Result<bool, anyhow::Error> {
+        self.query_cache.remove(&normalize_query(query)).await
+    }
+
+    /// Looks up the normalized query in the query cache, falling back to the most similar cached
+    /// query if fuzzy lookup is enabled. Returns (cached query, webpage_url)
+    async fn load_cached_query(&self, key: &str) -> Option<(String, String)> {
+        if let Some(webpage_url) = self.query_cache.load(key, Self::QUERY_CACHE_TTL).await {
+            return Some((key.to_owned(), webpage_url));
+        }
+
+        let threshold = self.fuzzy_threshold?;
+        let cached = self.query_cache.load_all(Self::QUERY_CACHE_TTL).await;
+        let (similar_key, _) = closest_query(key, &cached, threshold)?;
+        info!("Using cached '{similar_key}' for similar '{key}'");
+        // Load it again to count the hit
+        let webpage_url = self
+            .query_cache
+            .load(similar_key, Self::QUERY_CACHE_TTL)
+            .await?;
+        Some((similar_key.clone(), webpage_url))
+    }
+
+    /// Returns query cache statistics with up to `limit` most hit queries
+    pub(crate) async fn cache_stats(&self, limit: usize) -> CacheStats {
+        self.query_cache.stats(Self::QUERY_CACHE_TTL, limit).await
+    }
+
+    /// Resolves a query or URL using recently fetched instances if possible
+    async fn fetch_cached(&self, query: &str) -> Option<YtDlp> {
         // Two separate locks to avoid blocking everything on the long (up to 2s) yt-dlp query
-        let cached_yt_dlp = self.cache.read().await.get(query.as_ref()).cloned();
+        let cached_yt_dlp = self.cache.read().await.get(query).cloned();
         match cached_yt_dlp {
             Some(CacheEntry { loaded_at, yt_dlp })
                 if loaded_at.elapsed() < Self::CACHE_EXPIRATION =>
@@ -220,17 +335,7 @@ impl Resolver {
                 Some(yt_dlp)
             }
             _ => {
-                let yt_dlp = Self::fetch(self.http_client.clone(), query.as_ref()).await?;
-
-                // Save the query to webpage_url mapping if it was not a URL query
-                if !query.starts_with("http") {
-                    if let Err(err) = self
-                        .query_cache
-                        .save(query.as_ref(), &yt_dlp.metadata.source_url)
-                    {
-                        warn!("Failed to save yt-dlp query '{query}' to cache: {err}");
-                    }
-                }
+                let yt_dlp = Self::fetch(self.http_client.clone(), query).await?;
 
                 self.cache.write().await.insert(
                     yt_dlp.metadata.source_url.clone().into(),
@@ -244,6 +349,28 @@ impl Resolver {
         }
     }
 
+    /// Resolves up to `count` videos related to the provided YouTube video using YouTube mix.
+    /// Returns nothing for non-YouTube URLs.
+    pub(crate) async fn related(&self, source_url: &str, count: usize) -> Vec<YtDlp> {
+        let Some(video_id) = youtube_video_id(source_url) else {
+            return vec![];
+        };
+        let urls = match YtDlp::mix(video_id, count).await {
+            Ok(urls) => urls,
+            Err(err) => {
+                warn!("Failed to fetch YouTube mix for '{source_url}': {err}");
+                return vec![];
+            }
+        };
+
+        stream::iter(urls)
+            .map(|url| async move { self.resolve(&url).await })
+            .buffered(count.max(1))
+            .filter_map(|yt_dlp| async { yt_dlp })
+            .collect()
+            .await
+    }
+
     /// Inner function to fetch a yt-dlp instance
     async fn fetch(http_client: reqwest::Client, query: &str) -> Option<YtDlp> {
         let begin: std::time::Instant = std::time::Instant::now();
@@ -262,12 +389,155 @@ impl Resolver {
     }
 }
 
+/// Normalizes the search query to be used as a query cache key, so queries that differ only in
+/// letter case, whitespaces or Unicode representation (e.g. decomposed `й`) share the cache entry
+pub(crate) fn normalize_query(query: &str) -> String {
+    let query: String = query.nfkc().flat_map(char::to_lowercase).collect();
+    query.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Finds the cached query most similar to the normalized query, ignoring the words order.
+/// Returns `None` if there is no query with similarity of at least `threshold`, from 0 to 1
+fn closest_query<'a>(
+    query: &str,
+    cached: &'a [(String, String)],
+    threshold: f64,
+) -> Option<&'a (String, String)> {
+    fn sorted_words(query: &str) -> String {
+        let mut words: Vec<_> = query.split_whitespace().collect();
+        words.sort_unstable();
+        words.join(" ")
+    }
+
+    let query = sorted_words(query);
+    cached
+        .iter()
+        .map(|entry| {
+            // Keys saved before normalization was introduced aren't normalized
+            let cached_query = sorted_words(&normalize_query(&entry.0));
+            let similarity = strsim::normalized_damerau_levenshtein(&query, &cached_query);
+            (entry, similarity)
+        })
+        .filter(|(_, similarity)| *similarity >= threshold)
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map(|(entry, _)| entry)
+}
+
+/// Extracts YouTube video ID from the video URL
+fn youtube_video_id(url: &str) -> Option<&str> {
+    let url = url
+        .strip_prefix("https://")
+        .or_else(|| url.strip_prefix("http://"))?;
+
+    let video_id = if let Some(remaining) = url.strip_prefix("youtu.be/") {
+        remaining.split(['?', '&', '#']).next()
+    } else {
+        let (host, remaining) = url.split_once('/')?;
+        if host != "youtube.com" && !host.ends_with(".youtube.com") {
+            return None;
+        }
+        remaining
+            .strip_prefix("watch?")?
+            .split(['&', '#'])
+            .find_map(|param| param.strip_prefix("v="))
+    }?;
+    (!video_id.is_empty()).then_some(video_id)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use pretty_assertions::assert_eq;
     use std::time::Duration;
 
+    #[test]
+    fn youtube_video_id_test() {
+        let valid = [
+            "https://www.youtube.com/watch?v=dQw4w9WgXcQ",
+            "https://youtube.com/watch?v=dQw4w9WgXcQ",
+            "http://www.youtube.com/watch?v=dQw4w9WgXcQ",
+            "https://m.youtube.com/watch?v=dQw4w9WgXcQ",
+            "https://music.youtube.com/watch?v=dQw4w9WgXcQ&si=abcdef",
+            "https://www.youtube.com/watch?list=RDdQw4w9WgXcQ&v=dQw4w9WgXcQ",
+            "https://www.youtube.com/watch?v=dQw4w9WgXcQ#t=42",
+            "https://youtu.be/dQw4w9WgXcQ",
+            "https://youtu.be/dQw4w9WgXcQ?si=abcdef",
+        ];
+        for url in valid {
+            assert_eq!(
+                youtube_video_id(url),
+                Some("dQw4w9WgXcQ"),
+                "Failed at {url}"
+            );
+        }
+
+        let invalid = [
+            "https://www.youtube.com/",
+            "https://www.youtube.com/watch?v=",
+            "https://www.youtube.com/results?search_query=rick",
+            "https://youtu.be/",
+            "https://notyoutube.com/watch?v=dQw4w9WgXcQ",
+            "https://open.spotify.com/track/6rqhFgbbKwnb9MLmUQDhG6",
+            "https://radio-t.com/p/2024/05/11/podcast-912/",
+            "rick astley",
+        ];
+        for url in invalid {
+            assert_eq!(youtube_video_id(url), None, "Failed at {url}");
+        }
+    }
+
+    #[test]
+    fn normalize_query_test() {
+        assert_eq!(
+            normalize_query("Rick Astley never gonna"),
+            "rick astley never gonna"
+        );
+        assert_eq!(
+            normalize_query("  rick astley\tNever   Gonna \n"),
+            "rick astley never gonna"
+        );
+        assert_eq!(normalize_query("ПРИТОПТАТЬ"), "притоптать");
+        // Decomposed `й` and `ё` are composed
+        assert_eq!(
+            normalize_query("Нейромонах Фе\u{0451}"),
+            normalize_query("Не\u{0438}\u{0306}ромонах Фе\u{0435}\u{0308}")
+        );
+        // Compatibility characters are folded
+        assert_eq!(normalize_query("ｒｉｃｋ"), "rick");
+        assert_eq!(normalize_query("   "), "");
+    }
+
+    #[test]
+    fn closest_query_test() {
+        let cached = [
+            ("rick astley never gonna".to_owned(), "rick url".to_owned()),
+            (
+                "нейромонах феофан притоптать".to_owned(),
+                "feofan url".to_owned(),
+            ),
+            (
+                "Rick Astley - Together Forever".to_owned(),
+                "together url".to_owned(),
+            ),
+        ];
+        let closest = |query| closest_query(query, &cached, 0.85).map(|(_, url)| url.as_str());
+
+        assert_eq!(closest("rick astley never gonna"), Some("rick url"));
+        // Typos and the words order
+        assert_eq!(closest("rick astly never gona"), Some("rick url"));
+        assert_eq!(closest("never gonna rick astley"), Some("rick url"));
+        assert_eq!(closest("нейромонах феофан притоптат"), Some("feofan url"));
+        // Not normalized keys are normalized for comparison
+        assert_eq!(
+            closest("rick astley - together forever"),
+            Some("together url")
+        );
+
+        assert_eq!(closest("rick astley"), None);
+        assert_eq!(closest("нейромонах феофан"), None);
+        assert_eq!(closest_query("rick astley", &[], 0.0), None);
+    }
+
     #[ignore]
     #[tokio::test]
     async fn resolve_rick_roll() {